@@ -0,0 +1,36 @@
+// Instruction throughput on a tight arithmetic and memory loop.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use rust_vm::{assemble, Register, Vm};
+
+const PROGRAM: &str = "
+        .ORIG x3000
+        LD R1, COUNT
+LOOP    ADD R2, R2, #3
+        AND R3, R2, #7
+        ST R3, SCRATCH
+        LDR R4, R5, #0
+        NOT R4, R4
+        ADD R1, R1, #-1
+        BRp LOOP
+        HALT
+COUNT   .FILL x2710
+SCRATCH .FILL #0
+        .END
+";
+
+fn run_loop(c: &mut Criterion) {
+    let (origin, words) = assemble(PROGRAM).unwrap();
+    c.bench_function("dispatch loop", |b| {
+        b.iter(|| {
+            let mut vm = Vm::new();
+            vm.set_input(Vec::new());
+            vm.load_words(origin, &words).unwrap();
+            vm.reg[Register::RPC as usize] = origin;
+            vm.run().unwrap();
+        })
+    });
+}
+
+criterion_group!(benches, run_loop);
+criterion_main!(benches);
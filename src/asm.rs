@@ -0,0 +1,800 @@
+// Two-pass assembler for LC-3 source: `.ORIG`, `.FILL`, `.BLKW`, `.STRINGZ`,
+// `.END`, labels, every instruction and the TRAP aliases. The first pass
+// assigns addresses to labels, the second encodes the words.
+//
+// For linking (see `link`), `.GLOBAL NAME` exports a label and `.EXTERNAL
+// NAME` declares one defined in another object. References to an external
+// label are left zero and recorded as fixups for the linker to fill in.
+
+use std::collections::HashMap;
+
+use crate::isa::TrapCode;
+use crate::number::{parse_signed, NumberError};
+
+#[derive(Debug, PartialEq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for AsmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+// the words assembled from one source line
+#[derive(Debug, PartialEq)]
+pub struct SourceSpan {
+    pub line: usize,
+    pub address: u16,
+    // index of the first word in `Assembly::words` and how many there are
+    pub start: usize,
+    pub len: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Assembly {
+    pub origin: u16,
+    pub words: Vec<u16>,
+    pub spans: Vec<SourceSpan>,
+}
+
+impl Assembly {
+    // lc3as-style listing: address, hex word, line number and source text,
+    // with the extra words of .BLKW/.STRINGZ on lines of their own
+    pub fn listing(&self, source: &str) -> String {
+        let mut spans = self.spans.iter().peekable();
+        let mut out = String::new();
+        for (i, text) in source.lines().enumerate() {
+            let line = i + 1;
+            match spans.next_if(|span| span.line == line) {
+                Some(span) => {
+                    let words = &self.words[span.start..span.start + span.len];
+                    for (j, word) in words.iter().enumerate() {
+                        let address = span.address.wrapping_add(j as u16);
+                        if j == 0 {
+                            out.push_str(&format!(
+                                "x{:04X}  x{:04X}  {:>4}  {}\n",
+                                address, word, line, text
+                            ));
+                        } else {
+                            out.push_str(&format!("x{:04X}  x{:04X}\n", address, word));
+                        }
+                    }
+                }
+                None => out.push_str(&format!("{:14}{:>4}  {}\n", "", line, text)),
+            }
+        }
+        out
+    }
+}
+
+// which field of the word at a fixup's address holds the reference
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FixupKind {
+    // the whole word is the label's address, from .FILL
+    Word,
+    // a PC-relative offset of this many bits, from BR/JSR/LD/LEA and so on
+    PcOffset(u32),
+}
+
+// a reference to an .EXTERNAL label that the linker has to resolve
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fixup {
+    pub address: u16,
+    pub symbol: String,
+    pub kind: FixupKind,
+    pub line: usize,
+}
+
+// an assembled source file with the labels it exports and the references
+// it leaves to other objects
+#[derive(Debug, PartialEq)]
+pub struct ObjectFile {
+    pub origin: u16,
+    pub words: Vec<u16>,
+    pub exports: HashMap<String, u16>,
+    pub fixups: Vec<Fixup>,
+}
+
+// one source line with its label and comment stripped
+struct Statement {
+    line: usize,
+    op: String,
+    operands: Vec<String>,
+    // raw text after the opcode, for .STRINGZ
+    rest: String,
+    address: u16,
+}
+
+fn is_opcode(word: &str) -> bool {
+    let upper = word.to_ascii_uppercase();
+    if let Some(flags) = upper.strip_prefix("BR") {
+        return ["", "N", "Z", "P", "NZ", "NP", "ZP", "NZP", "V"].contains(&flags);
+    }
+    matches!(
+        upper.as_str(),
+        "ADD"
+            | "AND"
+            | "NOT"
+            | "JMP"
+            | "RET"
+            | "JSR"
+            | "JSRR"
+            | "LD"
+            | "LDI"
+            | "LDR"
+            | "LEA"
+            | "ST"
+            | "STI"
+            | "STR"
+            | "TRAP"
+            | "RTI"
+            | "GETC"
+            | "OUT"
+            | "PUTS"
+            | "IN"
+            | "PUTSP"
+            | "HALT"
+            | ".ORIG"
+            | ".FILL"
+            | ".BLKW"
+            | ".STRINGZ"
+            | ".END"
+            | ".GLOBAL"
+            | ".EXTERNAL"
+    )
+}
+
+// drop a `;` comment that is not inside a string literal
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+        } else if c == '"' {
+            in_string = true;
+        } else if c == ';' {
+            return &line[..i];
+        }
+    }
+    line
+}
+
+fn parse_string(s: &str) -> Option<Vec<u16>> {
+    let inner = s.strip_prefix('"')?.strip_suffix('"')?;
+    let mut words = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        let c = if c == '\\' {
+            match chars.next()? {
+                'n' => '\n',
+                't' => '\t',
+                'r' => '\r',
+                '0' => '\0',
+                other => other,
+            }
+        } else {
+            c
+        };
+        words.push(c as u16);
+    }
+    words.push(0);
+    Some(words)
+}
+
+struct Assembler<'a> {
+    labels: &'a HashMap<String, u16>,
+    // .EXTERNAL labels and the line declaring them
+    externals: &'a HashMap<String, usize>,
+    statement: &'a Statement,
+}
+
+impl Assembler<'_> {
+    fn error(&self, message: String) -> AsmError {
+        AsmError {
+            line: self.statement.line,
+            message,
+        }
+    }
+
+    fn expect_operands(&self, count: usize) -> Result<(), AsmError> {
+        if self.statement.operands.len() != count {
+            return Err(self.error(format!(
+                "{} expects {} operand(s), got {}",
+                self.statement.op,
+                count,
+                self.statement.operands.len()
+            )));
+        }
+        Ok(())
+    }
+
+    fn register(&self, i: usize) -> Result<u16, AsmError> {
+        let operand = &self.statement.operands[i];
+        match operand.to_ascii_uppercase().strip_prefix('R') {
+            Some(n) if n.len() == 1 && ("0"..="7").contains(&n) => Ok(n.parse().unwrap()),
+            _ => Err(self.error(format!("invalid register `{}`", operand))),
+        }
+    }
+
+    fn is_register(&self, i: usize) -> bool {
+        self.register(i).is_ok()
+    }
+
+    // the operand's value when it is a numeric literal
+    fn literal(&self, i: usize) -> Result<Option<i32>, AsmError> {
+        let operand = &self.statement.operands[i];
+        match parse_signed(operand) {
+            Ok(v) => Ok(Some(v)),
+            Err(NumberError::Invalid) => Ok(None),
+            Err(e) => Err(self.error(format!("`{}` is {}", operand, e))),
+        }
+    }
+
+    // a literal or the address of a label
+    fn value(&self, i: usize) -> Result<i32, AsmError> {
+        let operand = &self.statement.operands[i];
+        if let Some(v) = self.literal(i)? {
+            return Ok(v);
+        }
+        self.labels
+            .get(operand)
+            .map(|&addr| addr as i32)
+            .ok_or_else(|| self.error(format!("undefined label `{}`", operand)))
+    }
+
+    // a signed field of `bits` bits
+    fn signed(&self, value: i32, bits: u32) -> Result<u16, AsmError> {
+        let limit = 1 << (bits - 1);
+        if value < -limit || value >= limit {
+            return Err(self.error(format!("{} does not fit in {} bits", value, bits)));
+        }
+        Ok((value as u16) & ((1 << bits) - 1))
+    }
+
+    fn immediate(&self, i: usize, bits: u32) -> Result<u16, AsmError> {
+        let operand = &self.statement.operands[i];
+        let value = self
+            .literal(i)?
+            .ok_or_else(|| self.error(format!("invalid immediate `{}`", operand)))?;
+        self.signed(value, bits)
+    }
+
+    fn is_external(&self, i: usize) -> bool {
+        self.statement
+            .operands
+            .get(i)
+            .is_some_and(|operand| self.externals.contains_key(operand))
+    }
+
+    // the reference to an external label this statement leaves, if any
+    fn fixup(&self) -> Option<Fixup> {
+        let op = self.statement.op.to_ascii_uppercase();
+        let (i, kind) = match op.as_str() {
+            ".FILL" => (0, FixupKind::Word),
+            "JSR" => (0, FixupKind::PcOffset(11)),
+            "LD" | "LDI" | "LEA" | "ST" | "STI" => (1, FixupKind::PcOffset(9)),
+            _ if op.starts_with("BR") => (0, FixupKind::PcOffset(9)),
+            _ => return None,
+        };
+        if !self.is_external(i) {
+            return None;
+        }
+        Some(Fixup {
+            address: self.statement.address,
+            symbol: self.statement.operands[i].clone(),
+            kind,
+            line: self.statement.line,
+        })
+    }
+
+    // a label becomes an offset from the incremented PC; a literal is used
+    // as the offset itself. External labels are left to the linker.
+    fn pc_offset(&self, i: usize, bits: u32) -> Result<u16, AsmError> {
+        if self.is_external(i) {
+            return Ok(0);
+        }
+        let offset = match self.literal(i)? {
+            Some(v) => v,
+            None => self.value(i)? - (self.statement.address as i32 + 1),
+        };
+        self.signed(offset, bits)
+    }
+
+    fn encode(&self) -> Result<Vec<u16>, AsmError> {
+        let op = self.statement.op.to_ascii_uppercase();
+        let word = match op.as_str() {
+            "ADD" | "AND" => {
+                self.expect_operands(3)?;
+                let base = if op == "ADD" { 0x1000 } else { 0x5000 };
+                let head = base | self.register(0)? << 9 | self.register(1)? << 6;
+                if self.is_register(2) {
+                    head | self.register(2)?
+                } else {
+                    head | 0x20 | self.immediate(2, 5)?
+                }
+            }
+            "NOT" => {
+                self.expect_operands(2)?;
+                0x903F | self.register(0)? << 9 | self.register(1)? << 6
+            }
+            "JMP" => {
+                self.expect_operands(1)?;
+                0xC000 | self.register(0)? << 6
+            }
+            "RET" => {
+                self.expect_operands(0)?;
+                0xC1C0
+            }
+            "JSR" => {
+                self.expect_operands(1)?;
+                0x4800 | self.pc_offset(0, 11)?
+            }
+            "JSRR" => {
+                self.expect_operands(1)?;
+                0x4000 | self.register(0)? << 6
+            }
+            "LD" | "LDI" | "LEA" | "ST" | "STI" => {
+                self.expect_operands(2)?;
+                let base = match op.as_str() {
+                    "LD" => 0x2000,
+                    "LDI" => 0xA000,
+                    "LEA" => 0xE000,
+                    "ST" => 0x3000,
+                    _ => 0xB000,
+                };
+                base | self.register(0)? << 9 | self.pc_offset(1, 9)?
+            }
+            "LDR" | "STR" => {
+                self.expect_operands(3)?;
+                let base = if op == "LDR" { 0x6000 } else { 0x7000 };
+                base | self.register(0)? << 9 | self.register(1)? << 6 | self.immediate(2, 6)?
+            }
+            "TRAP" => {
+                self.expect_operands(1)?;
+                let vector = self.value(0)?;
+                if !(0..=0xFF).contains(&vector) {
+                    return Err(self.error(format!("invalid trap vector {}", vector)));
+                }
+                0xF000 | vector as u16
+            }
+            "RTI" => {
+                self.expect_operands(0)?;
+                0x8000
+            }
+            "GETC" | "OUT" | "PUTS" | "IN" | "PUTSP" | "HALT" => {
+                self.expect_operands(0)?;
+                let code = match op.as_str() {
+                    "GETC" => TrapCode::GETC,
+                    "OUT" => TrapCode::OUT,
+                    "PUTS" => TrapCode::PUTS,
+                    "IN" => TrapCode::IN,
+                    "PUTSP" => TrapCode::PUTSP,
+                    _ => TrapCode::HALT,
+                };
+                0xF000 | code as u16
+            }
+            ".FILL" => {
+                self.expect_operands(1)?;
+                if self.is_external(0) {
+                    return Ok(vec![0]);
+                }
+                let value = self.value(0)?;
+                if !(-0x8000..=0xFFFF).contains(&value) {
+                    return Err(self.error(format!("{} does not fit in a word", value)));
+                }
+                value as u16
+            }
+            ".BLKW" => return Ok(vec![0; self.statement_size()? as usize]),
+            ".STRINGZ" => return self.string(),
+            _ => {
+                let flags = op.strip_prefix("BR").unwrap();
+                let mut nzp = 0;
+                for (flag, bit) in [('N', 0x800), ('Z', 0x400), ('P', 0x200)] {
+                    if flags.contains(flag) {
+                        nzp |= bit;
+                    }
+                }
+                // a bare BR branches unconditionally; BRV keeps no bits set,
+                // which only the overflow flag extension branches on
+                if nzp == 0 && flags != "V" {
+                    nzp = 0xE00;
+                }
+                self.expect_operands(1)?;
+                nzp | self.pc_offset(0, 9)?
+            }
+        };
+        Ok(vec![word])
+    }
+
+    fn string(&self) -> Result<Vec<u16>, AsmError> {
+        parse_string(self.statement.rest.trim())
+            .ok_or_else(|| self.error("malformed string literal".to_string()))
+    }
+
+    // number of words the statement occupies
+    fn statement_size(&self) -> Result<u16, AsmError> {
+        match self.statement.op.to_ascii_uppercase().as_str() {
+            ".BLKW" => {
+                self.expect_operands(1)?;
+                match parse_signed(&self.statement.operands[0]) {
+                    Ok(n) if n >= 0 => Ok(n as u16),
+                    _ => Err(self.error("invalid .BLKW count".to_string())),
+                }
+            }
+            ".STRINGZ" => Ok(self.string()?.len() as u16),
+            _ => Ok(1),
+        }
+    }
+}
+
+// assemble `source` into its origin and words
+pub fn assemble(source: &str) -> Result<(u16, Vec<u16>), AsmError> {
+    assemble_program(source).map(|assembly| (assembly.origin, assembly.words))
+}
+
+// like `assemble`, also recording which words each source line produced
+pub fn assemble_program(source: &str) -> Result<Assembly, AsmError> {
+    let (assembly, object) = assemble_parts(source)?;
+    match object.fixups.first() {
+        Some(fixup) => Err(AsmError {
+            line: fixup.line,
+            message: format!("`{}` is .EXTERNAL; link the objects instead", fixup.symbol),
+        }),
+        None => Ok(assembly),
+    }
+}
+
+// assemble one of several sources to be linked together
+pub fn assemble_object(source: &str) -> Result<ObjectFile, AsmError> {
+    assemble_parts(source).map(|(_, object)| object)
+}
+
+fn assemble_parts(source: &str) -> Result<(Assembly, ObjectFile), AsmError> {
+    let mut labels = HashMap::new();
+    let mut externals = HashMap::new();
+    // exported labels and the line exporting them
+    let mut globals = Vec::new();
+    let no_labels = HashMap::new();
+    let mut statements = Vec::new();
+    let mut origin = None;
+    let mut address = 0u16;
+
+    for (i, raw) in source.lines().enumerate() {
+        let line = i + 1;
+        let text = strip_comment(raw).trim();
+        if text.is_empty() {
+            continue;
+        }
+        let mut words = text.splitn(2, char::is_whitespace);
+        let first = words.next().unwrap();
+        let mut rest = words.next().unwrap_or("").trim();
+        let label = if is_opcode(first) { None } else { Some(first) };
+        let op = if label.is_some() {
+            let mut words = rest.splitn(2, char::is_whitespace);
+            let op = words.next().unwrap_or("");
+            rest = words.next().unwrap_or("").trim();
+            op
+        } else {
+            first
+        };
+        if let Some(label) = label {
+            if origin.is_none() {
+                return Err(AsmError {
+                    line,
+                    message: "label before .ORIG".to_string(),
+                });
+            }
+            if labels.insert(label.to_string(), address).is_some() {
+                return Err(AsmError {
+                    line,
+                    message: format!("duplicate label `{}`", label),
+                });
+            }
+        }
+        if op.is_empty() {
+            continue;
+        }
+        if !is_opcode(op) {
+            return Err(AsmError {
+                line,
+                message: format!("unknown instruction `{}`", op),
+            });
+        }
+        let upper = op.to_ascii_uppercase();
+        if upper == ".END" {
+            break;
+        }
+        if upper == ".EXTERNAL" || upper == ".GLOBAL" {
+            let names: Vec<&str> = rest
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|s| !s.is_empty())
+                .collect();
+            if names.is_empty() {
+                return Err(AsmError {
+                    line,
+                    message: format!("{} expects a label", op),
+                });
+            }
+            for name in names {
+                if upper == ".EXTERNAL" {
+                    externals.insert(name.to_string(), line);
+                } else {
+                    globals.push((name.to_string(), line));
+                }
+            }
+            continue;
+        }
+        let operands: Vec<String> = if upper == ".STRINGZ" {
+            Vec::new()
+        } else {
+            rest.split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+                .collect()
+        };
+        if upper == ".ORIG" {
+            if origin.is_some() {
+                return Err(AsmError {
+                    line,
+                    message: "duplicate .ORIG".to_string(),
+                });
+            }
+            match operands.first().map(|s| parse_signed(s)) {
+                Some(Ok(v)) if operands.len() == 1 && v >= 0 => {
+                    origin = Some(v as u16);
+                    address = v as u16;
+                }
+                _ => {
+                    return Err(AsmError {
+                        line,
+                        message: "invalid .ORIG address".to_string(),
+                    })
+                }
+            }
+            continue;
+        }
+        if origin.is_none() {
+            return Err(AsmError {
+                line,
+                message: "missing .ORIG".to_string(),
+            });
+        }
+        let statement = Statement {
+            line,
+            op: op.to_string(),
+            operands,
+            rest: rest.to_string(),
+            address,
+        };
+        let size = Assembler {
+            labels: &no_labels,
+            externals: &externals,
+            statement: &statement,
+        }
+        .statement_size()?;
+        address = address.wrapping_add(size);
+        statements.push(statement);
+    }
+
+    let origin = origin.ok_or(AsmError {
+        line: source.lines().count(),
+        message: "missing .ORIG".to_string(),
+    })?;
+    for (name, line) in &globals {
+        if !labels.contains_key(name) {
+            return Err(AsmError {
+                line: *line,
+                message: format!(".GLOBAL label `{}` is not defined", name),
+            });
+        }
+    }
+    if let Some((name, &line)) = externals
+        .iter()
+        .find(|(name, _)| labels.contains_key(*name))
+    {
+        return Err(AsmError {
+            line,
+            message: format!("`{}` is both defined and .EXTERNAL", name),
+        });
+    }
+    let mut words = Vec::new();
+    let mut spans = Vec::new();
+    let mut fixups = Vec::new();
+    for statement in &statements {
+        let assembler = Assembler {
+            labels: &labels,
+            externals: &externals,
+            statement,
+        };
+        let encoded = assembler.encode()?;
+        fixups.extend(assembler.fixup());
+        spans.push(SourceSpan {
+            line: statement.line,
+            address: statement.address,
+            start: words.len(),
+            len: encoded.len(),
+        });
+        words.extend(encoded);
+    }
+    let exports = globals
+        .into_iter()
+        .map(|(name, _)| {
+            let address = labels[&name];
+            (name, address)
+        })
+        .collect();
+    let object = ObjectFile {
+        origin,
+        words: words.clone(),
+        exports,
+        fixups,
+    };
+    Ok((
+        Assembly {
+            origin,
+            words,
+            spans,
+        },
+        object,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{StepResult, Vm};
+
+    #[test]
+    fn assembles_and_runs_hello_world() {
+        let source = "        .ORIG x3000\n\
+                      MAIN    LEA R0, MSG   ; the greeting\n\
+                      \x20       PUTS\n\
+                      \x20       HALT\n\
+                      MSG     .STRINGZ \"Hello, World!\\n\"\n\
+                      \x20       .END\n";
+        let (origin, words) = assemble(source).unwrap();
+        assert_eq!(origin, 0x3000);
+        assert_eq!(&words[..3], &[0xE002, 0xF022, 0xF025]);
+        assert_eq!(words.len(), 3 + 15);
+
+        let mut vm = Vm::new();
+        vm.memory[0x3000..0x3000 + words.len()].copy_from_slice(&words);
+        vm.set_input(Vec::new());
+        assert_eq!(vm.run(), Ok(StepResult::Halted));
+        assert_eq!(vm.take_output(), b"Hello, World!\nHALT\n");
+    }
+
+    #[test]
+    fn encodes_every_instruction() {
+        let source = ".ORIG x3000\n\
+                      TOP ADD R1, R2, R3\n ADD R1, R2, #-16\n AND R0, R0, #0\n\
+                      NOT R4, R5\n JMP R6\n RET\n JSR TOP\n JSRR R3\n\
+                      LD R0, DATA\n LDI R1, DATA\n LEA R2, DATA\n ST R3, DATA\n\
+                      STI R4, DATA\n LDR R5, R6, #-1\n STR R7, R0, #31\n\
+                      BRnz TOP\n BR TOP\n RTI\n TRAP x23\n\
+                      GETC\n OUT\n PUTS\n IN\n PUTSP\n HALT\n\
+                      DATA .FILL xBEEF\n .BLKW #2\n .FILL #-1\n .END";
+        let (_, words) = assemble(source).unwrap();
+        assert_eq!(
+            words,
+            vec![
+                0x1283, 0x12B0, 0x5020, 0x997F, 0xC180, 0xC1C0, 0x4FF9, 0x40C0, 0x2010, 0xA20F,
+                0xE40E, 0x360D, 0xB80C, 0x6BBF, 0x7E1F, 0x0DF0, 0x0FEF, 0x8000, 0xF023, 0xF020,
+                0xF021, 0xF022, 0xF023, 0xF024, 0xF025, 0xBEEF, 0x0000, 0x0000, 0xFFFF,
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_the_line_of_an_error() {
+        let error = |source: &str| assemble(source).unwrap_err();
+        assert_eq!(error("ADD R0, R0, #1").message, "missing .ORIG");
+        assert_eq!(error(".ORIG x3000\nBR NOWHERE").line, 2);
+        assert_eq!(
+            error(".ORIG x3000\nADD R0, R0, #16").message,
+            "16 does not fit in 5 bits"
+        );
+        assert_eq!(
+            error(".ORIG x3000\nADD R8, R0, #1").message,
+            "invalid register `R8`"
+        );
+        assert_eq!(error(".ORIG x3000\nA ADD R0, R0, #1\nA HALT").line, 3);
+        assert_eq!(
+            error(".ORIG x3000\nLD R0, R1, R2").message,
+            "LD expects 2 operand(s), got 3"
+        );
+    }
+
+    #[test]
+    fn stops_at_end_and_ignores_semicolons_in_strings() {
+        let (_, words) = assemble(".ORIG x3000\n.STRINGZ \"a;b\" ; note\n.END\nHALT").unwrap();
+        assert_eq!(words, vec![0x61, 0x3B, 0x62, 0]);
+    }
+
+    #[test]
+    fn listing_shows_address_hex_and_source() {
+        let source = ".ORIG x3000\n; say hi\nLEA R0, MSG\nHALT\nMSG .STRINGZ \"Hi\"\n.END\n";
+        let assembly = assemble_program(source).unwrap();
+        assert_eq!(
+            assembly.listing(source),
+            [
+                "                 1  .ORIG x3000",
+                "                 2  ; say hi",
+                "x3000  xE001     3  LEA R0, MSG",
+                "x3001  xF025     4  HALT",
+                "x3002  x0048     5  MSG .STRINGZ \"Hi\"",
+                "x3003  x0069",
+                "x3004  x0000",
+                "                 6  .END",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn literals_can_be_binary_or_bare_decimal() {
+        let (_, words) = assemble(".ORIG x3000\nADD R0, R0, b101\nADD R0, R0, 3\n.END").unwrap();
+        assert_eq!(words, vec![0x1025, 0x1023]);
+        let err = assemble(".ORIG x3000\n.FILL x10000\n.END").unwrap_err();
+        assert_eq!(err.line, 2);
+    }
+
+    #[test]
+    fn objects_record_exports_and_external_references() {
+        let source = ".ORIG x3000\n.GLOBAL MAIN\n.EXTERNAL SUB, COUNT\n\
+                      MAIN JSR SUB\nLD R1, COUNT\nHALT\n.END";
+        let object = assemble_object(source).unwrap();
+        assert_eq!(object.words, vec![0x4800, 0x2200, 0xF025]);
+        assert_eq!(
+            object.exports,
+            HashMap::from([("MAIN".to_string(), 0x3000)])
+        );
+        assert_eq!(
+            object.fixups,
+            vec![
+                Fixup {
+                    address: 0x3000,
+                    symbol: "SUB".to_string(),
+                    kind: FixupKind::PcOffset(11),
+                    line: 4,
+                },
+                Fixup {
+                    address: 0x3001,
+                    symbol: "COUNT".to_string(),
+                    kind: FixupKind::PcOffset(9),
+                    line: 5,
+                },
+            ]
+        );
+
+        // a single program cannot leave references unresolved
+        let err = assemble_program(source).unwrap_err();
+        assert_eq!(err.line, 4);
+    }
+
+    #[test]
+    fn rejects_bad_link_directives() {
+        let undefined = assemble_object(".ORIG x3000\n.GLOBAL NOPE\nHALT\n.END").unwrap_err();
+        assert_eq!(undefined.line, 2);
+        let both = assemble_object(".ORIG x3000\n.EXTERNAL X\nX HALT\n.END").unwrap_err();
+        assert_eq!(both.line, 2);
+        assert!(assemble_object(".ORIG x3000\n.GLOBAL\n.END").is_err());
+    }
+
+    #[test]
+    fn brv_assembles_with_no_condition_bits() {
+        let (_, words) = assemble(".ORIG x3000\nLOOP BRv LOOP\nBR LOOP\n.END").unwrap();
+        assert_eq!(words, vec![0x01FF, 0x0FFE]);
+    }
+}
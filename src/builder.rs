@@ -0,0 +1,94 @@
+// Fluent setup of a machine for instruction tests and embedders:
+//
+//     let vm = VmBuilder::new()
+//         .reg(Register::RR1, 5)
+//         .word(0x3000, 0x1061) // ADD R0, R1, #1
+//         .build();
+//
+// Words are stored straight into memory, without going through devices,
+// hooks or history the way a program's stores do.
+
+use crate::isa::Register;
+use crate::vm::Vm;
+
+pub struct VmBuilder {
+    vm: Vm,
+}
+
+impl Default for VmBuilder {
+    fn default() -> VmBuilder {
+        VmBuilder::new()
+    }
+}
+
+impl VmBuilder {
+    // a powered-on machine with the PC at x3000
+    pub fn new() -> VmBuilder {
+        VmBuilder { vm: Vm::new() }
+    }
+
+    pub fn reg(mut self, reg: Register, value: u16) -> VmBuilder {
+        self.vm.reg[reg as usize] = value;
+        self
+    }
+
+    pub fn pc(self, addr: u16) -> VmBuilder {
+        self.reg(Register::RPC, addr)
+    }
+
+    pub fn word(self, addr: u16, value: u16) -> VmBuilder {
+        self.words(addr, &[value])
+    }
+
+    // consecutive words from `addr`, wrapping at the top of memory
+    pub fn words(mut self, addr: u16, values: &[u16]) -> VmBuilder {
+        for (addr, &value) in (addr..=u16::MAX).chain(0..).zip(values) {
+            self.vm.memory[addr as usize] = value;
+            #[cfg(feature = "debug_mem")]
+            {
+                self.vm.initialized[addr as usize] = true;
+            }
+        }
+        self
+    }
+
+    pub fn build(self) -> Vm {
+        self.vm
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::StepResult;
+
+    #[test]
+    fn runs_an_add_set_up_by_the_builder() {
+        let mut vm = VmBuilder::new()
+            .reg(Register::RR1, 5)
+            .word(0x3000, 0x1061) // ADD R0, R1, #1
+            .build();
+        assert_eq!(vm.step(), Ok(StepResult::Continued));
+        assert_eq!(vm.reg[Register::RR0 as usize], 6);
+        assert_eq!(vm.reg[Register::RPC as usize], 0x3001);
+    }
+
+    #[test]
+    fn runs_an_ldr_set_up_by_the_builder() {
+        let mut vm = VmBuilder::new()
+            .pc(0x4000)
+            .reg(Register::RR2, 0x5000)
+            .word(0x4000, 0x6683) // LDR R3, R2, #3
+            .words(0x5002, &[0x1111, 0x2222])
+            .build();
+        vm.step().unwrap();
+        assert_eq!(vm.reg[Register::RR3 as usize], 0x2222);
+        assert_eq!(vm.reg[Register::RPC as usize], 0x4001);
+    }
+
+    #[test]
+    fn words_wrap_at_the_top_of_memory() {
+        let vm = VmBuilder::new().words(0xFFFF, &[1, 2]).build();
+        assert_eq!((vm.memory[0xFFFF], vm.memory[0]), (1, 2));
+    }
+}
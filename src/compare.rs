@@ -0,0 +1,226 @@
+// Lock-step comparison of two machines, for checking an implementation
+// against a reference run and finding the first instruction they disagree on.
+
+use std::ops::Range;
+
+use crate::isa::Register;
+use crate::vm::{Vm, MEMORY_SIZE};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Difference {
+    // register index, 8 for PC
+    Register {
+        index: usize,
+        ours: u16,
+        theirs: u16,
+    },
+    Psr {
+        ours: u16,
+        theirs: u16,
+    },
+    Memory {
+        addr: u16,
+        ours: u16,
+        theirs: u16,
+    },
+}
+
+// the instruction after which the machines stopped agreeing
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Divergence {
+    pub pc: u16,
+    pub instr: u16,
+    pub difference: Difference,
+}
+
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "x{:04X}  x{:04X}: ", self.pc, self.instr)?;
+        match self.difference {
+            Difference::Register {
+                index,
+                ours,
+                theirs,
+            } => {
+                let name = if index == Register::RPC as usize {
+                    "PC".to_string()
+                } else {
+                    format!("R{}", index)
+                };
+                write!(f, "{} is x{:04X}, expected x{:04X}", name, ours, theirs)
+            }
+            Difference::Psr { ours, theirs } => {
+                write!(f, "PSR is x{:04X}, expected x{:04X}", ours, theirs)
+            }
+            Difference::Memory { addr, ours, theirs } => write!(
+                f,
+                "x{:04X} holds x{:04X}, expected x{:04X}",
+                addr, ours, theirs
+            ),
+        }
+    }
+}
+
+fn first_difference(ours: &Vm, theirs: &Vm) -> Option<Difference> {
+    for index in 0..Register::RCOUNT as usize {
+        if ours.reg[index] != theirs.reg[index] {
+            return Some(Difference::Register {
+                index,
+                ours: ours.reg[index],
+                theirs: theirs.reg[index],
+            });
+        }
+    }
+    if ours.psr() != theirs.psr() {
+        return Some(Difference::Psr {
+            ours: ours.psr(),
+            theirs: theirs.psr(),
+        });
+    }
+    (0..MEMORY_SIZE)
+        .find(|&addr| ours.memory[addr] != theirs.memory[addr])
+        .map(|addr| Difference::Memory {
+            addr: addr as u16,
+            ours: ours.memory[addr],
+            theirs: theirs.memory[addr],
+        })
+}
+
+impl Vm {
+    // (address, our word, their word) for every word in `range` that differs
+    pub fn diff_memory(&self, other: &Vm, range: Range<usize>) -> Vec<(u16, u16, u16)> {
+        let range = range.start.min(MEMORY_SIZE)..range.end.min(MEMORY_SIZE);
+        range
+            .filter(|&addr| self.memory[addr] != other.memory[addr])
+            .map(|addr| (addr as u16, self.memory[addr], other.memory[addr]))
+            .collect()
+    }
+
+    // execute one instruction and compare the result with `reference`, which
+    // the caller has already advanced by the same instruction
+    pub fn step_and_diff(&mut self, reference: &Vm) -> Option<Divergence> {
+        let pc = self.reg[Register::RPC as usize];
+        let instr = self.memory[pc as usize];
+        // a faulting instruction is compared like any other
+        let _ = self.step();
+        first_difference(self, reference).map(|difference| Divergence {
+            pc,
+            instr,
+            difference,
+        })
+    }
+}
+
+// run both machines in lock step for up to `max_instrs` instructions or
+// until the reference halts, returning the first divergence
+pub fn find_divergence(ours: &mut Vm, reference: &mut Vm, max_instrs: u64) -> Option<Divergence> {
+    for _ in 0..max_instrs {
+        if !reference.is_running() {
+            break;
+        }
+        let _ = reference.step();
+        if let Some(divergence) = ours.step_and_diff(reference) {
+            return Some(divergence);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // R0 = x7FFE; ADD R0, R0, #1; ADD R0, R0, #1; ADD R1, R1, #1; HALT
+    fn program() -> Vm {
+        let mut vm = Vm::new();
+        vm.reg[0] = 0x7FFE;
+        vm.memory[0x3000..0x3004].copy_from_slice(&[0x1021, 0x1021, 0x1261, 0xF025]);
+        vm
+    }
+
+    #[test]
+    fn identical_machines_never_diverge() {
+        let mut ours = program();
+        let mut reference = program();
+        assert_eq!(find_divergence(&mut ours, &mut reference, 100), None);
+        assert!(!ours.is_running());
+    }
+
+    #[test]
+    fn pinpoints_a_non_wrapping_add() {
+        let mut ours = program();
+        let mut reference = program();
+        // a reference whose ADD saturates instead of wrapping past x7FFF
+        let mut divergence = None;
+        for _ in 0..4 {
+            let before = reference.reg[0];
+            reference.step().unwrap();
+            if before == 0x7FFF {
+                reference.reg[0] = 0x7FFF;
+                reference.psr = 0x8001;
+            }
+            divergence = ours.step_and_diff(&reference);
+            if divergence.is_some() {
+                break;
+            }
+        }
+        let divergence = divergence.unwrap();
+        assert_eq!(
+            divergence,
+            Divergence {
+                pc: 0x3001,
+                instr: 0x1021,
+                difference: Difference::Register {
+                    index: 0,
+                    ours: 0x8000,
+                    theirs: 0x7FFF,
+                },
+            }
+        );
+        assert_eq!(
+            divergence.to_string(),
+            "x3001  x1021: R0 is x8000, expected x7FFF"
+        );
+    }
+
+    #[test]
+    fn reports_memory_and_psr_differences() {
+        let mut ours = program();
+        let mut reference = program();
+        reference.step().unwrap();
+        reference.memory[0x4000] = 1;
+        assert_eq!(
+            ours.step_and_diff(&reference).unwrap().difference,
+            Difference::Memory {
+                addr: 0x4000,
+                ours: 0,
+                theirs: 1,
+            }
+        );
+
+        let mut ours = program();
+        let mut reference = program();
+        reference.step().unwrap();
+        reference.psr |= 0x0100;
+        let divergence = ours.step_and_diff(&reference).unwrap();
+        assert_eq!(
+            divergence.to_string(),
+            "x3000  x1021: PSR is x8001, expected x8101"
+        );
+    }
+
+    #[test]
+    fn diff_memory_lists_the_differing_words() {
+        let mut ours = Vm::new();
+        let mut theirs = Vm::new();
+        ours.memory[0x4000..0x4004].copy_from_slice(&[1, 2, 3, 4]);
+        theirs.memory[0x4000..0x4004].copy_from_slice(&[1, 2, 7, 4]);
+        assert_eq!(
+            ours.diff_memory(&theirs, 0x4000..0x4004),
+            vec![(0x4002, 3, 7)]
+        );
+        assert!(ours.diff_memory(&theirs, 0x4000..0x4002).is_empty());
+        // the range is clipped to memory
+        assert_eq!(ours.diff_memory(&theirs, 0x4002..1 << 20).len(), 1);
+    }
+}
@@ -0,0 +1,694 @@
+// Interactive debugger prompt used by `--debug`.
+
+use std::io::Write;
+
+use crate::expr::{parse_condition, parse_expr, Condition, Expr};
+use crate::isa::{decode, Instruction, Register, TrapCode};
+use crate::number::parse_number;
+use crate::vm::{StepResult, Vm, VmError};
+use crate::{disasm, terminal};
+
+const HELP: &str = "commands:
+  step [n]            execute n instructions (default 1)
+  next                step over a subroutine call or trap
+  finish              run until the current subroutine returns to the
+                      address in R7
+  back [n]            undo the last n instructions (default 1)
+  continue            run until a breakpoint, watchpoint or halt
+  restore-last-break  go back to the state at the last breakpoint hit,
+                      undoing `set` as well as executed instructions
+  run-until xADDR     continue until the PC reaches ADDR
+  break xADDR [if C]  set a breakpoint, optionally only stopping when the
+                      comparison C holds, e.g. `R0 == x5` or `mem[x4000] < 0`
+  delete xADDR        remove a breakpoint
+  break-trap T        stop before any TRAP to T, a vector or a name like OUT
+  delete-trap T       remove a trap breakpoint
+  watch EXPR          print EXPR after every command that runs code, e.g.
+                      `R0 + mem[x4000]`
+  unwatch N           remove watch expression N
+  backtrace           list the subroutine calls in progress, innermost
+                      first
+  regs                show the registers
+  mem xADDR [count]   show memory words
+  set Rn|PC xVAL      change a register (SP and LR name R6 and R7)
+  set xADDR xVAL      change a memory word
+  disas xADDR [count] disassemble memory
+  write-obj xSTART xEND FILE
+                      save memory from START up to END as an .obj image
+  reset               restart the program, keeping memory and breakpoints
+  quit                leave the debugger";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Step(u32),
+    Next,
+    Finish,
+    Back(u32),
+    Continue,
+    RestoreLastBreak,
+    RunUntil(u16),
+    Break(u16, Option<Condition>),
+    Delete(u16),
+    BreakTrap(u8),
+    DeleteTrap(u8),
+    Watch(String, Expr),
+    Unwatch(usize),
+    Backtrace,
+    Regs,
+    Mem(u16, u16),
+    // register index (8 for PC) and value
+    Set(usize, u16),
+    SetMem(u16, u16),
+    Disas(u16, u16),
+    WriteObj(u16, u16, String),
+    Reset,
+    Help,
+    Quit,
+}
+
+// any literal `parse_number` accepts
+pub(crate) fn parse_value(s: &str) -> Result<u16, String> {
+    parse_number(s).map_err(|e| format!("invalid value `{}`: {}", s, e))
+}
+
+// `HALT`, `out` or a vector such as `x23`
+fn parse_trap(s: &str) -> Result<u8, String> {
+    let code = match s.to_ascii_uppercase().as_str() {
+        "GETC" => TrapCode::GETC,
+        "OUT" => TrapCode::OUT,
+        "PUTS" => TrapCode::PUTS,
+        "IN" => TrapCode::IN,
+        "PUTSP" => TrapCode::PUTSP,
+        "HALT" => TrapCode::HALT,
+        _ => {
+            let vector = parse_value(s)?;
+            return u8::try_from(vector).map_err(|_| format!("invalid trap vector `{}`", s));
+        }
+    };
+    Ok(code as u8)
+}
+
+pub(crate) fn parse_register(s: &str) -> Result<usize, String> {
+    let upper = s.to_ascii_uppercase();
+    match upper.as_str() {
+        "PC" => return Ok(Register::RPC as usize),
+        "SP" => return Ok(Register::RR6 as usize),
+        "LR" => return Ok(Register::RR7 as usize),
+        _ => {}
+    }
+    match upper
+        .strip_prefix('R')
+        .and_then(|n| n.parse::<usize>().ok())
+    {
+        Some(n) if n < 8 => Ok(n),
+        _ => Err(format!("invalid register `{}`", s)),
+    }
+}
+
+pub fn parse_command(line: &str) -> Result<Command, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let arg = |i: usize| -> Result<&str, String> {
+        words
+            .get(i)
+            .copied()
+            .ok_or(format!("`{}` needs more arguments", words[0]))
+    };
+    let count = |i: usize, default: u16| -> Result<u16, String> {
+        words.get(i).map_or(Ok(default), |w| parse_value(w))
+    };
+    let command = match words.first().copied() {
+        None => return Err("empty command".to_string()),
+        Some("step" | "s") => Command::Step(count(1, 1)? as u32),
+        Some("next" | "n" | "step-over") => Command::Next,
+        Some("finish" | "fin") => Command::Finish,
+        Some("back") => Command::Back(count(1, 1)? as u32),
+        Some("continue" | "c") => Command::Continue,
+        Some("restore-last-break" | "restore") => Command::RestoreLastBreak,
+        Some("run-until" | "until") => Command::RunUntil(parse_value(arg(1)?)?),
+        Some("break" | "b") => {
+            let addr = parse_value(arg(1)?)?;
+            let condition = match words.get(2) {
+                None => None,
+                Some(&"if") => Some(parse_condition(&words[3..].join(" "))?),
+                Some(other) => return Err(format!("expected `if`, got `{}`", other)),
+            };
+            Command::Break(addr, condition)
+        }
+        Some("delete" | "d") => Command::Delete(parse_value(arg(1)?)?),
+        Some("break-trap") => Command::BreakTrap(parse_trap(arg(1)?)?),
+        Some("delete-trap") => Command::DeleteTrap(parse_trap(arg(1)?)?),
+        Some("watch") => {
+            let text = words[1..].join(" ");
+            let expr = parse_expr(&text)?;
+            Command::Watch(text, expr)
+        }
+        Some("unwatch") => Command::Unwatch(parse_value(arg(1)?)? as usize),
+        Some("backtrace" | "bt") => Command::Backtrace,
+        Some("regs" | "r") => Command::Regs,
+        Some("mem" | "m") => Command::Mem(parse_value(arg(1)?)?, count(2, 1)?),
+        Some("set") => match parse_register(arg(1)?) {
+            Ok(r) => Command::Set(r, parse_value(arg(2)?)?),
+            Err(_) => Command::SetMem(parse_value(arg(1)?)?, parse_value(arg(2)?)?),
+        },
+        Some("disas") => Command::Disas(parse_value(arg(1)?)?, count(2, 1)?),
+        Some("write-obj") => Command::WriteObj(
+            parse_value(arg(1)?)?,
+            parse_value(arg(2)?)?,
+            arg(3)?.to_string(),
+        ),
+        Some("reset") => Command::Reset,
+        Some("help" | "h") => Command::Help,
+        Some("quit" | "q") => Command::Quit,
+        Some(other) => return Err(format!("unknown command `{}`", other)),
+    };
+    Ok(command)
+}
+
+fn report(vm: &mut Vm, result: Result<StepResult, VmError>) {
+    vm.flush_output();
+    match result {
+        Ok(StepResult::Halted) => println!("program halted"),
+        Ok(StepResult::Breakpoint(addr)) => {
+            println!("breakpoint at x{:04X}", addr);
+            vm.checkpoint();
+        }
+        Ok(StepResult::Watchpoint { addr, old, new }) => {
+            println!("watchpoint x{:04X}: x{:04X} -> x{:04X}", addr, old, new)
+        }
+        Ok(StepResult::TrapBreakpoint { pc, vector }) => {
+            println!("trap x{:02X} at x{:04X}", vector, pc)
+        }
+        Ok(StepResult::Continued | StepResult::Trap(_)) => {}
+        Err(e) => println!("{}", e),
+    }
+    if vm.is_running() {
+        let pc = vm.reg[Register::RPC as usize];
+        let instr = vm.memory[pc as usize];
+        println!(
+            "x{:04X}  x{:04X}  {}",
+            pc,
+            instr,
+            disasm::disassemble_with_symbols(instr, pc, &vm.symbols, vm.overflow_flag)
+        );
+    }
+    print_watches(vm);
+}
+
+// run with a temporary breakpoint at `addr`, which is removed again
+// afterwards; reaching it reports as Continued
+fn run_to(vm: &mut Vm, addr: u16) -> Result<StepResult, VmError> {
+    let temporary = !vm.breakpoints.contains(&addr);
+    let condition = vm.conditions.get(&addr).copied();
+    vm.add_breakpoint(addr);
+    let result = match vm.run() {
+        Ok(StepResult::Breakpoint(hit)) if temporary && hit == addr => Ok(StepResult::Continued),
+        result => result,
+    };
+    if temporary {
+        vm.remove_breakpoint(addr);
+    } else if let Some(condition) = condition {
+        vm.add_conditional_breakpoint(addr, condition);
+    }
+    result
+}
+
+fn print_watches(vm: &Vm) {
+    for (i, (text, expr)) in vm.watch_exprs.iter().enumerate() {
+        let value = expr.eval(vm);
+        println!("{}: {} = x{:04X}  {}", i, text, value, value as i16);
+    }
+}
+
+pub fn execute(vm: &mut Vm, command: Command) {
+    match command {
+        Command::Step(n) => {
+            let mut result = Ok(StepResult::Continued);
+            for _ in 0..n {
+                result = vm.step();
+                if !matches!(result, Ok(StepResult::Continued | StepResult::Trap(_))) {
+                    break;
+                }
+            }
+            report(vm, result);
+        }
+        Command::Next => {
+            let pc = vm.reg[Register::RPC as usize];
+            let is_call = matches!(
+                decode(vm.memory[pc as usize] >> 12),
+                Instruction::JSR | Instruction::TRAP
+            );
+            if !is_call {
+                execute(vm, Command::Step(1));
+                return;
+            }
+            let result = run_to(vm, pc.wrapping_add(1));
+            report(vm, result);
+        }
+        // the return address is whatever R7 holds now, so a routine that
+        // saved R7 and reused it must be finished after it restores it
+        Command::Finish => {
+            let result = run_to(vm, vm.reg[Register::RR7 as usize]);
+            report(vm, result);
+        }
+        Command::RunUntil(addr) => {
+            let result = run_to(vm, addr);
+            report(vm, result);
+        }
+        Command::Back(n) => {
+            for _ in 0..n {
+                if !vm.step_back() {
+                    println!("no more history");
+                    break;
+                }
+            }
+            report(vm, Ok(StepResult::Continued));
+        }
+        Command::Continue => {
+            let result = vm.run();
+            report(vm, result);
+        }
+        Command::RestoreLastBreak => {
+            if vm.restore_checkpoint() {
+                report(vm, Ok(StepResult::Continued));
+            } else {
+                println!("no breakpoint has been hit");
+            }
+        }
+        Command::Break(addr, None) => vm.add_breakpoint(addr),
+        Command::Break(addr, Some(condition)) => vm.add_conditional_breakpoint(addr, condition),
+        Command::Delete(addr) => {
+            if !vm.remove_breakpoint(addr) {
+                println!("no breakpoint at x{:04X}", addr);
+            }
+        }
+        Command::BreakTrap(vector) => vm.add_trap_breakpoint(vector),
+        Command::DeleteTrap(vector) => {
+            if !vm.remove_trap_breakpoint(vector) {
+                println!("no trap breakpoint on x{:02X}", vector);
+            }
+        }
+        Command::Watch(text, expr) => {
+            vm.watch_exprs.push((text, expr));
+            print_watches(vm);
+        }
+        Command::Unwatch(n) => {
+            if n < vm.watch_exprs.len() {
+                vm.watch_exprs.remove(n);
+            } else {
+                println!("no watch expression {}", n);
+            }
+        }
+        Command::Backtrace => {
+            if vm.call_stack().is_empty() {
+                println!("not in a subroutine");
+            }
+            for (i, frame) in vm.call_stack().iter().rev().enumerate() {
+                let name = vm
+                    .symbols
+                    .get(&frame.target)
+                    .map_or(String::new(), |label| format!(" {}", label));
+                println!(
+                    "#{}  x{:04X}{}  called from x{:04X}",
+                    i, frame.target, name, frame.caller
+                );
+            }
+        }
+        Command::Regs => match vm.previous_registers() {
+            Some(before) => print!("{}", vm.dump_registers_since(&before)),
+            None => print!("{}", vm.dump_registers()),
+        },
+        Command::Mem(addr, count) => print!("{}", vm.dump_memory(addr, count as usize)),
+        Command::Set(r, val) => vm.reg[r] = val,
+        Command::SetMem(addr, val) => vm.memory[addr as usize] = val,
+        Command::WriteObj(start, end, path) => {
+            if let Err(e) = vm.write_obj(start, end as usize, &path) {
+                println!("{}: {}", path, e);
+            }
+        }
+        Command::Disas(addr, count) => {
+            for i in 0..count {
+                let a = addr.wrapping_add(i);
+                let instr = vm.memory[a as usize];
+                println!(
+                    "x{:04X}  x{:04X}  {}",
+                    a,
+                    instr,
+                    disasm::disassemble_with_symbols(instr, a, &vm.symbols, vm.overflow_flag)
+                );
+            }
+        }
+        Command::Reset => {
+            vm.reset();
+            report(vm, Ok(StepResult::Continued));
+        }
+        Command::Help => println!("{}", HELP),
+        Command::Quit => {}
+    }
+}
+
+// the debugger reading commands from the terminal; it starts paused at the
+// current PC, before the first instruction runs
+pub fn run(vm: &mut Vm) {
+    session(vm, terminal::read_line);
+}
+
+// the command loop, reading lines from `next_line` until it runs out or the
+// user quits
+fn session(vm: &mut Vm, mut next_line: impl FnMut() -> Option<String>) {
+    vm.record_history = true;
+    // `reset` goes back here rather than to PC_START
+    let start = vm.reg[Register::RPC as usize];
+    report(vm, Ok(StepResult::Continued));
+    loop {
+        print!("(lc3) ");
+        std::io::stdout().flush().unwrap();
+        let line = match next_line() {
+            Some(line) => line,
+            None => break,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse_command(&line) {
+            Ok(Command::Quit) => break,
+            Ok(Command::Reset) => {
+                vm.reset();
+                vm.reg[Register::RPC as usize] = start;
+                report(vm, Ok(StepResult::Continued));
+            }
+            Ok(command) => execute(vm, command),
+            Err(msg) => println!("{}", msg),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::LoadOptions;
+
+    #[test]
+    fn parses_a_scripted_session() {
+        let script = "step\nstep 5\ncontinue\nbreak x3002\ndelete 0x3002\nregs\n\
+                      mem x4000 4\nset R3 #-1\nset pc x3000\ndisas x3000 3\nhelp\nquit";
+        let commands: Vec<Command> = script
+            .lines()
+            .map(|line| parse_command(line).unwrap())
+            .collect();
+        assert_eq!(
+            commands,
+            vec![
+                Command::Step(1),
+                Command::Step(5),
+                Command::Continue,
+                Command::Break(0x3002, None),
+                Command::Delete(0x3002),
+                Command::Regs,
+                Command::Mem(0x4000, 4),
+                Command::Set(3, 0xFFFF),
+                Command::Set(Register::RPC as usize, 0x3000),
+                Command::Disas(0x3000, 3),
+                Command::Help,
+                Command::Quit,
+            ]
+        );
+    }
+
+    #[test]
+    fn accepts_short_command_names() {
+        assert_eq!(parse_command("s 2"), Ok(Command::Step(2)));
+        assert_eq!(parse_command("c"), Ok(Command::Continue));
+        assert_eq!(parse_command("b x3000"), Ok(Command::Break(0x3000, None)));
+        assert_eq!(parse_command("m x3000"), Ok(Command::Mem(0x3000, 1)));
+        assert_eq!(parse_command("back"), Ok(Command::Back(1)));
+        assert_eq!(parse_command("back 3"), Ok(Command::Back(3)));
+        assert_eq!(parse_command("n"), Ok(Command::Next));
+        assert_eq!(parse_command("step-over"), Ok(Command::Next));
+        assert_eq!(parse_command("reset"), Ok(Command::Reset));
+        assert_eq!(parse_command("fin"), Ok(Command::Finish));
+        assert_eq!(parse_command("bt"), Ok(Command::Backtrace));
+        assert_eq!(parse_command("until x3005"), Ok(Command::RunUntil(0x3005)));
+        assert_eq!(
+            parse_command("break-trap OUT"),
+            Ok(Command::BreakTrap(0x21))
+        );
+        assert_eq!(
+            parse_command("break-trap x23"),
+            Ok(Command::BreakTrap(0x23))
+        );
+        assert_eq!(
+            parse_command("delete-trap halt"),
+            Ok(Command::DeleteTrap(0x25))
+        );
+    }
+
+    #[test]
+    fn rejects_bad_commands() {
+        assert!(parse_command("").is_err());
+        assert!(parse_command("jump x3000").is_err());
+        assert!(parse_command("break").is_err());
+        assert!(parse_command("set R8 x1").is_err());
+        assert!(parse_command("mem xZZZZ").is_err());
+        assert!(parse_command("break x3000 when R0 == 1").is_err());
+        assert!(parse_command("break x3000 if R0").is_err());
+        assert!(parse_command("break-trap x100").is_err());
+        assert!(parse_command("break-trap").is_err());
+    }
+
+    #[test]
+    fn execute_drives_the_vm() {
+        let mut vm = Vm::new();
+        vm.memory[0x3000..0x3003].copy_from_slice(&[0x1021, 0x1021, 0xF025]);
+        execute(&mut vm, Command::Set(1, 9));
+        execute(&mut vm, Command::Break(0x3001, None));
+        execute(&mut vm, Command::Continue);
+        assert_eq!(vm.reg[1], 9);
+        assert_eq!(vm.reg[Register::RPC as usize], 0x3001);
+        execute(&mut vm, Command::Step(2));
+        assert!(!vm.is_running());
+        assert_eq!(vm.reg[0], 2);
+    }
+
+    #[test]
+    fn back_undoes_recorded_steps() {
+        let mut vm = Vm::new();
+        vm.record_history = true;
+        vm.memory[0x3000..0x3003].copy_from_slice(&[0x1021, 0x1021, 0xF025]);
+        execute(&mut vm, Command::Step(2));
+        assert_eq!(vm.reg[0], 2);
+        execute(&mut vm, Command::Back(5));
+        assert_eq!(vm.reg[0], 0);
+        assert_eq!(vm.reg[Register::RPC as usize], 0x3000);
+    }
+
+    #[test]
+    fn next_steps_over_a_subroutine_call() {
+        let mut vm = Vm::new();
+        // JSR SUB; HALT; ...; SUB: ADD R1, R1, #5; ADD R2, R2, #1; RET
+        vm.memory[0x3000..0x3002].copy_from_slice(&[0x4804, 0xF025]);
+        vm.memory[0x3005..0x3008].copy_from_slice(&[0x1265, 0x14A1, 0xC1C0]);
+        execute(&mut vm, Command::Next);
+        assert_eq!(vm.reg[Register::RPC as usize], 0x3001);
+        assert_eq!(vm.reg[1], 5);
+        assert_eq!(vm.reg[2], 1);
+        assert!(vm.breakpoints.is_empty());
+
+        // anything else is a single step
+        vm.memory[0x3001] = 0x1021; // ADD R0, R0, #1
+        execute(&mut vm, Command::Next);
+        assert_eq!(vm.reg[Register::RPC as usize], 0x3002);
+        assert_eq!(vm.reg[0], 1);
+    }
+
+    #[test]
+    fn parses_conditional_breakpoints() {
+        assert_eq!(
+            parse_command("break x3001 if R0 == x0005"),
+            Ok(Command::Break(
+                0x3001,
+                Some(parse_condition("R0 == x0005").unwrap())
+            ))
+        );
+    }
+
+    #[test]
+    fn conditional_breakpoints_skip_hits_until_the_condition_holds() {
+        let mut vm = Vm::new();
+        // loop: ADD R0, R0, #1; ADD R1, R0, #-8; BRn loop; HALT
+        vm.memory[0x3000..0x3004].copy_from_slice(&[0x1021, 0x1238, 0x09FD, 0xF025]);
+        execute(&mut vm, parse_command("break x3001 if R0 == x5").unwrap());
+        execute(&mut vm, Command::Continue);
+        // four passes through x3001 went by without stopping
+        assert_eq!(vm.reg[Register::RPC as usize], 0x3001);
+        assert_eq!(vm.reg[0], 5);
+        execute(&mut vm, Command::Continue);
+        assert!(!vm.is_running());
+        assert_eq!(vm.reg[0], 8);
+    }
+
+    #[test]
+    fn reset_command_restarts_without_losing_memory() {
+        let mut vm = Vm::new();
+        vm.memory[0x3000..0x3002].copy_from_slice(&[0x1021, 0xF025]);
+        execute(&mut vm, Command::Continue);
+        assert!(!vm.is_running());
+        execute(&mut vm, Command::Reset);
+        assert_eq!(vm.reg[0], 0);
+        assert_eq!(vm.reg[Register::RPC as usize], 0x3000);
+        execute(&mut vm, Command::Continue);
+        assert_eq!(vm.reg[0], 1);
+    }
+
+    #[test]
+    fn trap_breakpoints_pause_before_the_trap() {
+        let mut vm = Vm::new();
+        vm.set_input(Vec::new());
+        // ADD R0, R0, #1; ADD R0, R0, #1; OUT; HALT
+        vm.memory[0x3000..0x3004].copy_from_slice(&[0x1021, 0x1021, 0xF021, 0xF025]);
+        execute(&mut vm, parse_command("break-trap OUT").unwrap());
+        execute(&mut vm, Command::Continue);
+        assert_eq!(vm.reg[Register::RPC as usize], 0x3002);
+        assert_eq!(vm.reg[0], 2);
+        assert!(vm.take_output().is_empty());
+
+        execute(&mut vm, Command::DeleteTrap(0x21));
+        execute(&mut vm, Command::Continue);
+        assert!(!vm.is_running());
+        assert_eq!(vm.take_output(), b"\x02HALT\n");
+    }
+
+    #[test]
+    fn watch_expressions_follow_the_program() {
+        let mut vm = Vm::new();
+        vm.memory[0x4000] = 10;
+        // ADD R0, R0, #2; ADD R0, R0, #2; HALT
+        vm.memory[0x3000..0x3003].copy_from_slice(&[0x1022, 0x1022, 0xF025]);
+        execute(&mut vm, parse_command("watch R0 + mem[x4000]").unwrap());
+        let (text, expr) = &vm.watch_exprs[0];
+        assert_eq!(text, "R0 + mem[x4000]");
+        assert_eq!(expr.eval(&vm), 10);
+        execute(&mut vm, Command::Step(1));
+        assert_eq!(vm.watch_exprs[0].1.eval(&vm), 12);
+        execute(&mut vm, Command::Step(1));
+        assert_eq!(vm.watch_exprs[0].1.eval(&vm), 14);
+
+        execute(&mut vm, Command::Unwatch(0));
+        assert!(vm.watch_exprs.is_empty());
+        assert!(parse_command("watch R0 +").is_err());
+    }
+
+    #[test]
+    fn run_until_stops_at_the_address_once() {
+        let mut vm = Vm::new();
+        // five ADD R2, R2, #1, then loop: ADD R0, R0, #1; ADD R1, R0, #-3;
+        // BRn loop; HALT
+        vm.memory[0x3000..0x3005].fill(0x14A1);
+        vm.memory[0x3005..0x3009].copy_from_slice(&[0x1021, 0x123D, 0x09FD, 0xF025]);
+        execute(&mut vm, parse_command("run-until x3005").unwrap());
+        assert_eq!(vm.reg[Register::RPC as usize], 0x3005);
+        assert_eq!(vm.reg[2], 5);
+        assert_eq!(vm.reg[0], 0);
+        assert!(vm.breakpoints.is_empty());
+
+        // the loop passes x3005 twice more without stopping
+        execute(&mut vm, Command::Continue);
+        assert!(!vm.is_running());
+        assert_eq!(vm.reg[0], 3);
+    }
+
+    #[test]
+    fn finish_runs_back_to_the_caller() {
+        let mut vm = Vm::new();
+        // JSR SUB; HALT; ...; SUB: ADD R1, R1, #5; ADD R2, R2, #1; RET
+        vm.memory[0x3000..0x3002].copy_from_slice(&[0x4804, 0xF025]);
+        vm.memory[0x3005..0x3008].copy_from_slice(&[0x1265, 0x14A1, 0xC1C0]);
+        execute(&mut vm, Command::Step(2));
+        assert_eq!(vm.reg[Register::RPC as usize], 0x3006);
+        execute(&mut vm, Command::Finish);
+        assert_eq!(vm.reg[Register::RPC as usize], 0x3001);
+        assert_eq!(vm.reg[2], 1);
+        assert!(vm.is_running());
+        assert!(vm.breakpoints.is_empty());
+    }
+
+    #[test]
+    fn the_session_pauses_before_the_first_instruction() {
+        let mut vm = Vm::new();
+        // ADD R3, R3, #2; HALT
+        vm.memory[0x3000..0x3002].copy_from_slice(&[0x16E2, 0xF025]);
+        session(&mut vm, || None);
+        assert_eq!(vm.instr_count, 0);
+        assert_eq!(vm.reg[Register::RPC as usize], 0x3000);
+        assert_eq!(vm.reg[3], 0);
+
+        let mut script = ["step", "quit"].into_iter().map(String::from);
+        session(&mut vm, || script.next());
+        assert_eq!(vm.instr_count, 1);
+        assert_eq!(vm.reg[3], 2);
+    }
+
+    #[test]
+    fn sp_and_lr_name_r6_and_r7() {
+        assert_eq!(parse_command("set SP xFE00"), Ok(Command::Set(6, 0xFE00)));
+        assert_eq!(parse_command("set lr x3000"), Ok(Command::Set(7, 0x3000)));
+    }
+
+    #[test]
+    fn restore_last_break_returns_to_the_breakpoint_state() {
+        let mut vm = Vm::new();
+        vm.record_history = true;
+        // ADD R0, R0, #1 three times; HALT
+        vm.memory[0x3000..0x3004].copy_from_slice(&[0x1021, 0x1021, 0x1021, 0xF025]);
+        execute(&mut vm, Command::RestoreLastBreak);
+        assert_eq!(vm.reg[Register::RPC as usize], 0x3000);
+
+        execute(&mut vm, Command::Break(0x3002, None));
+        execute(&mut vm, Command::Continue);
+        assert_eq!(vm.reg[0], 2);
+        let at_break = vm.save_snapshot();
+
+        execute(&mut vm, Command::Set(1, 7));
+        execute(&mut vm, Command::Step(2));
+        assert!(!vm.is_running());
+        execute(&mut vm, parse_command("restore-last-break").unwrap());
+        assert_eq!(vm.save_snapshot(), at_break);
+        assert_eq!(vm.instr_count, 2);
+
+        // history from before the breakpoint still steps back
+        execute(&mut vm, Command::Back(1));
+        assert_eq!(vm.reg[0], 1);
+        assert_eq!(vm.reg[Register::RPC as usize], 0x3001);
+    }
+
+    #[test]
+    fn memory_changed_in_the_debugger_survives_write_obj_and_reload() {
+        let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/add.obj");
+        let path = std::env::temp_dir().join(format!("lc3-write-obj-{}.obj", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut vm = Vm::new();
+        let loaded = vm.read_image(fixture, LoadOptions::default()).unwrap();
+        assert_eq!(loaded, 0x3000..0x3003);
+
+        // ADD R3, R3, #5 becomes ADD R3, R3, #-1
+        execute(&mut vm, parse_command("set x3001 x16FF").unwrap());
+        execute(
+            &mut vm,
+            parse_command(&format!("write-obj x3000 x3003 {}", path)).unwrap(),
+        );
+        let mut reloaded = Vm::new();
+        let range = reloaded.read_image(path, LoadOptions::default());
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(range.unwrap(), 0x3000..0x3003);
+        assert_eq!(reloaded.memory[0x3000..0x3003], [0x16E2, 0x16FF, 0xF025]);
+    }
+
+    #[test]
+    fn set_takes_a_register_or_an_address() {
+        assert_eq!(parse_command("set R2 x10"), Ok(Command::Set(2, 0x10)));
+        assert_eq!(
+            parse_command("set x4000 x10"),
+            Ok(Command::SetMem(0x4000, 0x10))
+        );
+        assert!(parse_command("write-obj x3000 x3003").is_err());
+    }
+}
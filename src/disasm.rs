@@ -0,0 +1,391 @@
+// Disassembler turning single LC-3 words into assembly text.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::isa::{decode, decode_trap, sign_extend, Instruction, TrapCode};
+use crate::lint::reachable;
+
+const HALT: u16 = 0xF025;
+
+fn reg(n: u16) -> String {
+    format!("R{}", n & 0x7)
+}
+
+fn imm(value: u16) -> String {
+    format!("#{}", value as i16)
+}
+
+// PC-relative operand: the target's label if known, otherwise the offset
+// with the resolved target address as a comment
+fn pc_relative(pc: u16, offset: u16, symbols: &HashMap<u16, String>) -> String {
+    let target = pc.wrapping_add(1).wrapping_add(offset);
+    match symbols.get(&target) {
+        Some(label) => label.clone(),
+        None => format!("#{} ; -> x{:04X}", offset as i16, target),
+    }
+}
+
+pub fn mnemonic(op: Instruction) -> &'static str {
+    match op {
+        Instruction::BR => "BR",
+        Instruction::ADD => "ADD",
+        Instruction::LD => "LD",
+        Instruction::ST => "ST",
+        Instruction::JSR => "JSR",
+        Instruction::AND => "AND",
+        Instruction::LDR => "LDR",
+        Instruction::STR => "STR",
+        Instruction::RTI => "RTI",
+        Instruction::NOT => "NOT",
+        Instruction::LDI => "LDI",
+        Instruction::STI => "STI",
+        Instruction::JMP => "JMP",
+        Instruction::RES => "RES",
+        Instruction::LEA => "LEA",
+        Instruction::TRAP => "TRAP",
+    }
+}
+
+// render `instr`, located at address `pc`, as standard LC-3 assembly
+pub fn disassemble(instr: u16, pc: u16) -> String {
+    disassemble_with_symbols(instr, pc, &HashMap::new(), false)
+}
+
+// like `disassemble`, printing PC-relative targets as labels where possible;
+// `overflow_flag` reads BR with no n/z/p bits as that extension's BRv
+pub fn disassemble_with_symbols(
+    instr: u16,
+    pc: u16,
+    symbols: &HashMap<u16, String>,
+    overflow_flag: bool,
+) -> String {
+    let dr = (instr >> 9) & 0x7;
+    let sr1 = (instr >> 6) & 0x7;
+    let op = decode(instr >> 12);
+    let name = mnemonic(op);
+    match op {
+        Instruction::ADD | Instruction::AND => {
+            if (instr >> 5) & 0x1 == 1 {
+                let imm5 = sign_extend(instr & 0x1F, 5);
+                format!("{} {}, {}, {}", name, reg(dr), reg(sr1), imm(imm5))
+            } else {
+                format!("{} {}, {}, {}", name, reg(dr), reg(sr1), reg(instr))
+            }
+        }
+        Instruction::NOT => format!("{} {}, {}", name, reg(dr), reg(sr1)),
+        Instruction::BR => {
+            let offset = sign_extend(instr & 0x1FF, 9);
+            // no n/z/p bits never branches, except as the overflow flag
+            // extension's BRv
+            if dr == 0 {
+                if overflow_flag && offset != 0 {
+                    return format!("BRv {}", pc_relative(pc, offset, symbols));
+                }
+                return "NOP".to_string();
+            }
+            let n = if instr & 0x0800 != 0 { "n" } else { "" };
+            let z = if instr & 0x0400 != 0 { "z" } else { "" };
+            let p = if instr & 0x0200 != 0 { "p" } else { "" };
+            format!("BR{}{}{} {}", n, z, p, pc_relative(pc, offset, symbols))
+        }
+        Instruction::JMP => {
+            if sr1 == 7 {
+                "RET".to_string()
+            } else {
+                format!("JMP {}", reg(sr1))
+            }
+        }
+        Instruction::JSR => {
+            if (instr >> 11) & 1 == 1 {
+                let offset = sign_extend(instr & 0x7FF, 11);
+                format!("JSR {}", pc_relative(pc, offset, symbols))
+            } else {
+                format!("JSRR {}", reg(sr1))
+            }
+        }
+        Instruction::LD
+        | Instruction::LDI
+        | Instruction::LEA
+        | Instruction::ST
+        | Instruction::STI => {
+            let offset = sign_extend(instr & 0x1FF, 9);
+            format!("{} {}, {}", name, reg(dr), pc_relative(pc, offset, symbols))
+        }
+        Instruction::LDR | Instruction::STR => {
+            let offset = sign_extend(instr & 0x3F, 6);
+            format!("{} {}, {}, {}", name, reg(dr), reg(sr1), imm(offset))
+        }
+        // the standard service routines by their assembler aliases
+        Instruction::TRAP => match decode_trap(instr & 0xFF) {
+            Some(TrapCode::GETC) => "GETC".to_string(),
+            Some(TrapCode::OUT) => "OUT".to_string(),
+            Some(TrapCode::PUTS) => "PUTS".to_string(),
+            Some(TrapCode::IN) => "IN".to_string(),
+            Some(TrapCode::PUTSP) => "PUTSP".to_string(),
+            Some(TrapCode::HALT) => "HALT".to_string(),
+            None => format!("TRAP x{:02X}", instr & 0xFF),
+        },
+        Instruction::RTI | Instruction::RES => name.to_string(),
+    }
+}
+
+// registers an instruction reads and writes, for tracing
+pub fn registers_used(instr: u16) -> (Vec<u16>, Vec<u16>) {
+    let dr = (instr >> 9) & 0x7;
+    let sr1 = (instr >> 6) & 0x7;
+    match decode(instr >> 12) {
+        Instruction::ADD | Instruction::AND => {
+            if (instr >> 5) & 0x1 == 1 {
+                (vec![sr1], vec![dr])
+            } else {
+                (vec![sr1, instr & 0x7], vec![dr])
+            }
+        }
+        Instruction::NOT | Instruction::LDR => (vec![sr1], vec![dr]),
+        Instruction::LD | Instruction::LDI | Instruction::LEA => (vec![], vec![dr]),
+        Instruction::ST | Instruction::STI => (vec![dr], vec![]),
+        Instruction::STR => (vec![dr, sr1], vec![]),
+        Instruction::JMP => (vec![sr1], vec![]),
+        Instruction::JSR => {
+            if (instr >> 11) & 1 == 1 {
+                (vec![], vec![7])
+            } else {
+                (vec![sr1], vec![7])
+            }
+        }
+        Instruction::TRAP => (vec![0], vec![0]),
+        Instruction::BR | Instruction::RTI | Instruction::RES => (vec![], vec![]),
+    }
+}
+
+// one `xADDR  xWORD  MNEMONIC` line per word in `range`; with `data_as_hex`
+// everything after the first HALT is assumed to be data
+pub fn disassemble_range(
+    memory: &[u16],
+    range: Range<usize>,
+    data_as_hex: bool,
+    symbols: &HashMap<u16, String>,
+    overflow_flag: bool,
+) -> String {
+    let mut out = String::new();
+    let mut after_halt = false;
+    for addr in range {
+        let word = memory[addr];
+        out.push_str(&listing_line(
+            addr as u16,
+            word,
+            !(data_as_hex && after_halt),
+            symbols,
+            overflow_flag,
+        ));
+        after_halt |= word == HALT;
+    }
+    out
+}
+
+// like `disassemble_range`, but only the words reachable from `entry` by
+// following branches, calls and fall-through are shown as instructions;
+// everything else is data. `range` stays a usize range so it can end at
+// x10000
+pub fn disassemble_reachable(
+    memory: &[u16],
+    range: Range<usize>,
+    entry: u16,
+    symbols: &HashMap<u16, String>,
+    overflow_flag: bool,
+) -> String {
+    let code = reachable(memory, entry, range.clone(), overflow_flag);
+    range
+        .map(|addr| {
+            listing_line(
+                addr as u16,
+                memory[addr],
+                code.contains(&(addr as u16)),
+                symbols,
+                overflow_flag,
+            )
+        })
+        .collect()
+}
+
+fn listing_line(
+    addr: u16,
+    word: u16,
+    is_code: bool,
+    symbols: &HashMap<u16, String>,
+    overflow_flag: bool,
+) -> String {
+    let text = if is_code {
+        disassemble_with_symbols(word, addr, symbols, overflow_flag)
+    } else {
+        format!(".FILL x{:04X}", word)
+    };
+    let label = symbols.get(&addr).map_or("", |l| l.as_str());
+    format!("x{:04X}  x{:04X}  {:<8} {}\n", addr, word, label, text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disassembles_every_opcode() {
+        let cases: &[(u16, &str)] = &[
+            (0x0E0A, "BRnzp #10 ; -> x300B"),
+            (0x05FE, "BRz #-2 ; -> x2FFF"),
+            (0x0000, "NOP"),
+            (0x1265, "ADD R1, R1, #5"),
+            (0x1042, "ADD R0, R1, R2"),
+            (0x2402, "LD R2, #2 ; -> x3003"),
+            (0x3E00, "ST R7, #0 ; -> x3001"),
+            (0x4810, "JSR #16 ; -> x3011"),
+            (0x4080, "JSRR R2"),
+            (0x5020, "AND R0, R0, #0"),
+            (0x5A83, "AND R5, R2, R3"),
+            (0x64FC, "LDR R2, R3, #-4"),
+            (0x7381, "STR R1, R6, #1"),
+            (0x8000, "RTI"),
+            (0x927F, "NOT R1, R1"),
+            (0xA3FF, "LDI R1, #-1 ; -> x3000"),
+            (0xB601, "STI R3, #1 ; -> x3002"),
+            (0xC080, "JMP R2"),
+            (0xC1C0, "RET"),
+            (0xD000, "RES"),
+            (0xE1FD, "LEA R0, #-3 ; -> x2FFE"),
+            (0xF025, "HALT"),
+        ];
+        for &(instr, text) in cases {
+            assert_eq!(disassemble(instr, 0x3000), text, "x{:04X}", instr);
+        }
+    }
+
+    #[test]
+    fn brv_needs_the_overflow_flag_extension() {
+        let symbols = HashMap::new();
+        assert_eq!(disassemble(0x0003, 0x3000), "NOP");
+        assert_eq!(
+            disassemble_with_symbols(0x0003, 0x3000, &symbols, true),
+            "BRv #3 ; -> x3004"
+        );
+        // a word with no offset branches nowhere either way
+        assert_eq!(
+            disassemble_with_symbols(0x0000, 0x3000, &symbols, true),
+            "NOP"
+        );
+
+        // LD R0, #0 falls through to BRv #1 over a HALT
+        let mut memory = vec![0u16; 0x3004];
+        memory[0x3000..0x3004].copy_from_slice(&[0x2000, 0x0001, 0xF025, 0xF025]);
+        let listing = |overflow_flag| {
+            disassemble_reachable(&memory, 0x3000..0x3004, 0x3000, &symbols, overflow_flag)
+        };
+        assert!(listing(false).ends_with("x3003  xF025           .FILL xF025\n"));
+        assert!(listing(true).ends_with("x3003  xF025           HALT\n"));
+    }
+
+    #[test]
+    fn pc_relative_targets_wrap_around_memory() {
+        assert_eq!(disassemble(0x0E01, 0xFFFF), "BRnzp #1 ; -> x0001");
+    }
+
+    #[test]
+    fn disassemble_range_prints_address_word_and_text() {
+        let mut memory = vec![0u16; 0x3003];
+        memory[0x3000..0x3003].copy_from_slice(&[0x1265, 0xF025, 0x0048]);
+        let symbols = HashMap::new();
+        assert_eq!(
+            disassemble_range(&memory, 0x3000..0x3003, false, &symbols, false),
+            "x3000  x1265           ADD R1, R1, #5\n\
+             x3001  xF025           HALT\n\
+             x3002  x0048           NOP\n"
+        );
+        assert!(
+            disassemble_range(&memory, 0x3000..0x3003, true, &symbols, false)
+                .ends_with("x0048           .FILL x0048\n")
+        );
+    }
+
+    #[test]
+    fn registers_used_lists_reads_and_writes() {
+        assert_eq!(registers_used(0x1042), (vec![1, 2], vec![0])); // ADD R0, R1, R2
+        assert_eq!(registers_used(0x1265), (vec![1], vec![1])); // ADD R1, R1, #5
+        assert_eq!(registers_used(0x7381), (vec![1, 6], vec![])); // STR R1, R6, #1
+        assert_eq!(registers_used(0x4080), (vec![2], vec![7])); // JSRR R2
+        assert_eq!(registers_used(0x0E0A), (vec![], vec![])); // BRnzp
+    }
+
+    #[test]
+    fn labels_replace_pc_relative_targets() {
+        let symbols = HashMap::from([(0x3000, "LOOP".to_string())]);
+        assert_eq!(
+            disassemble_with_symbols(0x0FFE, 0x3001, &symbols, false),
+            "BRnzp LOOP"
+        );
+        assert_eq!(
+            disassemble_with_symbols(0xE1FD, 0x3002, &symbols, false),
+            "LEA R0, LOOP"
+        );
+        let memory = vec![0x0FFF; 0x3001];
+        assert_eq!(
+            disassemble_range(&memory, 0x3000..0x3001, false, &symbols, false),
+            "x3000  x0FFF  LOOP     BRnzp LOOP\n"
+        );
+    }
+
+    #[test]
+    fn traps_use_the_alias_mnemonics() {
+        let cases = [
+            (0xF020, "GETC"),
+            (0xF021, "OUT"),
+            (0xF022, "PUTS"),
+            (0xF023, "IN"),
+            (0xF024, "PUTSP"),
+            (0xF025, "HALT"),
+            (0xF026, "TRAP x26"),
+            (0xF000, "TRAP x00"),
+        ];
+        for (instr, text) in cases {
+            assert_eq!(disassemble(instr, 0x3000), text);
+        }
+    }
+
+    #[test]
+    fn reachable_disassembly_labels_embedded_data() {
+        // LD R0, VALUE; BRnzp SKIP; VALUE .FILL x1021; SKIP ADD R0, R0, #1;
+        // HALT
+        let mut memory = vec![0u16; 0x3005];
+        memory[0x3000..0x3005].copy_from_slice(&[0x2001, 0x0E01, 0x1021, 0x1021, 0xF025]);
+        let listing =
+            disassemble_reachable(&memory, 0x3000..0x3005, 0x3000, &HashMap::new(), false);
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[2].ends_with(".FILL x1021"), "{:?}", lines);
+        assert!(lines[3].ends_with("ADD R0, R0, #1"), "{:?}", lines);
+        // a linear listing would decode the same word as an ADD
+        let linear = disassemble_range(&memory, 0x3000..0x3005, true, &HashMap::new(), false);
+        assert!(linear.lines().nth(2).unwrap().ends_with("ADD R0, R0, #1"));
+    }
+
+    #[test]
+    fn reachable_disassembly_stops_at_register_jumps() {
+        // JMP R2 and whatever follows it is data
+        let mut memory = vec![0u16; 0x3002];
+        memory[0x3000..0x3002].copy_from_slice(&[0xC080, 0x1021]);
+        let listing =
+            disassemble_reachable(&memory, 0x3000..0x3002, 0x3000, &HashMap::new(), false);
+        assert!(listing.lines().next().unwrap().ends_with("JMP R2"));
+        assert!(listing.lines().nth(1).unwrap().ends_with(".FILL x1021"));
+    }
+
+    #[test]
+    fn reachable_disassembly_runs_to_the_end_of_memory() {
+        // ADD R0, R0, #1; HALT in the last two words
+        let mut memory = vec![0u16; 0x10000];
+        memory[0xFFFE..].copy_from_slice(&[0x1021, 0xF025]);
+        let listing =
+            disassemble_reachable(&memory, 0xFFFE..0x10000, 0xFFFE, &HashMap::new(), false);
+        assert!(listing.lines().next().unwrap().ends_with("ADD R0, R0, #1"));
+        assert!(listing.lines().nth(1).unwrap().ends_with("HALT"));
+    }
+}
@@ -0,0 +1,94 @@
+// Memory-mapped framebuffer: 128x124 pixels at xC000-xFDFF, one RGB555 word
+// per pixel (red in bits 14-10, green 9-5, blue 4-0), row by row.
+
+use crate::vm::Vm;
+
+pub const DISPLAY_START: u16 = 0xC000;
+pub const DISPLAY_WIDTH: usize = 128;
+pub const DISPLAY_HEIGHT: usize = 124;
+
+pub(crate) fn in_display(address: u16) -> bool {
+    let start = DISPLAY_START as usize;
+    (start..start + DISPLAY_WIDTH * DISPLAY_HEIGHT).contains(&(address as usize))
+}
+
+// 8-bit channels of an RGB555 pixel
+fn rgb(pixel: u16) -> (u8, u8, u8) {
+    let channel = |shift: u16| (((pixel >> shift) & 0x1F) * 255 / 31) as u8;
+    (channel(10), channel(5), channel(0))
+}
+
+impl Vm {
+    // the framebuffer contents; clears the flag `display_changed` reports
+    pub fn take_display(&mut self) -> &[u16] {
+        self.display_dirty = false;
+        let start = DISPLAY_START as usize;
+        &self.memory[start..start + DISPLAY_WIDTH * DISPLAY_HEIGHT]
+    }
+
+    // whether the program drew anything since the last `take_display`
+    pub fn display_changed(&self) -> bool {
+        self.display_dirty
+    }
+
+    // the framebuffer as ANSI true-colour text, two pixel rows per line
+    pub fn render_display(&mut self) -> String {
+        let pixels = self.take_display();
+        let mut out = String::new();
+        for rows in pixels.chunks(DISPLAY_WIDTH * 2) {
+            let (top, bottom) = rows.split_at(DISPLAY_WIDTH);
+            for (&upper, &lower) in top.iter().zip(bottom) {
+                let (r1, g1, b1) = rgb(upper);
+                let (r2, g2, b2) = rgb(lower);
+                out.push_str(&format!(
+                    "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m\u{2580}",
+                    r1, g1, b1, r2, g2, b2
+                ));
+            }
+            out.push_str("\x1b[0m\n");
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_display_returns_what_the_program_drew() {
+        let mut vm = Vm::new();
+        // LD R1, BASE; LD R0, RED; STR R0, R1, #0; STR R0, R1, #2; HALT
+        vm.memory[0x3000..0x3007]
+            .copy_from_slice(&[0x2204, 0x2004, 0x7040, 0x7042, 0xF025, 0xC000, 0x7C00]);
+        assert!(!vm.display_changed());
+        vm.set_input(Vec::new());
+        vm.run().unwrap();
+        assert!(vm.display_changed());
+        let pixels = vm.take_display();
+        assert_eq!(pixels.len(), DISPLAY_WIDTH * DISPLAY_HEIGHT);
+        assert_eq!(&pixels[..4], &[0x7C00, 0, 0x7C00, 0]);
+        assert!(!vm.display_changed());
+    }
+
+    #[test]
+    fn only_framebuffer_writes_mark_the_display_changed() {
+        let mut vm = Vm::new();
+        vm.mem_write(0xBFFF, 1);
+        vm.mem_write(0xFE00, 1);
+        assert!(!vm.display_changed());
+        vm.mem_write(0xFDFF, 1);
+        assert!(vm.display_changed());
+    }
+
+    #[test]
+    fn render_display_draws_two_pixel_rows_per_line() {
+        let mut vm = Vm::new();
+        vm.memory[DISPLAY_START as usize] = 0x7FFF;
+        vm.memory[DISPLAY_START as usize + DISPLAY_WIDTH] = 0x001F;
+        let text = vm.render_display();
+        assert_eq!(text.lines().count(), DISPLAY_HEIGHT / 2);
+        assert!(text.starts_with("\x1b[38;2;255;255;255m\x1b[48;2;0;0;255m\u{2580}"));
+        assert!(text.contains("\x1b[38;2;0;0;0m\x1b[48;2;0;0;0m\u{2580}\x1b[0m\n"));
+    }
+}
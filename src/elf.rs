@@ -0,0 +1,331 @@
+// ELF containers for LC-3 code, as emitted by experimental LLVM backends.
+// Every PT_LOAD segment is read as 16-bit words in the file's byte order
+// and placed at its virtual address, taken as a word address; the part of
+// a segment beyond its file contents is zeroed. 32- and 64-bit ELF files
+// of either byte order are accepted.
+
+use std::ops::Range;
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+
+use crate::loader::{Endian, LoadError};
+use crate::vm::{Vm, MEMORY_SIZE};
+
+const PT_LOAD: u32 = 1;
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    endian: Endian,
+}
+
+impl Reader<'_> {
+    fn field(&self, offset: usize, len: usize) -> Result<&[u8], LoadError> {
+        offset
+            .checked_add(len)
+            .and_then(|end| self.bytes.get(offset..end))
+            .ok_or(LoadError::Elf("file is truncated"))
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16, LoadError> {
+        Ok(self.endian.word(self.field(offset, 2)?))
+    }
+
+    fn u32(&self, offset: usize) -> Result<u64, LoadError> {
+        let field = self.field(offset, 4)?;
+        Ok(match self.endian {
+            Endian::Big => BigEndian::read_u32(field),
+            Endian::Little => LittleEndian::read_u32(field),
+        } as u64)
+    }
+
+    fn u64(&self, offset: usize) -> Result<u64, LoadError> {
+        let field = self.field(offset, 8)?;
+        Ok(match self.endian {
+            Endian::Big => BigEndian::read_u64(field),
+            Endian::Little => LittleEndian::read_u64(field),
+        })
+    }
+}
+
+impl Vm {
+    // load the segments of an ELF file and return its entry point and the
+    // memory ranges the segments occupy
+    pub fn load_elf(&mut self, bytes: &[u8]) -> Result<(u16, Vec<Range<usize>>), LoadError> {
+        if bytes.get(0..4) != Some(b"\x7FELF".as_slice()) {
+            return Err(LoadError::Elf("not an ELF file"));
+        }
+        let endian = match bytes.get(5) {
+            Some(1) => Endian::Little,
+            Some(2) => Endian::Big,
+            _ => return Err(LoadError::Elf("unknown byte order")),
+        };
+        let elf = Reader { bytes, endian };
+        let wide = match bytes.get(4) {
+            Some(1) => false,
+            Some(2) => true,
+            _ => return Err(LoadError::Elf("unknown ELF class")),
+        };
+        // (entry, phoff, phentsize, phnum) for the class
+        let (entry, phoff, phentsize, phnum) = if wide {
+            (
+                elf.u64(0x18)?,
+                elf.u64(0x20)?,
+                elf.u16(0x36)?,
+                elf.u16(0x38)?,
+            )
+        } else {
+            (
+                elf.u32(0x18)?,
+                elf.u32(0x1C)?,
+                elf.u16(0x2A)?,
+                elf.u16(0x2C)?,
+            )
+        };
+        let entry = u16::try_from(entry).map_err(|_| LoadError::Elf("entry point out of range"))?;
+
+        let mut segments = Vec::new();
+        for i in 0..phnum as u64 {
+            let header = (phentsize as u64)
+                .checked_mul(i)
+                .and_then(|at| at.checked_add(phoff))
+                .and_then(|at| usize::try_from(at).ok())
+                .ok_or(LoadError::Elf("program header offset out of range"))?;
+            // the header's fields are read from its own slice of the file
+            let header_size = if wide { 56 } else { 32 };
+            let ph = Reader {
+                bytes: elf.field(header, header_size)?,
+                endian,
+            };
+            if ph.u32(0)? != PT_LOAD as u64 {
+                continue;
+            }
+            let (offset, vaddr, filesz, memsz) = if wide {
+                (ph.u64(8)?, ph.u64(16)?, ph.u64(32)?, ph.u64(40)?)
+            } else {
+                (ph.u32(4)?, ph.u32(8)?, ph.u32(16)?, ph.u32(20)?)
+            };
+            if filesz % 2 != 0 {
+                return Err(LoadError::Elf(
+                    "segment size is not a whole number of words",
+                ));
+            }
+            if memsz < filesz {
+                return Err(LoadError::Elf(
+                    "segment is smaller in memory than in the file",
+                ));
+            }
+            // check the segment fits before allocating memsz worth of words
+            let end = vaddr
+                .checked_add(memsz / 2)
+                .filter(|&end| end <= MEMORY_SIZE as u64)
+                .ok_or(LoadError::OriginOutOfRange)?;
+            let offset =
+                usize::try_from(offset).map_err(|_| LoadError::Elf("file is truncated"))?;
+            let data = elf.field(offset, filesz as usize)?;
+            let mut words: Vec<u16> = data.chunks(2).map(|w| endian.word(w)).collect();
+            words.resize((end - vaddr) as usize, 0);
+            segments.push(self.load_words(vaddr as u16, &words)?);
+        }
+        Ok((entry, segments))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a segment: p_type, vaddr, the words in the file and memsz in words
+    type Segment<'a> = (u32, u16, &'a [u16], usize);
+
+    // an ELF file with the given class and byte order; the segment data
+    // follows the program headers
+    fn build(wide: bool, endian: Endian, entry: u16, segments: &[Segment]) -> Vec<u8> {
+        let put = |out: &mut Vec<u8>, value: u64, len: usize| {
+            let bytes = value.to_le_bytes();
+            match endian {
+                Endian::Little => out.extend(&bytes[..len]),
+                Endian::Big => out.extend(bytes[..len].iter().rev()),
+            }
+        };
+        // address-sized fields
+        let word = if wide { 8 } else { 4 };
+        let (ehsize, phentsize) = if wide { (64, 56) } else { (52, 32) };
+
+        let mut out = b"\x7FELF".to_vec();
+        out.push(if wide { 2 } else { 1 });
+        out.push(if endian == Endian::Little { 1 } else { 2 });
+        out.push(1);
+        out.resize(16, 0);
+        put(&mut out, 2, 2); // e_type
+        put(&mut out, 0, 2); // e_machine
+        put(&mut out, 1, 4); // e_version
+        put(&mut out, entry as u64, word);
+        put(&mut out, ehsize, word); // e_phoff
+        put(&mut out, 0, word); // e_shoff
+        put(&mut out, 0, 4); // e_flags
+        put(&mut out, ehsize, 2);
+        put(&mut out, phentsize, 2);
+        put(&mut out, segments.len() as u64, 2);
+        out.resize(ehsize as usize, 0);
+
+        let mut offset = ehsize + phentsize * segments.len() as u64;
+        for &(p_type, vaddr, words, memsz) in segments {
+            let filesz = 2 * words.len() as u64;
+            put(&mut out, p_type as u64, 4);
+            if wide {
+                put(&mut out, 5, 4); // p_flags
+            }
+            put(&mut out, offset, word);
+            put(&mut out, vaddr as u64, word); // p_vaddr
+            put(&mut out, vaddr as u64, word); // p_paddr
+            put(&mut out, filesz, word);
+            put(&mut out, 2 * memsz as u64, word);
+            if !wide {
+                put(&mut out, 5, 4); // p_flags
+            }
+            put(&mut out, 2, word); // p_align
+            offset += filesz;
+        }
+        for &(_, _, words, _) in segments {
+            for &w in words {
+                put(&mut out, w as u64, 2);
+            }
+        }
+        out
+    }
+
+    const HELLO: [u16; 6] = [0xE002, 0xF022, 0xF025, 0x0048, 0x0069, 0x0000];
+
+    #[test]
+    fn loads_the_handcrafted_fixture() {
+        let bytes = include_bytes!("../tests/fixtures/hello.elf");
+        assert_eq!(
+            bytes.as_slice(),
+            build(
+                false,
+                Endian::Little,
+                0x3000,
+                &[(PT_LOAD, 0x3000, &HELLO, 6)]
+            )
+        );
+        let mut vm = Vm::new();
+        let (entry, segments) = vm.load_elf(bytes).unwrap();
+        assert_eq!(entry, 0x3000);
+        assert_eq!(segments, vec![0x3000..0x3006]);
+        assert_eq!(vm.memory[0x3000..0x3006], HELLO);
+
+        vm.set_input(Vec::new());
+        vm.run().unwrap();
+        assert_eq!(vm.take_output(), b"HiHALT\n");
+    }
+
+    #[test]
+    fn loads_64_bit_big_endian_files_and_zero_fills() {
+        let bytes = build(
+            true,
+            Endian::Big,
+            0x0200,
+            &[
+                (PT_LOAD, 0x0200, &[0x1021, 0xF025], 2),
+                // not loadable
+                (4, 0x5000, &[0xAAAA], 1),
+                (PT_LOAD, 0x4000, &[7], 3),
+            ],
+        );
+        let mut vm = Vm::new();
+        vm.memory[0x4000..0x4004].fill(0xFFFF);
+        vm.memory[0x5000] = 1;
+        let (entry, segments) = vm.load_elf(&bytes).unwrap();
+        assert_eq!(entry, 0x0200);
+        assert_eq!(segments, vec![0x0200..0x0202, 0x4000..0x4003]);
+        assert_eq!(vm.memory[0x0200..0x0202], [0x1021, 0xF025]);
+        assert_eq!(vm.memory[0x4000..0x4004], [7, 0, 0, 0xFFFF]);
+        assert_eq!(vm.memory[0x5000], 1);
+    }
+
+    #[test]
+    fn rejects_files_that_are_not_elf() {
+        let mut vm = Vm::new();
+        let elf = build(
+            false,
+            Endian::Little,
+            0x3000,
+            &[(PT_LOAD, 0x3000, &HELLO, 6)],
+        );
+        let reason = |bytes: &[u8]| match Vm::new().load_elf(bytes) {
+            Err(LoadError::Elf(reason)) => reason,
+            other => panic!("{:?}", other.map(|_| ())),
+        };
+        assert_eq!(reason(b"\x30\x00\xF0\x25"), "not an ELF file");
+        assert_eq!(reason(&elf[..40]), "file is truncated");
+        assert_eq!(reason(&elf[..elf.len() - 1]), "file is truncated");
+        let mut odd = elf.clone();
+        odd[5] = 3;
+        assert_eq!(reason(&odd), "unknown byte order");
+        assert!(vm.load_elf(&elf).is_ok());
+    }
+
+    #[test]
+    fn rejects_malformed_headers_without_panicking() {
+        let reason = |bytes: &[u8]| match Vm::new().load_elf(bytes) {
+            Err(LoadError::Elf(reason)) => reason,
+            other => panic!("{:?}", other.map(|_| ())),
+        };
+        let elf = build(
+            true,
+            Endian::Little,
+            0x3000,
+            &[(PT_LOAD, 0x3000, &HELLO, 6)],
+        );
+        // e_phoff, where reading the first header used to overflow
+        let mut far = elf.clone();
+        far[0x20..0x28].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(reason(&far), "file is truncated");
+
+        // p_memsz is 40 bytes into the program header at 64
+        let mut short = elf.clone();
+        short[104..112].copy_from_slice(&2u64.to_le_bytes());
+        assert_eq!(
+            reason(&short),
+            "segment is smaller in memory than in the file"
+        );
+        let mut odd = elf.clone();
+        odd[96..104].copy_from_slice(&3u64.to_le_bytes());
+        assert_eq!(reason(&odd), "segment size is not a whole number of words");
+        // p_offset
+        let mut past = elf.clone();
+        past[72..80].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(reason(&past), "file is truncated");
+    }
+
+    #[test]
+    fn rejects_segments_past_the_end_of_memory_before_allocating() {
+        let mut elf = build(
+            true,
+            Endian::Little,
+            0x3000,
+            &[(PT_LOAD, 0x3000, &HELLO, 6)],
+        );
+        elf[104..112].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(
+            Vm::new().load_elf(&elf),
+            Err(LoadError::OriginOutOfRange)
+        ));
+        let mut top = build(
+            false,
+            Endian::Little,
+            0xFFFE,
+            &[(PT_LOAD, 0xFFFE, &[1, 2], 3)],
+        );
+        assert!(matches!(
+            Vm::new().load_elf(&top),
+            Err(LoadError::OriginOutOfRange)
+        ));
+        // p_vaddr
+        top[60..64].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            Vm::new().load_elf(&top),
+            Err(LoadError::OriginOutOfRange)
+        ));
+    }
+}
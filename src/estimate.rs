@@ -0,0 +1,156 @@
+// Static estimate of how many instructions a program runs before HALT, for
+// straight-line code. The walk follows fall-through, unconditional branches
+// and JSR/RET pairs from the current PC; anything whose path depends on
+// data (a conditional branch, JMP/JSRR through a register, a TRAP into an
+// installed service routine) gives up. Stores are assumed not to modify
+// the code being walked.
+
+use std::collections::HashSet;
+
+use crate::isa::{decode, decode_trap, Instruction, Register, TrapCode};
+use crate::lint::pc_relative;
+use crate::vm::{UnknownTrap, Vm};
+
+// longest walk attempted before giving up
+const ESTIMATE_LIMIT: u64 = 100_000;
+
+// instructions that load a destination register in bits 11-9
+fn writes_dr(op: Instruction) -> bool {
+    matches!(
+        op,
+        Instruction::ADD
+            | Instruction::AND
+            | Instruction::NOT
+            | Instruction::LD
+            | Instruction::LDR
+            | Instruction::LDI
+            | Instruction::LEA
+    )
+}
+
+impl Vm {
+    // the number of instructions until HALT, counting the HALT, or None
+    // when that cannot be shown without running the program
+    pub fn estimate_steps_to_halt(&self) -> Option<u64> {
+        let mut pc = self.reg[Register::RPC as usize];
+        // return addresses of the JSRs walked into
+        let mut calls: Vec<u16> = Vec::new();
+        let mut seen = HashSet::new();
+        for steps in 1..=ESTIMATE_LIMIT {
+            // the same place with the same calls pending is a loop
+            if !seen.insert((pc, calls.clone())) {
+                return None;
+            }
+            let instr = self.memory[pc as usize];
+            let op = decode(instr >> 12);
+            let next = pc.wrapping_add(1);
+            if writes_dr(op) && (instr >> 9) & 0x7 == Register::RR7 as u16 && !calls.is_empty() {
+                // the return address is no longer known
+                return None;
+            }
+            pc = match op {
+                Instruction::BR => match (instr >> 9) & 0x7 {
+                    // BRv when the overflow flag extension is on
+                    0 if !self.overflow_flag => next,
+                    0x7 => pc_relative(pc, instr, 9),
+                    _ => return None,
+                },
+                Instruction::JSR if (instr >> 11) & 1 == 1 => {
+                    calls.push(next);
+                    pc_relative(pc, instr, 11)
+                }
+                Instruction::JMP if (instr >> 6) & 0x7 == Register::RR7 as u16 => calls.pop()?,
+                Instruction::JSR | Instruction::JMP | Instruction::RTI | Instruction::RES => {
+                    return None
+                }
+                Instruction::TRAP => {
+                    let vector = instr & 0xFF;
+                    if self.memory[vector as usize] != 0 {
+                        return None;
+                    }
+                    match decode_trap(vector) {
+                        Some(TrapCode::HALT) => return Some(steps),
+                        Some(_) => next,
+                        None => match self.on_unknown_trap {
+                            UnknownTrap::Halt => return Some(steps),
+                            UnknownTrap::Ignore => next,
+                            UnknownTrap::Error => return None,
+                        },
+                    }
+                }
+                _ => next,
+            };
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn estimate(program: &[u16]) -> Option<u64> {
+        let mut vm = Vm::new();
+        vm.memory[0x3000..0x3000 + program.len()].copy_from_slice(program);
+        vm.estimate_steps_to_halt()
+    }
+
+    #[test]
+    fn counts_straight_line_code_exactly() {
+        // ADD; ADD; OUT; BRnzp #1; (skipped); HALT
+        let program = [0x1021, 0x1021, 0xF021, 0x0E01, 0x1021, 0xF025];
+        assert_eq!(estimate(&program), Some(5));
+
+        let mut vm = Vm::new();
+        vm.memory[0x3000..0x3006].copy_from_slice(&program);
+        vm.set_input(Vec::new());
+        vm.run().unwrap();
+        assert_eq!(vm.instr_count, 5);
+    }
+
+    #[test]
+    fn follows_subroutine_calls() {
+        // JSR #2; HALT; (unused); ADD R0, R0, #1; RET
+        assert_eq!(estimate(&[0x4802, 0xF025, 0x0000, 0x1021, 0xC1C0]), Some(4));
+    }
+
+    #[test]
+    fn gives_up_on_loops_and_data_dependent_branches() {
+        // BRnzp #-1
+        assert_eq!(estimate(&[0x0FFF]), None);
+        // ADD R0, R0, #1; BRp #-2; HALT
+        assert_eq!(estimate(&[0x1021, 0x03FE, 0xF025]), None);
+        // JMP R2
+        assert_eq!(estimate(&[0xC080]), None);
+    }
+
+    #[test]
+    fn gives_up_on_installed_trap_routines() {
+        let mut vm = Vm::new();
+        vm.memory[0x0025] = 0x1000;
+        vm.memory[0x3000] = 0xF025;
+        assert_eq!(vm.estimate_steps_to_halt(), None);
+    }
+
+    #[test]
+    fn unknown_traps_count_as_configured() {
+        let mut vm = Vm::new();
+        // TRAP x30; HALT
+        vm.memory[0x3000..0x3002].copy_from_slice(&[0xF030, 0xF025]);
+        assert_eq!(vm.estimate_steps_to_halt(), Some(1));
+        vm.on_unknown_trap = UnknownTrap::Ignore;
+        assert_eq!(vm.estimate_steps_to_halt(), Some(2));
+        vm.on_unknown_trap = UnknownTrap::Error;
+        assert_eq!(vm.estimate_steps_to_halt(), None);
+    }
+
+    #[test]
+    fn gives_up_on_brv_with_the_overflow_flag_on() {
+        let mut vm = Vm::new();
+        // BRv #1; HALT; HALT
+        vm.memory[0x3000..0x3003].copy_from_slice(&[0x0001, 0xF025, 0xF025]);
+        assert_eq!(vm.estimate_steps_to_halt(), Some(2));
+        vm.overflow_flag = true;
+        assert_eq!(vm.estimate_steps_to_halt(), None);
+    }
+}
@@ -0,0 +1,133 @@
+// Per-instruction execution log for autograders, built on the VM hook. Each
+// executed instruction becomes one line of JSON, for example
+//
+//   {"pc":12288,"instr":4129,"op":"ADD","disasm":"ADD R0, R0, #1","regs":[[0,1]],"mem":[]}
+//
+// `pc` and `instr` are the instruction's address and word, `op` its
+// mnemonic and `disasm` its disassembly without labels. `regs` holds a
+// [register, new value] pair for each of R0-R7 the instruction changed, in
+// register order, and `mem` an [address, new value] pair for each word it
+// wrote, in the order written. Numbers are decimal, and every field is
+// always present in this order, so logs of two runs can be compared line
+// by line. The words pushed when an interrupt is taken are listed with the
+// instruction before it.
+
+use std::io::{self, Write};
+
+use serde::Serialize;
+
+use crate::disasm;
+use crate::isa::decode;
+use crate::vm::HookEvent;
+
+#[derive(Serialize)]
+struct Record {
+    pc: u16,
+    instr: u16,
+    op: &'static str,
+    disasm: String,
+    regs: Vec<(u16, u16)>,
+    mem: Vec<(u16, u16)>,
+}
+
+pub struct ExecLog<W: Write> {
+    out: W,
+    // the instruction in progress; written once the next one is fetched
+    record: Option<Record>,
+}
+
+impl<W: Write> ExecLog<W> {
+    pub fn new(out: W) -> ExecLog<W> {
+        ExecLog { out, record: None }
+    }
+
+    // feed a hook event into the log
+    pub fn event(&mut self, event: &HookEvent) -> io::Result<()> {
+        match *event {
+            HookEvent::InstructionFetched { pc, instr } => {
+                self.write_record()?;
+                self.record = Some(Record {
+                    pc,
+                    instr,
+                    op: disasm::mnemonic(decode(instr >> 12)),
+                    disasm: disasm::disassemble(instr, pc),
+                    regs: Vec::new(),
+                    mem: Vec::new(),
+                });
+            }
+            HookEvent::RegWrite { reg, value } => {
+                if let Some(record) = &mut self.record {
+                    record.regs.push((reg, value));
+                }
+            }
+            HookEvent::MemWrite { addr, value } => {
+                if let Some(record) = &mut self.record {
+                    record.mem.push((addr, value));
+                }
+            }
+            HookEvent::TrapExecuted { .. } => {}
+        }
+        Ok(())
+    }
+
+    // write the last instruction's line and flush
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.write_record()?;
+        self.out.flush()
+    }
+
+    fn write_record(&mut self) -> io::Result<()> {
+        if let Some(record) = self.record.take() {
+            serde_json::to_writer(&mut self.out, &record)?;
+            self.out.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+}
+
+// a log still attached to a VM is completed when the hook is dropped
+impl<W: Write> Drop for ExecLog<W> {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            eprintln!("execution log: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_instruction_becomes_a_line_with_its_changes() {
+        let mut out = Vec::new();
+        {
+            let mut log = ExecLog::new(&mut out);
+            let events = [
+                HookEvent::InstructionFetched {
+                    pc: 0x3000,
+                    instr: 0x1021,
+                },
+                HookEvent::RegWrite { reg: 0, value: 1 },
+                HookEvent::InstructionFetched {
+                    pc: 0x3001,
+                    instr: 0x3002,
+                },
+                HookEvent::MemWrite {
+                    addr: 0x3004,
+                    value: 1,
+                },
+            ];
+            for event in &events {
+                log.event(event).unwrap();
+            }
+        }
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"pc\":12288,\"instr\":4129,\"op\":\"ADD\",\"disasm\":\"ADD R0, R0, #1\",\
+             \"regs\":[[0,1]],\"mem\":[]}\n\
+             {\"pc\":12289,\"instr\":12290,\"op\":\"ST\",\"disasm\":\"ST R0, #2 ; -> x3004\",\
+             \"regs\":[],\"mem\":[[12292,1]]}\n"
+        );
+    }
+}
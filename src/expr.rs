@@ -0,0 +1,216 @@
+// Breakpoint conditions: a single comparison such as `R0 == x0005` or
+// `mem[x4000] < #0` between registers, memory words and literals. Ordering
+// comparisons treat values as signed.
+//
+// Watch expressions add and subtract the same operands, written with
+// spaces around the operators: `R0 + mem[x4000] - #1`.
+
+use crate::debugger::{parse_register, parse_value};
+use crate::vm::Vm;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operand {
+    // register index, 8 for PC
+    Reg(usize),
+    Mem(u16),
+    Value(u16),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Condition {
+    pub left: Operand,
+    pub op: Comparison,
+    pub right: Operand,
+}
+
+fn parse_operand(s: &str) -> Result<Operand, String> {
+    let s = s.trim();
+    let lower = s.to_ascii_lowercase();
+    if let Some(inner) = lower.strip_prefix("mem[") {
+        let addr = inner
+            .strip_suffix(']')
+            .ok_or(format!("missing `]` in `{}`", s))?;
+        return Ok(Operand::Mem(parse_value(addr.trim())?));
+    }
+    if let Ok(r) = parse_register(s) {
+        return Ok(Operand::Reg(r));
+    }
+    parse_value(s).map(Operand::Value)
+}
+
+pub fn parse_condition(s: &str) -> Result<Condition, String> {
+    // two-character operators first so `<=` is not read as `<`
+    let operators = [
+        ("==", Comparison::Eq),
+        ("!=", Comparison::Ne),
+        ("<=", Comparison::Le),
+        (">=", Comparison::Ge),
+        ("<", Comparison::Lt),
+        (">", Comparison::Gt),
+    ];
+    for (token, op) in operators {
+        if let Some((left, right)) = s.split_once(token) {
+            return Ok(Condition {
+                left: parse_operand(left)?,
+                op,
+                right: parse_operand(right)?,
+            });
+        }
+    }
+    Err(format!("expected a comparison in `{}`", s))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sign {
+    Plus,
+    Minus,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expr {
+    pub terms: Vec<(Sign, Operand)>,
+}
+
+pub fn parse_expr(s: &str) -> Result<Expr, String> {
+    let mut terms = Vec::new();
+    let mut sign = Some(Sign::Plus);
+    for token in s.split_whitespace() {
+        match (sign, token) {
+            (None, "+") => sign = Some(Sign::Plus),
+            (None, "-") => sign = Some(Sign::Minus),
+            (None, _) => return Err(format!("expected `+` or `-` before `{}`", token)),
+            (Some(current), _) => {
+                terms.push((current, parse_operand(token)?));
+                sign = None;
+            }
+        }
+    }
+    if terms.is_empty() || sign.is_some() {
+        return Err(format!("incomplete expression `{}`", s));
+    }
+    Ok(Expr { terms })
+}
+
+impl Operand {
+    fn value(self, vm: &Vm) -> u16 {
+        match self {
+            Operand::Reg(r) => vm.reg[r],
+            Operand::Mem(addr) => vm.memory[addr as usize],
+            Operand::Value(v) => v,
+        }
+    }
+}
+
+impl Condition {
+    pub fn holds(&self, vm: &Vm) -> bool {
+        let left = self.left.value(vm);
+        let right = self.right.value(vm);
+        let (sl, sr) = (left as i16, right as i16);
+        match self.op {
+            Comparison::Eq => left == right,
+            Comparison::Ne => left != right,
+            Comparison::Lt => sl < sr,
+            Comparison::Le => sl <= sr,
+            Comparison::Gt => sl > sr,
+            Comparison::Ge => sl >= sr,
+        }
+    }
+}
+
+impl Expr {
+    // the sum with 16-bit wrapping
+    pub fn eval(&self, vm: &Vm) -> u16 {
+        self.terms
+            .iter()
+            .fold(0u16, |total, &(sign, operand)| match sign {
+                Sign::Plus => total.wrapping_add(operand.value(vm)),
+                Sign::Minus => total.wrapping_sub(operand.value(vm)),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_operands_and_operators() {
+        assert_eq!(
+            parse_condition("R0 == x0005"),
+            Ok(Condition {
+                left: Operand::Reg(0),
+                op: Comparison::Eq,
+                right: Operand::Value(5),
+            })
+        );
+        assert_eq!(
+            parse_condition("mem[x4000]<=#-1"),
+            Ok(Condition {
+                left: Operand::Mem(0x4000),
+                op: Comparison::Le,
+                right: Operand::Value(0xFFFF),
+            })
+        );
+        let ops = [
+            ("!=", Comparison::Ne),
+            ("<", Comparison::Lt),
+            (">", Comparison::Gt),
+            (">=", Comparison::Ge),
+        ];
+        for (token, op) in ops {
+            let condition = parse_condition(&format!("pc {} r7", token)).unwrap();
+            assert_eq!(condition.op, op);
+            assert_eq!(condition.left, Operand::Reg(8));
+            assert_eq!(condition.right, Operand::Reg(7));
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_conditions() {
+        assert!(parse_condition("R0").is_err());
+        assert!(parse_condition("R0 == ").is_err());
+        assert!(parse_condition("mem[x4000 == 1").is_err());
+        assert!(parse_condition("R9 == 1").is_err());
+    }
+
+    #[test]
+    fn ordering_comparisons_are_signed() {
+        let mut vm = Vm::new();
+        vm.reg[1] = 0xFFFF;
+        vm.memory[0x4000] = 3;
+        let holds = |s: &str, vm: &Vm| parse_condition(s).unwrap().holds(vm);
+        assert!(holds("R1 < 0", &vm));
+        assert!(holds("R1 == xFFFF", &vm));
+        assert!(holds("mem[x4000] > R1", &vm));
+        assert!(holds("mem[x4000] >= 3", &vm));
+        assert!(!holds("mem[x4000] != 3", &vm));
+    }
+
+    #[test]
+    fn watch_expressions_add_and_subtract_operands() {
+        let mut vm = Vm::new();
+        vm.reg[0] = 3;
+        vm.memory[0x4000] = 0xFFFF;
+        assert_eq!(parse_expr("R0").unwrap().eval(&vm), 3);
+        assert_eq!(parse_expr("R0 + mem[x4000]").unwrap().eval(&vm), 2);
+        assert_eq!(parse_expr("R0 - #5 + x10").unwrap().eval(&vm), 14);
+        assert_eq!(parse_expr("#0 - R0").unwrap().eval(&vm), 0xFFFD);
+    }
+
+    #[test]
+    fn rejects_malformed_watch_expressions() {
+        for s in ["", "R0 +", "+ R0", "R0 R1", "R0 * R1", "R9"] {
+            assert!(parse_expr(s).is_err(), "{:?}", s);
+        }
+    }
+}
@@ -0,0 +1,216 @@
+// C ABI for embedding the VM in C/C++ front ends. Build a shared or static
+// library with
+//
+//     cargo rustc --lib --release --features ffi --crate-type cdylib
+//
+// Every function returns a status code instead of panicking: 0 or more on
+// success, one of the negative LC3_ERR_* values otherwise. The machine runs
+// with headless I/O; feed keys with `lc3_set_input` and collect what the
+// program prints with `lc3_read_output`.
+//
+// Safety contract for the caller: a handle must come from `lc3_new` and not
+// be used after `lc3_free`, and a buffer must hold the number of bytes
+// passed with it. Null pointers are rejected with LC3_ERR_NULL.
+#![allow(clippy::missing_safety_doc)]
+
+use std::os::raw::c_int;
+
+use crate::isa::Register;
+use crate::loader::LoadOptions;
+use crate::vm::{StepResult, Vm};
+
+pub const LC3_OK: c_int = 0;
+pub const LC3_HALTED: c_int = 1;
+// stopped at a breakpoint, watchpoint or trap breakpoint
+pub const LC3_STOPPED: c_int = 2;
+pub const LC3_ERR_NULL: c_int = -1;
+pub const LC3_ERR_ARG: c_int = -2;
+pub const LC3_ERR_LOAD: c_int = -3;
+// the instruction faulted (illegal opcode, privilege or access violation,
+// input exhausted, unknown trap vector)
+pub const LC3_ERR_FAULT: c_int = -4;
+
+// the handle C code holds: the machine plus output not yet read
+pub struct Lc3 {
+    vm: Box<Vm>,
+    output: Vec<u8>,
+}
+
+// a fresh machine; free it with `lc3_free`
+#[no_mangle]
+pub extern "C" fn lc3_new() -> *mut Lc3 {
+    let mut vm = Box::new(Vm::new());
+    vm.set_input(Vec::new());
+    Box::into_raw(Box::new(Lc3 {
+        vm,
+        output: Vec::new(),
+    }))
+}
+
+// free a handle; null is ignored
+#[no_mangle]
+pub unsafe extern "C" fn lc3_free(vm: *mut Lc3) {
+    if !vm.is_null() {
+        drop(Box::from_raw(vm));
+    }
+}
+
+// load an .obj image and start at its origin
+#[no_mangle]
+pub unsafe extern "C" fn lc3_load(vm: *mut Lc3, bytes: *const u8, len: usize) -> c_int {
+    let (Some(lc3), false) = (vm.as_mut(), bytes.is_null()) else {
+        return LC3_ERR_NULL;
+    };
+    let mut image = std::slice::from_raw_parts(bytes, len);
+    match lc3.vm.read_image_file(&mut image, LoadOptions::default()) {
+        Ok(range) => {
+            lc3.vm.reg[Register::RPC as usize] = range.start as u16;
+            LC3_OK
+        }
+        Err(_) => LC3_ERR_LOAD,
+    }
+}
+
+// execute one instruction
+#[no_mangle]
+pub unsafe extern "C" fn lc3_step(vm: *mut Lc3) -> c_int {
+    let Some(lc3) = vm.as_mut() else {
+        return LC3_ERR_NULL;
+    };
+    let result = lc3.vm.step();
+    lc3.output.extend(lc3.vm.take_output());
+    match result {
+        Ok(StepResult::Continued | StepResult::Trap(_)) => LC3_OK,
+        Ok(StepResult::Halted) => LC3_HALTED,
+        Ok(_) => LC3_STOPPED,
+        Err(_) => LC3_ERR_FAULT,
+    }
+}
+
+// register `index`: 0-7 for R0-R7, 8 for the PC, 9 for the PSR
+#[no_mangle]
+pub unsafe extern "C" fn lc3_reg(vm: *const Lc3, index: c_int, out: *mut u16) -> c_int {
+    let (Some(lc3), Some(out)) = (vm.as_ref(), out.as_mut()) else {
+        return LC3_ERR_NULL;
+    };
+    *out = match index {
+        0..=8 => lc3.vm.reg[index as usize],
+        9 => lc3.vm.psr(),
+        _ => return LC3_ERR_ARG,
+    };
+    LC3_OK
+}
+
+// the word at `addr`, without the side effects a program's read of a
+// device register has
+#[no_mangle]
+pub unsafe extern "C" fn lc3_mem_read(vm: *const Lc3, addr: u16, out: *mut u16) -> c_int {
+    let (Some(lc3), Some(out)) = (vm.as_ref(), out.as_mut()) else {
+        return LC3_ERR_NULL;
+    };
+    *out = lc3.vm.memory[addr as usize];
+    LC3_OK
+}
+
+// replace the pending keyboard input
+#[no_mangle]
+pub unsafe extern "C" fn lc3_set_input(vm: *mut Lc3, bytes: *const u8, len: usize) -> c_int {
+    let (Some(lc3), false) = (vm.as_mut(), bytes.is_null()) else {
+        return LC3_ERR_NULL;
+    };
+    lc3.vm
+        .set_input(std::slice::from_raw_parts(bytes, len).to_vec());
+    LC3_OK
+}
+
+// copy up to `cap` bytes of program output into `buf` and return how many
+// were copied; the rest stays for the next call
+#[no_mangle]
+pub unsafe extern "C" fn lc3_read_output(vm: *mut Lc3, buf: *mut u8, cap: usize) -> c_int {
+    let (Some(lc3), false) = (vm.as_mut(), buf.is_null()) else {
+        return LC3_ERR_NULL;
+    };
+    let n = cap.min(lc3.output.len()).min(c_int::MAX as usize);
+    std::ptr::copy_nonoverlapping(lc3.output.as_ptr(), buf, n);
+    lc3.output.drain(..n);
+    n as c_int
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // LEA R0, #2; PUTS; HALT; "Hi"
+    const HELLO: [u8; 14] = [
+        0x30, 0x00, 0xE0, 0x02, 0xF0, 0x22, 0xF0, 0x25, 0x00, 0x48, 0x00, 0x69, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn steps_a_loaded_program_through_the_c_abi() {
+        unsafe {
+            let vm = lc3_new();
+            assert_eq!(lc3_load(vm, HELLO.as_ptr(), HELLO.len()), LC3_OK);
+            let mut word = 0;
+            assert_eq!(lc3_reg(vm, 8, &mut word), LC3_OK);
+            assert_eq!(word, 0x3000);
+            assert_eq!(lc3_mem_read(vm, 0x3003, &mut word), LC3_OK);
+            assert_eq!(word, 0x0048);
+
+            assert_eq!(lc3_step(vm), LC3_OK);
+            assert_eq!(lc3_reg(vm, 0, &mut word), LC3_OK);
+            assert_eq!(word, 0x3003);
+            assert_eq!(lc3_step(vm), LC3_OK);
+            assert_eq!(lc3_step(vm), LC3_HALTED);
+            assert_eq!(lc3_step(vm), LC3_HALTED);
+
+            // output comes out in pieces no bigger than the buffer
+            let mut buf = [0u8; 4];
+            assert_eq!(lc3_read_output(vm, buf.as_mut_ptr(), buf.len()), 4);
+            assert_eq!(&buf, b"HiHA");
+            assert_eq!(lc3_read_output(vm, buf.as_mut_ptr(), buf.len()), 3);
+            assert_eq!(&buf[..3], b"LT\n");
+            assert_eq!(lc3_read_output(vm, buf.as_mut_ptr(), buf.len()), 0);
+            lc3_free(vm);
+        }
+    }
+
+    #[test]
+    fn feeds_input_to_getc() {
+        unsafe {
+            let vm = lc3_new();
+            let image = [0x30, 0x00, 0xF0, 0x20, 0xF0, 0x25]; // GETC; HALT
+            assert_eq!(lc3_load(vm, image.as_ptr(), image.len()), LC3_OK);
+            assert_eq!(lc3_set_input(vm, b"k".as_ptr(), 1), LC3_OK);
+            assert_eq!(lc3_step(vm), LC3_OK);
+            let mut r0 = 0;
+            lc3_reg(vm, 0, &mut r0);
+            assert_eq!(r0, b'k' as u16);
+            lc3_free(vm);
+        }
+    }
+
+    #[test]
+    fn reports_bad_arguments_with_status_codes() {
+        unsafe {
+            let null = std::ptr::null_mut();
+            let mut word = 0;
+            assert_eq!(lc3_step(null), LC3_ERR_NULL);
+            assert_eq!(lc3_reg(null, 0, &mut word), LC3_ERR_NULL);
+            assert_eq!(lc3_load(null, HELLO.as_ptr(), HELLO.len()), LC3_ERR_NULL);
+            lc3_free(null);
+
+            let vm = lc3_new();
+            assert_eq!(lc3_load(vm, std::ptr::null(), 0), LC3_ERR_NULL);
+            assert_eq!(lc3_load(vm, HELLO.as_ptr(), 3), LC3_ERR_LOAD);
+            assert_eq!(lc3_reg(vm, 10, &mut word), LC3_ERR_ARG);
+            assert_eq!(lc3_reg(vm, -1, &mut word), LC3_ERR_ARG);
+            assert_eq!(lc3_mem_read(vm, 0, std::ptr::null_mut()), LC3_ERR_NULL);
+
+            // an illegal opcode faults instead of panicking
+            let res = [0x30, 0x00, 0xD0, 0x00];
+            assert_eq!(lc3_load(vm, res.as_ptr(), res.len()), LC3_OK);
+            assert_eq!(lc3_step(vm), LC3_ERR_FAULT);
+            lc3_free(vm);
+        }
+    }
+}
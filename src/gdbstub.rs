@@ -0,0 +1,216 @@
+// Minimal GDB remote serial protocol stub. LC-3 memory is word addressed,
+// so addresses and lengths in `m`/`M` packets count words and every word
+// or register is sent as four big-endian hex digits. The register file is
+// R0-R7, PC and PSR.
+
+use std::io::{self, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::isa::Register;
+use crate::vm::{StepResult, Vm, VmError};
+
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Connection {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0u8];
+        match self.reader.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    // next `$data#cs` packet, acknowledged; None when the client hangs up
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'$') => break,
+                // acks and interrupt requests outside a packet
+                Some(_) => {}
+            }
+        }
+        let mut data = Vec::new();
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'#') => break,
+                Some(b) => data.push(b),
+            }
+        }
+        let mut checksum = [0u8; 2];
+        self.reader.read_exact(&mut checksum)?;
+        let expected = std::str::from_utf8(&checksum)
+            .ok()
+            .and_then(|s| u8::from_str_radix(s, 16).ok());
+        let actual = data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        if expected != Some(actual) {
+            self.writer.write_all(b"-")?;
+            return self.read_packet();
+        }
+        self.writer.write_all(b"+")?;
+        Ok(Some(String::from_utf8_lossy(&data).into_owned()))
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        write!(self.writer, "${}#{:02x}", data, checksum)?;
+        self.writer.flush()?;
+        // wait for the ack, resending on `-`
+        loop {
+            match self.read_byte()? {
+                Some(b'+') | None => return Ok(()),
+                Some(b'-') => return self.send(data),
+                Some(_) => {}
+            }
+        }
+    }
+}
+
+fn parse_hex(s: &str) -> Option<u16> {
+    u16::from_str_radix(s, 16).ok()
+}
+
+fn parse_words(s: &str) -> Option<Vec<u16>> {
+    if !s.len().is_multiple_of(4) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(4)
+        .map(|i| parse_hex(s.get(i..i + 4)?))
+        .collect()
+}
+
+// `addr,len` as sent by `m`, `M` and `Z0`
+fn parse_addr_len(s: &str) -> Option<(u16, u16)> {
+    let (addr, len) = s.split_once(',')?;
+    Some((parse_hex(addr)?, parse_hex(len)?))
+}
+
+fn stop_reply(vm: &Vm, result: Result<StepResult, VmError>) -> String {
+    match result {
+        Err(_) => format!("S{:02x}", SIGILL),
+        Ok(StepResult::Halted) => "W00".to_string(),
+        Ok(_) if !vm.is_running() => "W00".to_string(),
+        Ok(_) => format!("S{:02x}", SIGTRAP),
+    }
+}
+
+fn read_registers(vm: &Vm) -> String {
+    let mut out = String::new();
+    for r in 0..8 {
+        out.push_str(&format!("{:04x}", vm.reg[r]));
+    }
+    out.push_str(&format!("{:04x}", vm.reg[Register::RPC as usize]));
+    out.push_str(&format!("{:04x}", vm.psr()));
+    out
+}
+
+fn write_registers(vm: &mut Vm, data: &str) -> bool {
+    match parse_words(data) {
+        Some(words) if words.len() == 10 => {
+            vm.reg[..8].copy_from_slice(&words[..8]);
+            vm.reg[Register::RPC as usize] = words[8];
+            vm.psr = words[9];
+            true
+        }
+        _ => false,
+    }
+}
+
+// answer one packet; None means the session is over
+fn handle(vm: &mut Vm, last_stop: &mut String, packet: &str) -> Option<String> {
+    let error = || "E01".to_string();
+    let (kind, args) = packet.split_at(packet.len().min(1));
+    let reply = match kind {
+        "?" => last_stop.clone(),
+        "g" => read_registers(vm),
+        "G" => {
+            if write_registers(vm, args) {
+                "OK".to_string()
+            } else {
+                error()
+            }
+        }
+        "m" => match parse_addr_len(args) {
+            Some((addr, len)) => (0..len)
+                .map(|i| format!("{:04x}", vm.memory[addr.wrapping_add(i) as usize]))
+                .collect(),
+            None => error(),
+        },
+        "M" => {
+            let words = args
+                .split_once(':')
+                .and_then(|(range, data)| Some((parse_addr_len(range)?, parse_words(data)?)));
+            match words {
+                Some(((addr, len), words)) if words.len() == len as usize => {
+                    for (i, word) in words.into_iter().enumerate() {
+                        vm.mem_write(addr.wrapping_add(i as u16), word);
+                    }
+                    "OK".to_string()
+                }
+                _ => error(),
+            }
+        }
+        "s" => {
+            let result = vm.step();
+            *last_stop = stop_reply(vm, result);
+            last_stop.clone()
+        }
+        "c" => {
+            let result = vm.run();
+            *last_stop = stop_reply(vm, result);
+            last_stop.clone()
+        }
+        "Z" | "z" if args.starts_with("0,") => match parse_addr_len(&args[2..]) {
+            Some((addr, _)) => {
+                if kind == "Z" {
+                    vm.add_breakpoint(addr);
+                } else {
+                    vm.remove_breakpoint(addr);
+                }
+                "OK".to_string()
+            }
+            None => error(),
+        },
+        "k" | "D" => return None,
+        // unsupported packets get an empty reply
+        _ => String::new(),
+    };
+    Some(reply)
+}
+
+// serve a single client on `stream` until it detaches or disconnects
+pub fn serve_connection(vm: &mut Vm, stream: TcpStream) -> io::Result<()> {
+    let mut conn = Connection {
+        reader: BufReader::new(stream.try_clone()?),
+        writer: stream,
+    };
+    let mut last_stop = format!("S{:02x}", SIGTRAP);
+    while let Some(packet) = conn.read_packet()? {
+        match handle(vm, &mut last_stop, &packet) {
+            Some(reply) => conn.send(&reply)?,
+            None => {
+                if packet == "D" {
+                    conn.send("OK")?;
+                }
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+// wait for a debugger on `port` and serve it
+pub fn serve(vm: &mut Vm, port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    eprintln!("waiting for gdb on port {}", port);
+    let (stream, _) = listener.accept()?;
+    serve_connection(vm, stream)
+}
@@ -0,0 +1,209 @@
+// LC-3 instruction set definitions and instruction decoding.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Register {
+    RR0 = 0,
+    RR1,
+    RR2,
+    RR3,
+    RR4,
+    RR5,
+    RR6,
+    RR7,
+    RPC,
+    RCOUNT,
+}
+
+// instructions
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Instruction {
+    BR = 0,
+    // branch
+    ADD,
+    // add
+    LD,
+    // load
+    ST,
+    // store
+    JSR,
+    // jump register
+    AND,
+    // bitwise and
+    LDR,
+    // load register
+    STR,
+    // store register
+    RTI,
+    // unused
+    NOT,
+    // bitwise not
+    LDI,
+    // load indirect
+    STI,
+    // store indirect
+    JMP,
+    // jump
+    RES,
+    // reserved (unused)
+    LEA,
+    // load effective address
+    TRAP, // execute trap
+}
+
+// condition codes, kept one-hot in bits 2-0 of the PSR (N, Z, P) in the
+// same order as the n/z/p bits (11-9) of a BR instruction, so BR can test
+// them with a shift and a plain AND
+pub enum ConditionFlag {
+    POS = 1 << 0,
+    // P
+    ZRO = 1 << 1,
+    // Z
+    NEG = 1 << 2, // N
+}
+
+// trap
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrapCode {
+    GETC = 0x20,
+    OUT = 0x21,
+    PUTS = 0x22,
+    IN = 0x23,
+    PUTSP = 0x24,
+    HALT = 0x25,
+}
+
+// exception and interrupt vectors, relative to the interrupt vector table
+pub enum Exception {
+    PrivilegeViolation = 0x00,
+    IllegalOpcode = 0x01,
+    AccessViolation = 0x02,
+}
+
+pub const KEYBOARD_VECTOR: u16 = 0x80;
+pub const KEYBOARD_PRIORITY: u16 = 4;
+
+// KBSR bits: a key is waiting in KBDR / raise an interrupt when one arrives
+pub const KBSR_READY: u16 = 1 << 15;
+pub const KBSR_INTERRUPT_ENABLE: u16 = 1 << 14;
+
+pub enum MemoryMappedRegister {
+    KBSR = 0xFE00, // keyboard status
+    KBDR = 0xFE02, // keyboard data
+    TMR = 0xFE08,  // instructions executed, wrapping at 16 bits
+    RNG = 0xFE0A,  // next pseudo-random value (xFE06 is the display data register)
+    MCR = 0xFFFE,  // machine control, bit 15 is the clock enable
+}
+
+pub fn decode(op: u16) -> Instruction {
+    match op & 0xF {
+        0 => Instruction::BR,
+        1 => Instruction::ADD,
+        2 => Instruction::LD,
+        3 => Instruction::ST,
+        4 => Instruction::JSR,
+        5 => Instruction::AND,
+        6 => Instruction::LDR,
+        7 => Instruction::STR,
+        8 => Instruction::RTI,
+        9 => Instruction::NOT,
+        10 => Instruction::LDI,
+        11 => Instruction::STI,
+        12 => Instruction::JMP,
+        13 => Instruction::RES,
+        14 => Instruction::LEA,
+        _ => Instruction::TRAP,
+    }
+}
+
+pub fn decode_trap(code: u16) -> Option<TrapCode> {
+    match code {
+        0x20 => Some(TrapCode::GETC),
+        0x21 => Some(TrapCode::OUT),
+        0x22 => Some(TrapCode::PUTS),
+        0x23 => Some(TrapCode::IN),
+        0x24 => Some(TrapCode::PUTSP),
+        0x25 => Some(TrapCode::HALT),
+        _ => None,
+    }
+}
+
+pub fn sign_extend(x: u16, bit_count: u16) -> u16 {
+    if (x >> (bit_count - 1)) & 1 == 1 {
+        return x | (0xFFFF << bit_count);
+    }
+    x
+}
+
+// cycles charged per instruction, indexed by opcode; every memory access
+// beyond the fetch costs one extra cycle
+pub const CYCLE_COSTS: [u8; 16] = [
+    1, // BR
+    1, // ADD
+    2, // LD
+    2, // ST
+    1, // JSR
+    1, // AND
+    2, // LDR
+    2, // STR
+    3, // RTI
+    1, // NOT
+    3, // LDI
+    3, // STI
+    1, // JMP
+    1, // RES
+    1, // LEA
+    2, // TRAP
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_extend_fills_negative_values() {
+        assert_eq!(sign_extend(0x1F, 5), 0xFFFF);
+        assert_eq!(sign_extend(0x10, 5), 0xFFF0);
+        assert_eq!(sign_extend(0x1FF, 9), 0xFFFF);
+        assert_eq!(sign_extend(0x100, 9), 0xFF00);
+        assert_eq!(sign_extend(0x400, 11), 0xFC00);
+    }
+
+    #[test]
+    fn sign_extend_keeps_positive_values() {
+        assert_eq!(sign_extend(0x0F, 5), 0x000F);
+        assert_eq!(sign_extend(0x0FF, 9), 0x00FF);
+        assert_eq!(sign_extend(0x3FF, 11), 0x03FF);
+    }
+
+    #[test]
+    fn decode_maps_every_opcode_nibble() {
+        let expected = [
+            Instruction::BR,
+            Instruction::ADD,
+            Instruction::LD,
+            Instruction::ST,
+            Instruction::JSR,
+            Instruction::AND,
+            Instruction::LDR,
+            Instruction::STR,
+            Instruction::RTI,
+            Instruction::NOT,
+            Instruction::LDI,
+            Instruction::STI,
+            Instruction::JMP,
+            Instruction::RES,
+            Instruction::LEA,
+            Instruction::TRAP,
+        ];
+        for (op, &instruction) in expected.iter().enumerate() {
+            assert_eq!(decode(op as u16), instruction, "opcode {:#x}", op);
+        }
+    }
+
+    #[test]
+    fn decode_trap_knows_the_standard_vectors() {
+        assert_eq!(decode_trap(0x20), Some(TrapCode::GETC));
+        assert_eq!(decode_trap(0x25), Some(TrapCode::HALT));
+        assert_eq!(decode_trap(0x26), None);
+    }
+}
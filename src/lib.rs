@@ -0,0 +1,52 @@
+#![allow(clippy::upper_case_acronyms)]
+
+// LC-3 virtual machine library: the VM core, image loading, disassembly and
+// the interactive debugger used by the `rust_vm` binary.
+
+pub mod asm;
+pub mod builder;
+pub mod compare;
+pub mod debugger;
+pub mod disasm;
+#[cfg(feature = "display")]
+pub mod display;
+#[cfg(feature = "elf")]
+pub mod elf;
+pub mod estimate;
+pub mod execlog;
+pub mod expr;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod gdbstub;
+pub mod isa;
+pub mod json;
+pub mod link;
+pub mod lint;
+pub mod loader;
+pub mod memdump;
+pub mod mmio;
+pub mod number;
+pub mod style;
+pub mod symbols;
+pub mod terminal;
+pub mod vm;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use asm::{
+    assemble, assemble_object, assemble_program, AsmError, Assembly, Fixup, FixupKind, ObjectFile,
+    SourceSpan,
+};
+pub use builder::VmBuilder;
+pub use disasm::disassemble;
+pub use execlog::ExecLog;
+pub use isa::{Instruction, Register, TrapCode};
+pub use link::{link, Image, LinkError};
+pub use loader::{Endian, LoadError, LoadOptions};
+pub use memdump::DumpParseError;
+pub use mmio::MmioDevice;
+pub use number::{parse_number, NumberError};
+pub use vm::{
+    EofBehavior, Frame, Hook, HookEvent, Newline, OutputEncoding, RunOutcome, SnapshotError,
+    StepResult, UnknownTrap, Vm, VmError,
+};
@@ -0,0 +1,186 @@
+// Linker for objects from `assemble_object`: checks that their regions do
+// not overlap, resolves each .EXTERNAL reference against the .GLOBAL labels
+// of the others and keeps each object as its own segment, so loading the
+// result leaves the memory between them alone.
+
+use std::collections::HashMap;
+
+use crate::asm::{FixupKind, ObjectFile};
+
+// a linked program: an (origin, words) segment per object, in address
+// order, each ready for `Vm::load_words`
+#[derive(Debug, PartialEq)]
+pub struct Image {
+    pub segments: Vec<(u16, Vec<u16>)>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LinkError {
+    NoObjects,
+    // two objects both .GLOBAL the same label
+    DuplicateSymbol(String),
+    // an .EXTERNAL label no object exports
+    UndefinedSymbol(String),
+    // the label is too far from the reference at `address` for its offset
+    OutOfRange { symbol: String, address: u16 },
+    // two objects claim the word at `address`
+    Overlap { address: u16 },
+}
+
+impl std::fmt::Display for LinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LinkError::NoObjects => write!(f, "nothing to link"),
+            LinkError::DuplicateSymbol(symbol) => {
+                write!(f, "`{}` is exported by more than one object", symbol)
+            }
+            LinkError::UndefinedSymbol(symbol) => write!(f, "`{}` is not exported", symbol),
+            LinkError::OutOfRange { symbol, address } => {
+                write!(f, "`{}` is out of range of x{:04X}", symbol, address)
+            }
+            LinkError::Overlap { address } => {
+                write!(f, "objects overlap at x{:04X}", address)
+            }
+        }
+    }
+}
+
+pub fn link(objects: Vec<ObjectFile>) -> Result<Image, LinkError> {
+    let mut symbols = HashMap::new();
+    for object in &objects {
+        for (name, &address) in &object.exports {
+            if symbols.insert(name.clone(), address).is_some() {
+                return Err(LinkError::DuplicateSymbol(name.clone()));
+            }
+        }
+    }
+
+    let mut objects = objects;
+    objects.sort_by_key(|object| object.origin);
+    if objects.is_empty() {
+        return Err(LinkError::NoObjects);
+    }
+    for pair in objects.windows(2) {
+        if (pair[1].origin as usize) < pair[0].origin as usize + pair[0].words.len() {
+            return Err(LinkError::Overlap {
+                address: pair[1].origin,
+            });
+        }
+    }
+
+    let mut segments = Vec::new();
+    for object in objects {
+        let mut words = object.words;
+        for fixup in &object.fixups {
+            let target = *symbols
+                .get(&fixup.symbol)
+                .ok_or_else(|| LinkError::UndefinedSymbol(fixup.symbol.clone()))?;
+            let word = &mut words[(fixup.address - object.origin) as usize];
+            match fixup.kind {
+                FixupKind::Word => *word = target,
+                FixupKind::PcOffset(bits) => {
+                    let offset = target as i32 - (fixup.address as i32 + 1);
+                    let limit = 1 << (bits - 1);
+                    if offset < -limit || offset >= limit {
+                        return Err(LinkError::OutOfRange {
+                            symbol: fixup.symbol.clone(),
+                            address: fixup.address,
+                        });
+                    }
+                    *word |= (offset as u16) & ((1 << bits) - 1);
+                }
+            }
+        }
+        segments.push((object.origin, words));
+    }
+    Ok(Image { segments })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble_object;
+    use crate::vm::Vm;
+
+    const MAIN: &str = ".ORIG x3000\n.EXTERNAL SUB\nJSR SUB\nHALT\n.END";
+    const LIB: &str = ".ORIG x3100\n.GLOBAL SUB\nSUB ADD R0, R0, #1\nRET\n.END";
+
+    fn objects(sources: &[&str]) -> Vec<ObjectFile> {
+        sources
+            .iter()
+            .map(|source| assemble_object(source).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn resolves_a_call_into_another_object() {
+        let image = link(objects(&[MAIN, LIB])).unwrap();
+        // JSR to x3100 from x3001
+        assert_eq!(
+            image.segments,
+            vec![
+                (0x3000, vec![0x48FF, 0xF025]),
+                (0x3100, vec![0x1021, 0xC1C0])
+            ]
+        );
+
+        let mut vm = Vm::new();
+        vm.set_input(Vec::new());
+        for (origin, words) in &image.segments {
+            vm.load_words(*origin, words).unwrap();
+        }
+        vm.run().unwrap();
+        assert_eq!(vm.reg[0], 1);
+    }
+
+    #[test]
+    fn leaves_the_gaps_between_objects_alone() {
+        let lib = ".ORIG x4000\n.GLOBAL SUB\nSUB RET\n.END";
+        let main = ".ORIG x3000\n.EXTERNAL SUB\nLD R1, PTR\nHALT\nPTR .FILL SUB\n.END";
+        let image = link(objects(&[lib, main])).unwrap();
+        let mut vm = Vm::new();
+        vm.memory[0x3800] = 0x1234;
+        for (origin, words) in &image.segments {
+            vm.load_words(*origin, words).unwrap();
+        }
+        assert_eq!(vm.memory[0x3800], 0x1234);
+        assert_eq!(vm.memory[0x3002], 0x4000);
+        assert_eq!(vm.memory[0x4000], 0xC1C0);
+    }
+
+    #[test]
+    fn fills_words_with_external_addresses() {
+        let main = ".ORIG x3000\n.EXTERNAL SUB\nLD R1, PTR\nHALT\nPTR .FILL SUB\n.END";
+        let image = link(objects(&[LIB, main])).unwrap();
+        assert_eq!(image.segments[0].0, 0x3000);
+        assert_eq!(image.segments[0].1[2], 0x3100);
+    }
+
+    #[test]
+    fn reports_unresolvable_programs() {
+        assert_eq!(link(Vec::new()), Err(LinkError::NoObjects));
+        assert_eq!(
+            link(objects(&[MAIN])),
+            Err(LinkError::UndefinedSymbol("SUB".to_string()))
+        );
+        let other = ".ORIG x3200\n.GLOBAL SUB\nSUB RET\n.END";
+        assert_eq!(
+            link(objects(&[MAIN, LIB, other])),
+            Err(LinkError::DuplicateSymbol("SUB".to_string()))
+        );
+        let overlapping = ".ORIG x3001\n.GLOBAL SUB\nSUB RET\n.END";
+        assert_eq!(
+            link(objects(&[MAIN, overlapping])),
+            Err(LinkError::Overlap { address: 0x3001 })
+        );
+        let far = ".ORIG x3000\n.EXTERNAL SUB\nBRnzp SUB\n.END";
+        let distant = ".ORIG x4000\n.GLOBAL SUB\nSUB RET\n.END";
+        assert_eq!(
+            link(objects(&[far, distant])),
+            Err(LinkError::OutOfRange {
+                symbol: "SUB".to_string(),
+                address: 0x3000
+            })
+        );
+    }
+}
@@ -0,0 +1,316 @@
+// Static checks for common mistakes in a loaded program. Code is found by
+// following control flow from the origin, so data words are not mistaken
+// for instructions.
+
+use std::collections::HashSet;
+use std::ops::Range;
+
+use crate::isa::{decode, decode_trap, sign_extend, Instruction, MemoryMappedRegister, TrapCode};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LintKind {
+    // a BR or JSR leaving the loaded region
+    BranchOutOfRange { target: u16 },
+    // a TRAP with neither a built-in handler nor a vector table entry
+    UnknownTrap { vector: u16 },
+    // an ST/STI aimed at a device register that ignores writes
+    ReadOnlyWrite { target: u16 },
+    // instructions after a HALT that nothing jumps to or reads
+    UnreachableAfterHalt,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Lint {
+    pub addr: u16,
+    pub kind: LintKind,
+}
+
+impl std::fmt::Display for Lint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "x{:04X}: ", self.addr)?;
+        match self.kind {
+            LintKind::BranchOutOfRange { target } => {
+                write!(f, "branch to x{:04X} outside the program", target)
+            }
+            LintKind::UnknownTrap { vector } => {
+                write!(f, "TRAP x{:02X} has no service routine", vector)
+            }
+            LintKind::ReadOnlyWrite { target } => {
+                write!(f, "store to read-only device register x{:04X}", target)
+            }
+            LintKind::UnreachableAfterHalt => write!(f, "unreachable code after HALT"),
+        }
+    }
+}
+
+fn read_only(addr: u16) -> bool {
+    [
+        MemoryMappedRegister::KBDR,
+        MemoryMappedRegister::TMR,
+        MemoryMappedRegister::RNG,
+    ]
+    .into_iter()
+    .any(|r| r as u16 == addr)
+}
+
+pub(crate) fn pc_relative(addr: u16, instr: u16, bits: u16) -> u16 {
+    let mask = (1 << bits) - 1;
+    addr.wrapping_add(1)
+        .wrapping_add(sign_extend(instr & mask, bits))
+}
+
+// addresses in `range` reachable from `entry`; paths end at HALT and
+// at jumps through a register, whose targets are not known statically.
+// `overflow_flag` follows BR with no n/z/p bits as that extension's BRv.
+pub(crate) fn reachable(
+    memory: &[u16],
+    entry: u16,
+    range: Range<usize>,
+    overflow_flag: bool,
+) -> HashSet<u16> {
+    let mut seen = HashSet::new();
+    let mut pending = vec![entry];
+    while let Some(addr) = pending.pop() {
+        if !range.contains(&(addr as usize)) || !seen.insert(addr) {
+            continue;
+        }
+        let instr = memory[addr as usize];
+        let next = addr.wrapping_add(1);
+        match decode(instr >> 12) {
+            Instruction::BR => {
+                let nzp = (instr >> 9) & 0x7;
+                if nzp != 0 || overflow_flag {
+                    pending.push(pc_relative(addr, instr, 9));
+                }
+                if nzp != 0x7 {
+                    pending.push(next);
+                }
+            }
+            Instruction::JSR => {
+                if (instr >> 11) & 1 == 1 {
+                    pending.push(pc_relative(addr, instr, 11));
+                }
+                pending.push(next);
+            }
+            Instruction::TRAP if instr & 0xFF == TrapCode::HALT as u16 => {}
+            Instruction::JMP | Instruction::RTI | Instruction::RES => {}
+            _ => pending.push(next),
+        }
+    }
+    seen
+}
+
+// check the program loaded at `range`, which may end at x10000, with BRv
+// branches when the overflow flag extension is on
+pub fn analyze(memory: &[u16], range: Range<usize>, overflow_flag: bool) -> Vec<Lint> {
+    let code = reachable(memory, range.start as u16, range.clone(), overflow_flag);
+    let mut addrs: Vec<u16> = code.iter().copied().collect();
+    addrs.sort();
+
+    let mut data = HashSet::new();
+    let mut lints = Vec::new();
+    for &addr in &addrs {
+        let instr = memory[addr as usize];
+        let mut lint = |kind| lints.push(Lint { addr, kind });
+        match decode(instr >> 12) {
+            // BRv with a target counts when the overflow flag extension is on
+            Instruction::BR if (instr >> 9) & 0x7 != 0 || overflow_flag && instr & 0x1FF != 0 => {
+                let target = pc_relative(addr, instr, 9);
+                if !range.contains(&(target as usize)) {
+                    lint(LintKind::BranchOutOfRange { target });
+                }
+            }
+            Instruction::JSR if (instr >> 11) & 1 == 1 => {
+                let target = pc_relative(addr, instr, 11);
+                if !range.contains(&(target as usize)) {
+                    lint(LintKind::BranchOutOfRange { target });
+                }
+            }
+            Instruction::TRAP => {
+                let vector = instr & 0xFF;
+                if decode_trap(vector).is_none() && memory[vector as usize] == 0 {
+                    lint(LintKind::UnknownTrap { vector });
+                }
+            }
+            Instruction::ST => {
+                let target = pc_relative(addr, instr, 9);
+                data.insert(target);
+                if read_only(target) {
+                    lint(LintKind::ReadOnlyWrite { target });
+                }
+            }
+            Instruction::STI => {
+                let pointer = pc_relative(addr, instr, 9);
+                data.insert(pointer);
+                let target = memory[pointer as usize];
+                if read_only(target) {
+                    lint(LintKind::ReadOnlyWrite { target });
+                }
+            }
+            Instruction::LD | Instruction::LDI | Instruction::LEA => {
+                data.insert(pc_relative(addr, instr, 9));
+            }
+            _ => {}
+        }
+    }
+
+    for &addr in &addrs {
+        let after = addr.wrapping_add(1);
+        let is_halt = memory[addr as usize] == 0xF000 | TrapCode::HALT as u16;
+        if is_halt
+            && range.contains(&(after as usize))
+            && !code.contains(&after)
+            && !data.contains(&after)
+            && memory[after as usize] != 0
+        {
+            lints.push(Lint {
+                addr: after,
+                kind: LintKind::UnreachableAfterHalt,
+            });
+        }
+    }
+    lints.sort_by_key(|lint| lint.addr);
+    lints
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // lints for `program` loaded at `origin`, with extra words placed first
+    fn lint_at(origin: u16, program: &[u16], extra: &[(u16, u16)]) -> Vec<Lint> {
+        let mut memory = vec![0; 1 << 16];
+        for &(addr, word) in extra {
+            memory[addr as usize] = word;
+        }
+        let start = origin as usize;
+        memory[start..start + program.len()].copy_from_slice(program);
+        analyze(&memory, start..start + program.len(), false)
+    }
+
+    fn lint(program: &[u16]) -> Vec<Lint> {
+        lint_at(0x3000, program, &[])
+    }
+
+    #[test]
+    fn a_clean_program_has_no_lints() {
+        // LEA R0, MSG; PUTS; HALT; MSG .STRINGZ "Hi"
+        assert_eq!(lint(&[0xE002, 0xF022, 0xF025, 0x48, 0x69, 0]), vec![]);
+    }
+
+    #[test]
+    fn flags_branches_and_calls_outside_the_program() {
+        // BRz #16; JSR #-17; HALT
+        assert_eq!(
+            lint(&[0x0410, 0x4FEF, 0xF025]),
+            vec![
+                Lint {
+                    addr: 0x3000,
+                    kind: LintKind::BranchOutOfRange { target: 0x3011 },
+                },
+                Lint {
+                    addr: 0x3001,
+                    kind: LintKind::BranchOutOfRange { target: 0x2FF1 },
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn flags_traps_without_a_service_routine() {
+        let program = [0xF030, 0xF025]; // TRAP x30; HALT
+        assert_eq!(
+            lint(&program),
+            vec![Lint {
+                addr: 0x3000,
+                kind: LintKind::UnknownTrap { vector: 0x30 },
+            }]
+        );
+        // a vector table entry counts as an implementation
+        assert_eq!(lint_at(0x3000, &program, &[(0x0030, 0x1000)]), vec![]);
+    }
+
+    #[test]
+    fn flags_stores_to_read_only_device_registers() {
+        // STI R0, #1; HALT; .FILL xFE02
+        assert_eq!(
+            lint(&[0xB001, 0xF025, 0xFE02]),
+            vec![Lint {
+                addr: 0x3000,
+                kind: LintKind::ReadOnlyWrite { target: 0xFE02 },
+            }]
+        );
+        // ST R0, #17 from xFDF0 lands on KBDR; ST R0, #14 on KBSR is fine
+        assert_eq!(
+            lint_at(0xFDF0, &[0x3011, 0x300E, 0xF025], &[]),
+            vec![Lint {
+                addr: 0xFDF0,
+                kind: LintKind::ReadOnlyWrite { target: 0xFE02 },
+            }]
+        );
+    }
+
+    #[test]
+    fn flags_code_after_halt_that_nothing_reaches() {
+        // HALT; ADD R0, R0, #1
+        assert_eq!(
+            lint(&[0xF025, 0x1021]),
+            vec![Lint {
+                addr: 0x3001,
+                kind: LintKind::UnreachableAfterHalt,
+            }]
+        );
+        // BRnzp over the HALT makes the next word reachable
+        assert_eq!(lint(&[0x0E01, 0xF025, 0x1021, 0xF025]), vec![]);
+    }
+
+    #[test]
+    fn checks_programs_that_run_to_the_end_of_memory() {
+        // HALT; ADD R0, R0, #1 in the last two words
+        assert_eq!(
+            lint_at(0xFFFE, &[0xF025, 0x1021], &[]),
+            vec![Lint {
+                addr: 0xFFFF,
+                kind: LintKind::UnreachableAfterHalt,
+            }]
+        );
+    }
+
+    #[test]
+    fn follows_brv_only_with_the_overflow_flag_on() {
+        let brv = |program: &[u16], overflow_flag: bool| {
+            let mut memory = vec![0; 1 << 16];
+            memory[0x3000..0x3000 + program.len()].copy_from_slice(program);
+            analyze(&memory, 0x3000..0x3000 + program.len(), overflow_flag)
+        };
+        // BRv #1; HALT; ADD R0, R0, #1; HALT
+        let skip = [0x0001, 0xF025, 0x1021, 0xF025];
+        assert_eq!(brv(&skip, true), vec![]);
+        assert_eq!(
+            brv(&skip, false),
+            vec![Lint {
+                addr: 0x3002,
+                kind: LintKind::UnreachableAfterHalt,
+            }]
+        );
+        // BRv #16; HALT, and without the extension a word that never branches
+        let far = [0x0010, 0xF025];
+        assert_eq!(
+            brv(&far, true),
+            vec![Lint {
+                addr: 0x3000,
+                kind: LintKind::BranchOutOfRange { target: 0x3011 },
+            }]
+        );
+        assert_eq!(brv(&far, false), vec![]);
+    }
+
+    #[test]
+    fn lints_print_with_their_address() {
+        let lint = Lint {
+            addr: 0x3000,
+            kind: LintKind::UnknownTrap { vector: 0x30 },
+        };
+        assert_eq!(lint.to_string(), "x3000: TRAP x30 has no service routine");
+    }
+}
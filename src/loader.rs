@@ -0,0 +1,356 @@
+// Loading of LC-3 object images into memory.
+
+use std::fs::File;
+use std::io::Read;
+use std::ops::Range;
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+
+use crate::isa::MemoryMappedRegister;
+use crate::vm::{Vm, PC_START};
+
+// byte order of words in an image file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Endian {
+    Big,
+    Little,
+}
+
+impl Endian {
+    pub fn parse(s: &str) -> Option<Endian> {
+        match s {
+            "big" | "be" => Some(Endian::Big),
+            "little" | "le" => Some(Endian::Little),
+            _ => None,
+        }
+    }
+
+    // guess the byte order from the origin word: pick the reading that puts
+    // the origin below the device registers, then the one that is aligned to
+    // a 256-word page, preferring big-endian when still ambiguous
+    pub fn detect(origin: &[u8]) -> Endian {
+        let (big, little) = (Endian::Big.word(origin), Endian::Little.word(origin));
+        let sane = |addr: u16| addr < MemoryMappedRegister::KBSR as u16;
+        let aligned = |addr: u16| addr & 0xFF == 0;
+        if sane(little) && (!sane(big) || (aligned(little) && !aligned(big))) {
+            Endian::Little
+        } else {
+            Endian::Big
+        }
+    }
+
+    pub fn word(self, bytes: &[u8]) -> u16 {
+        match self {
+            Endian::Big => BigEndian::read_u16(bytes),
+            Endian::Little => LittleEndian::read_u16(bytes),
+        }
+    }
+}
+
+impl std::fmt::Display for Endian {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Endian::Big => write!(f, "big-endian"),
+            Endian::Little => write!(f, "little-endian"),
+        }
+    }
+}
+
+// how to interpret an image file
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LoadOptions {
+    // byte order; when None it is guessed from the origin word, with a
+    // warning: the reading below the device registers wins, and when both
+    // are, the one on a 256-word page boundary (x0200 over x0002), then
+    // big-endian (see `Endian::detect`)
+    pub endian: Option<Endian>,
+    // load here instead of at the origin stored in the file
+    pub load_at: Option<u16>,
+    // the file has no origin word, every word is program data; it is
+    // loaded at `load_at`, or at x3000 when that is not given
+    pub raw: bool,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(std::io::Error),
+    Truncated,
+    OriginOutOfRange,
+    // a malformed or unsupported ELF file
+    #[cfg(feature = "elf")]
+    Elf(&'static str),
+}
+
+impl From<std::io::Error> for LoadError {
+    fn from(e: std::io::Error) -> LoadError {
+        LoadError::Io(e)
+    }
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "{}", e),
+            LoadError::Truncated => write!(
+                f,
+                "image is truncated (odd number of bytes or missing origin)"
+            ),
+            LoadError::OriginOutOfRange => write!(f, "image does not fit in memory at its origin"),
+            #[cfg(feature = "elf")]
+            LoadError::Elf(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl Vm {
+    // load an image and return the memory range it occupies
+    pub fn read_image_file<R: Read>(
+        &mut self,
+        file: &mut R,
+        options: LoadOptions,
+    ) -> Result<Range<usize>, LoadError> {
+        let mut bytes: Vec<u8> = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let header = if options.raw { 0 } else { 2 };
+        if bytes.len() < header || !bytes.len().is_multiple_of(2) {
+            return Err(LoadError::Truncated);
+        }
+        let endian = options.endian.unwrap_or_else(|| {
+            if options.raw || bytes.is_empty() {
+                return Endian::Big;
+            }
+            let detected = Endian::detect(&bytes[0..2]);
+            eprintln!(
+                "warning: image looks {}, loading it as such (use --endian to override)",
+                detected
+            );
+            detected
+        });
+        let origin: u16 = match options.load_at {
+            Some(addr) => addr,
+            None if options.raw => PC_START,
+            None => endian.word(&bytes[0..2]),
+        };
+        let buffer: Vec<u16> = bytes[header..].chunks(2).map(|w| endian.word(w)).collect();
+        self.load_words(origin, &buffer)
+    }
+
+    // copy `words` into memory at `origin` and return the range they occupy
+    pub fn load_words(&mut self, origin: u16, words: &[u16]) -> Result<Range<usize>, LoadError> {
+        let origin_location: usize = origin as usize;
+        let buffer_size: usize = words.len();
+        if origin_location + buffer_size > self.memory_size() {
+            return Err(LoadError::OriginOutOfRange);
+        }
+        let range = origin_location..origin_location + buffer_size;
+        self.memory[range.clone()].copy_from_slice(words);
+        #[cfg(feature = "debug_mem")]
+        self.initialized[range.clone()].fill(true);
+        Ok(range)
+    }
+
+    pub fn read_image(
+        &mut self,
+        path: &str,
+        options: LoadOptions,
+    ) -> Result<Range<usize>, LoadError> {
+        let mut file = File::open(path)?;
+        self.read_image_file(&mut file, options)
+    }
+
+    // write memory from `start` up to, not including, `end` as a big-endian
+    // .obj image with `start` as its origin; `end` may be x10000 so the
+    // image can run through xFFFF
+    pub fn write_obj(&self, start: u16, end: usize, path: &str) -> std::io::Result<()> {
+        let words = self.memory.get(start as usize..end).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("invalid range x{:04X}..x{:04X}", start, end),
+            )
+        })?;
+        let mut bytes = Vec::with_capacity((words.len() + 1) * 2);
+        for &word in std::iter::once(&start).chain(words) {
+            bytes.extend_from_slice(&word.to_be_bytes());
+        }
+        std::fs::write(path, bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endian(endian: Endian) -> LoadOptions {
+        LoadOptions {
+            endian: Some(endian),
+            ..LoadOptions::default()
+        }
+    }
+
+    #[test]
+    fn read_image_file_round_trips_a_big_endian_obj() {
+        // origin x0000, then ADD R0, R0, #1 and HALT
+        let obj = [0x00, 0x00, 0x10, 0x21, 0xF0, 0x25];
+        let mut vm = Vm::new();
+        vm.read_image_file(&mut &obj[..], endian(Endian::Big))
+            .unwrap();
+        assert_eq!(vm.memory[0x0000], 0x1021);
+        assert_eq!(vm.memory[0x0001], 0xF025);
+    }
+
+    #[test]
+    fn read_image_file_reads_little_endian_words() {
+        let obj = [0x00, 0x00, 0x21, 0x10, 0x25, 0xF0];
+        let mut vm = Vm::new();
+        vm.read_image_file(&mut &obj[..], endian(Endian::Little))
+            .unwrap();
+        assert_eq!(vm.memory[0x0000], 0x1021);
+        assert_eq!(vm.memory[0x0001], 0xF025);
+    }
+
+    #[test]
+    fn endian_parse_accepts_long_and_short_names() {
+        assert_eq!(Endian::parse("big"), Some(Endian::Big));
+        assert_eq!(Endian::parse("le"), Some(Endian::Little));
+        assert_eq!(Endian::parse("middle"), None);
+    }
+
+    #[test]
+    fn read_image_file_places_words_at_the_origin() {
+        let obj = [0x30, 0x00, 0x11, 0x11, 0x22, 0x22, 0x33, 0x33];
+        let mut vm = Vm::new();
+        vm.read_image_file(&mut &obj[..], endian(Endian::Big))
+            .unwrap();
+        assert_eq!(vm.memory[0x2FFF], 0);
+        assert_eq!(&vm.memory[0x3000..0x3003], &[0x1111, 0x2222, 0x3333]);
+        assert_eq!(vm.memory[0x3003], 0);
+    }
+
+    #[test]
+    fn read_image_reports_a_missing_file() {
+        let mut vm = Vm::new();
+        let result = vm.read_image("/nonexistent/program.obj", endian(Endian::Big));
+        assert!(matches!(result, Err(LoadError::Io(_))));
+    }
+
+    #[test]
+    fn read_image_reports_a_truncated_word() {
+        let path = std::env::temp_dir().join(format!("lc3-truncated-{}.obj", std::process::id()));
+        std::fs::write(&path, [0x30]).unwrap();
+        let mut vm = Vm::new();
+        let result = vm.read_image(path.to_str().unwrap(), endian(Endian::Big));
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(LoadError::Truncated)));
+    }
+
+    #[test]
+    fn read_image_file_rejects_images_past_the_end_of_memory() {
+        let obj = [0xFF, 0xFF, 0x00, 0x01, 0x00, 0x02];
+        let mut vm = Vm::new();
+        let result = vm.read_image_file(&mut &obj[..], endian(Endian::Big));
+        assert!(matches!(result, Err(LoadError::OriginOutOfRange)));
+    }
+
+    #[test]
+    fn detect_picks_the_byte_order_with_a_sane_origin() {
+        assert_eq!(Endian::detect(&[0x30, 0x00]), Endian::Big);
+        assert_eq!(Endian::detect(&[0x00, 0x30]), Endian::Little);
+        // x0200 and x0002 are both plausible; the page-aligned one wins
+        assert_eq!(Endian::detect(&[0x02, 0x00]), Endian::Big);
+        assert_eq!(Endian::detect(&[0x00, 0x02]), Endian::Little);
+        // x3030 either way
+        assert_eq!(Endian::detect(&[0x30, 0x30]), Endian::Big);
+    }
+
+    #[test]
+    fn read_image_file_breaks_ties_on_page_alignment() {
+        // x0200 big-endian or x0002 little-endian
+        let obj = [0x02, 0x00, 0x10, 0x21];
+        let mut vm = Vm::new();
+        let region = vm
+            .read_image_file(&mut &obj[..], LoadOptions::default())
+            .unwrap();
+        assert_eq!(region, 0x0200..0x0201);
+        assert_eq!(vm.memory[0x0200], 0x1021);
+    }
+
+    #[test]
+    fn read_image_file_detects_little_endian_images() {
+        let obj = [0x00, 0x30, 0x21, 0x10];
+        let mut vm = Vm::new();
+        let region = vm
+            .read_image_file(&mut &obj[..], LoadOptions::default())
+            .unwrap();
+        assert_eq!(region, 0x3000..0x3001);
+        assert_eq!(vm.memory[0x3000], 0x1021);
+    }
+
+    #[test]
+    fn load_at_overrides_the_origin_word() {
+        let obj = [0x30, 0x00, 0x11, 0x11, 0x22, 0x22];
+        let mut vm = Vm::new();
+        let options = LoadOptions {
+            load_at: Some(0x4000),
+            ..LoadOptions::default()
+        };
+        let region = vm.read_image_file(&mut &obj[..], options).unwrap();
+        assert_eq!(region, 0x4000..0x4002);
+        assert_eq!(&vm.memory[0x4000..0x4002], &[0x1111, 0x2222]);
+        assert_eq!(vm.memory[0x3000], 0);
+    }
+
+    #[test]
+    fn raw_images_have_no_origin_word() {
+        let raw = [0x30, 0x00, 0x11, 0x11];
+        let mut vm = Vm::new();
+        let options = LoadOptions {
+            load_at: Some(0x5000),
+            raw: true,
+            ..LoadOptions::default()
+        };
+        let region = vm.read_image_file(&mut &raw[..], options).unwrap();
+        assert_eq!(region, 0x5000..0x5002);
+        assert_eq!(&vm.memory[0x5000..0x5002], &[0x3000, 0x1111]);
+    }
+
+    #[test]
+    fn raw_images_load_at_x3000_by_default() {
+        let raw = [0x10, 0x21, 0x10, 0x21, 0x12, 0x61, 0xF0, 0x25];
+        let mut vm = Vm::new();
+        let options = LoadOptions {
+            raw: true,
+            ..LoadOptions::default()
+        };
+        let region = vm.read_image_file(&mut &raw[..], options).unwrap();
+        assert_eq!(region, 0x3000..0x3004);
+        assert_eq!(vm.memory[0x2FFF], 0);
+        assert_eq!(
+            &vm.memory[0x3000..0x3004],
+            &[0x1021, 0x1021, 0x1261, 0xF025]
+        );
+        assert_eq!(vm.memory[0x3004], 0);
+    }
+
+    #[test]
+    fn write_obj_saves_up_to_the_last_word_of_memory() {
+        let path = std::env::temp_dir().join(format!("lc3-write-top-{}.obj", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut vm = Vm::new();
+        vm.memory[0xFFFE] = 0x1234;
+        vm.memory[0xFFFF] = 0x5678;
+        vm.write_obj(0xFFFE, 0x10000, path).unwrap();
+        let bytes = std::fs::read(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(bytes, [0xFF, 0xFE, 0x12, 0x34, 0x56, 0x78]);
+    }
+
+    #[test]
+    fn write_obj_rejects_a_backwards_range() {
+        let path = std::env::temp_dir().join(format!("lc3-write-back-{}.obj", std::process::id()));
+        let path = path.to_str().unwrap();
+        let vm = Vm::new();
+        let err = vm.write_obj(0x3003, 0x3000, path).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(!std::path::Path::new(path).exists());
+    }
+}
@@ -1,316 +1,1079 @@
-use std::io::prelude::*;
-use std::fs::File;
-
-const PC_START: u16 = 0x3000;
-const UINT16_MAX: u16 = 65536;
-const ALLMEMORY: [u16; UINT16_MAX as usize] = [0; UINT16_MAX];
-
-#[derive(Clone, Copy)]
-enum Register {
-    RR0 = 0,
-    RR1,
-    RR2,
-    RR3,
-    RR4,
-    RR5,
-    RR6,
-    RR7,
-    RPC,
-    RCOND,
-    RCOUNT,
-}
+use rust_vm::{
+    assemble_object, assemble_program, debugger, disasm, gdbstub, link, lint, parse_number, style,
+    symbols, terminal, Endian, EofBehavior, ExecLog, LoadOptions, Newline, OutputEncoding,
+    Register, RunOutcome, StepResult, UnknownTrap, Vm, VmError,
+};
 
-// register in array
-const REG: [Register; 10] = [
-    Register::RR0,
-    Register::RR1,
-    Register::RR2,
-    Register::RR3,
-    Register::RR4,
-    Register::RR5,
-    Register::RR6,
-    Register::RR7,
-    Register::RPC,
-    Register::RCOND,
-];
-
-
-// instructions
-
-enum Instruction {
-    BR = 0,
-    // branch
-    ADD,
-    // add
-    LD,
-    // load
-    ST,
-    // store
-    JSR,
-    // jump register
-    AND,
-    // bitwise and
-    LDR,
-    // load register
-    STR,
-    // store register
-    RTI,
-    // unused
-    NOT,
-    // bitwise not
-    LDI,
-    // load indirect
-    STI,
-    // store indirect
-    JMP,
-    // jump
-    RES,
-    // reserved (unused)
-    LEA,
-    // load effective address
-    TRAP,   // execute trap
-}
+const USAGE: &str = "usage: rust_vm [options] <image.obj> [image.obj ...]
+       rust_vm [options] --asm <prog.asm> [--asm <lib.asm> ...]
+       rust_vm --diff <a.snapshot> <b.snapshot>
 
-// condition flags
-enum ConditionFlag {
-    POS = 1 << 0,
-    // P
-    ZRO = 1 << 1,
-    // Z
-    NEG = 1 << 2, // N
-}
+options:
+  --asm FILE            assemble FILE and run it; given more than once, the files
+                        are linked through .GLOBAL/.EXTERNAL and run from the
+                        first one's origin. FILE `-` reads the source from
+                        stdin, leaving no input for the program
+  --listing FILE        with --asm, write an address/hex/source listing to FILE
+  --endian big|little   byte order of the image files (default: detected)
+  --load-at xADDR       load images at ADDR instead of their origin
+  --pc xADDR            start executing at ADDR instead of the last image's origin
+  --raw                 images have no origin word; load them at --load-at or x3000
+  --disasm              print the disassembly of the loaded images and exit
+  --lint                check the loaded images for likely mistakes and exit
+  --trace-disasm        like --disasm, but follow the control flow from the entry
+                        point and print the words it never reaches as data
+  --data-as-hex         with --disasm, print words following a HALT as .FILL data
+  --dump-on-halt        print the registers when the program halts
+  --exit-from-r0        exit with the low byte of R0 when the program halts
+  --output-encoding raw|ascii|utf8-lossy
+                        how printed bytes above 0x7F are written (default: raw)
+  --newline lf|crlf|none
+                        translate printed line endings: crlf turns LF into CR LF,
+                        lf turns CR and CR LF into LF (default: none)
+  --on-eof halt|zero|error
+                        what GETC/IN do at the end of input (default: halt)
+  --unknown-trap halt|ignore|error
+                        what a TRAP to a vector with no handler does: print a
+                        message and halt, skip it, or stop with an error naming
+                        the vector (default: halt)
+  --strict              same as --unknown-trap error
+  --lea-sets-cc on|off  whether LEA sets the condition codes (default: off, as in
+                        the third edition ISA; lc3sim and older texts use on)
+  --enable-overflow-flag
+                        extension: ADD sets PSR bit 3 on signed overflow and BRv
+                        (BR with no n/z/p bits) branches on it
+  --protect-vectors     fault when a user-mode program writes to x0000-x01FF or
+                        the device registers at xFE00 and above; OS code below
+                        x3000, such as trap service routines, is exempt
+  --no-flush            buffer program output until it reads input or halts
+  --quiet               print only the program's own output on stdout, without
+                        the HALT banner
+  --no-color            never color register and memory dumps (also NO_COLOR)
+  --abi-names           label R6 and R7 as R6/SP and R7/LR in register dumps and
+                        traces
+  --unsigned            print registers in hex only, without the signed column
+  --trace               print every executed instruction to stderr
+  --stats               print instruction counts to stderr when the program stops
+  --log-json FILE       write one JSON line per executed instruction to FILE, with
+                        the registers and memory words it changed
+  --coverage            print the executed address ranges to stderr on exit
+  --profile N           print the N most executed addresses to stderr on exit
+  --cycles              print the cycle count to stderr when the program stops
+  --debug               start the interactive debugger, paused before the first
+                        instruction
+  --step-on-start       same as --debug
+  --gdb PORT            wait for a gdb remote connection on PORT
+  --max-instructions N  stop with an error after N instructions
+  --fill-mem FILE       preload memory from a listing in the --dump-mem format
+                        (repeatable)
+  --dump-mem xSTART:xLEN  print LEN words of memory from START on exit
+  --dump-obj FILE       on exit, write the memory the images were loaded into,
+                        as changed by the program or debugger, to an .obj FILE
+  --trap-vector xCODE=xADDR
+                        run TRAP CODE's service routine at ADDR (repeatable)
+  --mem-width N         words per row in memory dumps (default: 8)
+  --seed N              seed the pseudo-random register at xFE0A
+  --elf FILE            load the segments of an ELF-wrapped image and start at
+                        its entry point (needs the `elf` feature)
+  --display             draw the xC000 framebuffer when the program stops
+                        (needs the `display` feature)
+  --diff A B            print the memory words that differ between two snapshot
+                        files and exit, with status 1 if any do
+  --sym FILE            load labels from an lc3as symbol table for --disasm/--trace
 
-// trap
-enum TrapCode {
-    GETC = 0x20,
-    OUT = 0x21,
-    PUTS = 0x22,
-    IN = 0x23,
-    PUTSP = 0x24,
-    HALT = 0x25,
+Addresses and counts for --load-at, --pc, --dump-mem and --trap-vector take
+a prefix: hex with x or 0x (x3000), binary with b or 0b, decimal with #
+(#12288). A bare number like `3000` is refused as ambiguous.";
+
+struct Options {
+    load: LoadOptions,
+    images: Vec<String>,
+    asm: Vec<String>,
+    listing: Option<String>,
+    disasm: bool,
+    lint: bool,
+    data_as_hex: bool,
+    trace_disasm: bool,
+    dump_on_halt: bool,
+    exit_from_r0: bool,
+    unsigned: bool,
+    abi_names: bool,
+    no_color: bool,
+    quiet: bool,
+    output_encoding: OutputEncoding,
+    newline: Newline,
+    on_eof: EofBehavior,
+    on_unknown_trap: UnknownTrap,
+    lea_sets_cc: bool,
+    overflow_flag: bool,
+    no_flush: bool,
+    protect_vectors: bool,
+    trace: bool,
+    stats: bool,
+    cycles: bool,
+    coverage: bool,
+    log_json: Option<String>,
+    profile: Option<usize>,
+    sym: Option<String>,
+    debug: bool,
+    gdb: Option<u16>,
+    max_instructions: Option<u64>,
+    dump_mem: Option<(u16, usize)>,
+    dump_obj: Option<String>,
+    pc: Option<u16>,
+    seed: Option<u32>,
+    trap_vectors: Vec<(u8, u16)>,
+    fill_mem: Vec<String>,
+    mem_width: Option<usize>,
+    diff: Option<(String, String)>,
+    #[cfg(feature = "display")]
+    display: bool,
+    elf: Option<String>,
 }
 
-enum MemoryMappedRegister {
-    KBSR = 0xFE00, // keyboard status
-    KBDR = 0xFE02, // keyboard data
+// an address or count for the flags the usage lists; a bare `3000` is hex
+// to anyone used to lc3sim but decimal to `parse_number`, so a prefix is
+// required
+fn parse_address(s: &str) -> Result<u16, String> {
+    let prefixes = ["x", "X", "0x", "0X", "b", "B", "0b", "0B", "#"];
+    if !s.is_empty() && !prefixes.iter().any(|prefix| s.starts_with(prefix)) {
+        return Err(format!(
+            "`{}` has no prefix, write x{} for hex or #{} for decimal",
+            s, s, s
+        ));
+    }
+    parse_number(s).map_err(|e| e.to_string())
 }
 
-fn sign_extend(x: u16, bit_count: u16) -> u16 {
-    if (x >> (bit_count - 1)) & 1 {
-        x | (0xFFFF << bit_count);
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+    let mut options = Options {
+        load: LoadOptions::default(),
+        images: Vec::new(),
+        asm: Vec::new(),
+        listing: None,
+        disasm: false,
+        lint: false,
+        data_as_hex: false,
+        trace_disasm: false,
+        dump_on_halt: false,
+        exit_from_r0: false,
+        unsigned: false,
+        abi_names: false,
+        no_color: false,
+        quiet: false,
+        output_encoding: OutputEncoding::Raw,
+        newline: Newline::AsIs,
+        on_eof: EofBehavior::Halt,
+        on_unknown_trap: UnknownTrap::Halt,
+        lea_sets_cc: false,
+        overflow_flag: false,
+        no_flush: false,
+        protect_vectors: false,
+        trace: false,
+        stats: false,
+        cycles: false,
+        coverage: false,
+        log_json: None,
+        profile: None,
+        sym: None,
+        debug: false,
+        gdb: None,
+        max_instructions: None,
+        dump_mem: None,
+        dump_obj: None,
+        pc: None,
+        seed: None,
+        trap_vectors: Vec::new(),
+        fill_mem: Vec::new(),
+        mem_width: None,
+        diff: None,
+        #[cfg(feature = "display")]
+        display: false,
+        elf: None,
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--endian" => {
+                let value = args.next().unwrap_or_default();
+                options.load.endian = Some(Endian::parse(&value).ok_or(format!(
+                    "--endian expects `big` or `little`, got `{}`",
+                    value
+                ))?);
+            }
+            "--load-at" => {
+                let value = args.next().unwrap_or_default();
+                options.load.load_at = Some(parse_address(&value).map_err(|e| {
+                    format!("--load-at expects an address, got `{}`: {}", value, e)
+                })?);
+            }
+            "--pc" => {
+                let value = args.next().unwrap_or_default();
+                options.pc = Some(
+                    parse_address(&value)
+                        .map_err(|e| format!("--pc expects an address, got `{}`: {}", value, e))?,
+                );
+            }
+            "--raw" => options.load.raw = true,
+            "--dump-obj" => {
+                options.dump_obj = Some(args.next().ok_or("--dump-obj expects a file")?)
+            }
+            "--dump-mem" => {
+                let value = args.next().unwrap_or_default();
+                let (start, len) = value
+                    .split_once(':')
+                    .ok_or(format!("--dump-mem expects xSTART:xLEN, got `{}`", value))?;
+                let range = parse_address(start)
+                    .and_then(|start| parse_address(len).map(|len| (start, len as usize)));
+                options.dump_mem = Some(range.map_err(|e| {
+                    format!("--dump-mem expects xSTART:xLEN, got `{}`: {}", value, e)
+                })?);
+            }
+            "--disasm" => options.disasm = true,
+            "--lint" => options.lint = true,
+            "--data-as-hex" => options.data_as_hex = true,
+            "--trace-disasm" => options.trace_disasm = true,
+            "--dump-on-halt" => options.dump_on_halt = true,
+            "--exit-from-r0" => options.exit_from_r0 = true,
+            "--trace" => options.trace = true,
+            "--stats" => options.stats = true,
+            "--cycles" => options.cycles = true,
+            "--coverage" => options.coverage = true,
+            "--log-json" => {
+                options.log_json = Some(args.next().ok_or("--log-json expects a file")?)
+            }
+            "--profile" => {
+                let value = args.next().unwrap_or_default();
+                options.profile = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("--profile expects a number, got `{}`", value))?,
+                );
+            }
+            "--unsigned" => options.unsigned = true,
+            "--abi-names" => options.abi_names = true,
+            "--no-color" => options.no_color = true,
+            "--quiet" | "-q" => options.quiet = true,
+            "--no-flush" => options.no_flush = true,
+            "--protect-vectors" => options.protect_vectors = true,
+            "--enable-overflow-flag" => options.overflow_flag = true,
+            "--lea-sets-cc" => {
+                options.lea_sets_cc = match args.next().as_deref() {
+                    Some("on") => true,
+                    Some("off") => false,
+                    other => {
+                        return Err(format!(
+                            "--lea-sets-cc expects on or off, got `{}`",
+                            other.unwrap_or_default()
+                        ))
+                    }
+                };
+            }
+            "--on-eof" => {
+                let value = args.next().unwrap_or_default();
+                options.on_eof = EofBehavior::parse(&value).ok_or(format!(
+                    "--on-eof expects halt, zero or error, got `{}`",
+                    value
+                ))?;
+            }
+            "--output-encoding" => {
+                let value = args.next().unwrap_or_default();
+                options.output_encoding = OutputEncoding::parse(&value).ok_or(format!(
+                    "--output-encoding expects raw, ascii or utf8-lossy, got `{}`",
+                    value
+                ))?;
+            }
+            "--unknown-trap" => {
+                let value = args.next().unwrap_or_default();
+                options.on_unknown_trap = UnknownTrap::parse(&value).ok_or(format!(
+                    "--unknown-trap expects halt, ignore or error, got `{}`",
+                    value
+                ))?;
+            }
+            "--strict" => options.on_unknown_trap = UnknownTrap::Error,
+            "--newline" => {
+                let value = args.next().unwrap_or_default();
+                options.newline = Newline::parse(&value).ok_or(format!(
+                    "--newline expects lf, crlf or none, got `{}`",
+                    value
+                ))?;
+            }
+            // the debugger always waits for a command before running
+            "--debug" | "--step-on-start" => options.debug = true,
+            "--max-instructions" => {
+                let value = args.next().unwrap_or_default();
+                options.max_instructions = Some(value.parse().map_err(|_| {
+                    format!("--max-instructions expects a number, got `{}`", value)
+                })?);
+            }
+            "--gdb" => {
+                let value = args.next().unwrap_or_default();
+                options.gdb = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("--gdb expects a port number, got `{}`", value))?,
+                );
+            }
+            "--asm" => options.asm.push(args.next().ok_or("--asm expects a file")?),
+            "--fill-mem" => options
+                .fill_mem
+                .push(args.next().ok_or("--fill-mem expects a file")?),
+            "--listing" => options.listing = Some(args.next().ok_or("--listing expects a file")?),
+            "--trap-vector" => {
+                let value = args.next().unwrap_or_default();
+                let (code, addr) = value.split_once('=').ok_or(format!(
+                    "--trap-vector expects xCODE=xADDR, got `{}`",
+                    value
+                ))?;
+                let mapping = parse_address(code).and_then(|code| {
+                    let code = u8::try_from(code).map_err(|_| "code is over xFF".to_string())?;
+                    Ok((code, parse_address(addr)?))
+                });
+                options.trap_vectors.push(mapping.map_err(|e| {
+                    format!("--trap-vector expects xCODE=xADDR, got `{}`: {}", value, e)
+                })?);
+            }
+            "--mem-width" => {
+                let value = args.next().unwrap_or_default();
+                options.mem_width = Some(value.parse().ok().filter(|&n| n > 0).ok_or(format!(
+                    "--mem-width expects a positive number, got `{}`",
+                    value
+                ))?);
+            }
+            "--seed" => {
+                let value = args.next().unwrap_or_default();
+                options.seed = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("--seed expects a number, got `{}`", value))?,
+                );
+            }
+            #[cfg(feature = "display")]
+            "--display" => options.display = true,
+            #[cfg(feature = "elf")]
+            "--elf" => options.elf = Some(args.next().ok_or("--elf expects a file")?),
+            "--diff" => {
+                let a = args.next().ok_or("--diff expects two snapshot files")?;
+                let b = args.next().ok_or("--diff expects two snapshot files")?;
+                options.diff = Some((a, b));
+            }
+            "--sym" => options.sym = Some(args.next().ok_or("--sym expects a file")?),
+            _ if arg.starts_with("--") => return Err(format!("unknown option `{}`", arg)),
+            _ => options.images.push(arg),
+        }
+    }
+    if options.images.is_empty()
+        && options.asm.is_empty()
+        && options.elf.is_none()
+        && options.diff.is_none()
+    {
+        return Err("no image file given".to_string());
+    }
+    if options.asm.iter().filter(|path| *path == "-").count() > 1 {
+        return Err("stdin can only be given to --asm once".to_string());
+    }
+    if options.listing.is_some() && options.asm.len() != 1 {
+        return Err("--listing needs exactly one --asm".to_string());
     }
-    x
+    Ok(options)
 }
 
-fn update_flags(r: u16) {
-    if REG[r] == 0 {
-        REG[Register::RCOND] = ConditionFlag::ZRO;
-    } else if REG[r] >> 15 {
-        REG[Register::RCOND] = ConditionFlag::NEG;
+// assembly source from a file, or from stdin for `-`
+fn read_source(path: &str) -> std::io::Result<String> {
+    if path == "-" {
+        std::io::read_to_string(std::io::stdin())
     } else {
-        REG[Register::RCOND] = ConditionFlag::POS;
+        std::fs::read_to_string(path)
     }
 }
 
-fn read_image_file(file: &mut File) {
-    let origin: u16 = file.read_u16::<LittleEndian>().unwrap();
-    let max_read: u16 = UINT16_MAX - origin;
-    let mut buffer: Vec<u16> = Vec::new();
-    let mut read: u16 = origin;
-    while read < max_read {
-        let val: u16 = file.read_u16::<LittleEndian>().unwrap();
-        buffer.push(val);
-        read += 1;
-    }
-    let origin_location: usize = origin as usize;
-    let buffer_location: usize = 0;
-    let buffer_size: usize = buffer.len();
-    ALLMEMORY[origin_location..buffer_size].copy_from_slice(&buffer[buffer_location..buffer_size]);
+fn report_error(vm: &Vm, e: VmError) {
+    let pc = match e {
+        VmError::IllegalOpcode { pc, .. }
+        | VmError::PrivilegeViolation { pc }
+        | VmError::InputExhausted { pc }
+        | VmError::AccessViolation { pc, .. }
+        | VmError::UnknownTrap { pc, .. } => pc,
+    };
+    eprintln!("error: {}", e);
+    eprintln!("  x{:04X}  x{:04X}", pc, vm.memory[pc as usize]);
 }
 
-fn read_image(path: &str) {
-    let mut file = File::open(path).unwrap();
-    read_image_file(&mut file);
+// --dump-obj: everything from the first loaded word to the last as one
+// image
+fn write_loaded_obj(vm: &Vm, regions: &[std::ops::Range<usize>], path: &str) {
+    let start = regions.iter().map(|region| region.start).min().unwrap_or(0);
+    let end = regions.iter().map(|region| region.end).max().unwrap_or(0);
+    if let Err(e) = vm.write_obj(start as u16, end, path) {
+        eprintln!("{}: {}", path, e);
+    }
 }
 
-fn mem_write(address: u16, val: u16) {
-    ALLMEMORY[address as usize] = val;
+fn read_snapshot(path: &str) -> Result<Vm, String> {
+    let data = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut vm = Vm::new();
+    vm.load_snapshot(&data)
+        .map_err(|e| format!("{}: {}", path, e))?;
+    Ok(vm)
 }
 
-fn mem_read(address: u16) -> u16 {
-    if address == MemoryMappedRegister::KBSR as u16 {
-        if check_key() {
-            ALLMEMORY[MemoryMappedRegister::KBSR as usize] = 1 << 15;
-            ALLMEMORY[MemoryMappedRegister::KBDR as usize] = std::io::stdin().bytes().next().unwrap().unwrap() as u16;
-        } else {
-            ALLMEMORY[MemoryMappedRegister::KBSR as usize] = 0;
-        }
+// --diff: compare the memory of two snapshots
+fn diff_snapshots(a: &str, b: &str) -> Result<bool, String> {
+    let (a, b) = (read_snapshot(a)?, read_snapshot(b)?);
+    let differences = a.diff_memory(&b, 0..1 << 16);
+    for (addr, ours, theirs) in &differences {
+        println!("x{:04X}: x{:04X} x{:04X}", addr, ours, theirs);
     }
-    ALLMEMORY[address as usize]
+    Ok(differences.is_empty())
 }
 
 fn main() {
-    REG[Register::RPC] = PC_START;
-
-    let mut running: i32 = 1;
-    while running {
-        let instr = mem_read(REG[Register::RPC]);
-        REG[Register::RPC] += 1;
-        let op = instr >> 12;
-
-        match op {
-            Instruction::ADD => {
-                let dr = (instr >> 9) & 0x7;
-                let sr1 = (instr >> 6) & 0x7;
-                let imm_flag = (instr >> 5) & 0x1;
-                if imm_flag == 1 {
-                    let imm5 = sign_extend(instr & 0x1F, 5);
-                    REG[dr] = REG[sr1] + imm5;
-                } else {
-                    let sr2 = instr & 0x7;
-                    REG[dr] = REG[sr1] + REG[sr2];
-                }
-                update_flags(dr);
-            }
-
-            Instruction::AND => {
-                let dr = (instr >> 9) & 0x7;
-                let sr1 = (instr >> 6) & 0x7;
-                let imm_flag = (instr >> 5) & 0x1;
-                if imm_flag == 1 {
-                    let imm5 = sign_extend(instr & 0x1F, 5);
-                    REG[dr] = REG[sr1] & imm5;
-                } else {
-                    let sr2 = instr & 0x7;
-                    REG[dr] = REG[sr1] & REG[sr2];
-                }
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            eprintln!("{}", USAGE);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some((a, b)) = &options.diff {
+        match diff_snapshots(a, b) {
+            Ok(same) => std::process::exit(if same { 0 } else { 1 }),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(2);
             }
-            Instruction::NOT => {
-                let dr = (instr >> 9) & 0x7;
-                let sr1 = (instr >> 6) & 0x7;
-                REG[dr] = !REG[sr1];
-                update_flags(dr);
-            }
-            Instruction::BR => {
-                let pc_offset = sign_extend(instr & 0x1FF, 9);
-                let cond_flag = (instr >> 9) & 0x7;
-                if cond_flag & REG[Register::RCOND] != 0 {
-                    REG[Register::RPC] += pc_offset;
-                }
+        }
+    }
+
+    let mut vm = Vm::new();
+    vm.trace = options.trace;
+    vm.show_signed = !options.unsigned;
+    vm.abi_names = options.abi_names;
+    vm.quiet = options.quiet;
+    if let Some(width) = options.mem_width {
+        vm.mem_width = width;
+    }
+    vm.color = !options.no_color && style::color_wanted();
+    vm.output_encoding = options.output_encoding;
+    vm.newline = options.newline;
+    vm.on_eof = options.on_eof;
+    vm.on_unknown_trap = options.on_unknown_trap;
+    vm.lea_sets_cc = options.lea_sets_cc;
+    vm.overflow_flag = options.overflow_flag;
+    vm.auto_flush = !options.no_flush;
+    vm.protect_vectors = options.protect_vectors;
+    if let Some(seed) = options.seed {
+        vm.seed_rng(seed);
+    }
+    if options.coverage || options.profile.is_some() {
+        vm.enable_coverage();
+    }
+    if let Some(path) = &options.sym {
+        match symbols::load_symbols(path) {
+            Ok(symbols) => vm.symbols = symbols,
+            Err(e) => {
+                eprintln!("failed to load symbols {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    // later images overwrite overlapping regions of earlier ones
+    let mut regions = Vec::new();
+    for path in &options.images {
+        match vm.read_image(path, options.load) {
+            Ok(region) => regions.push(region),
+            Err(e) => {
+                eprintln!("failed to load image {}: {}", path, e);
+                std::process::exit(1);
             }
-            Instruction::JMP => {
-                let base_r = (instr >> 6) & 0x7;
-                REG[Register::RPC] = REG[base_r];
-            }
-            Instruction::JSR => {
-                REG[Register::RR7] = REG[Register::RPC];
-                let flag = (instr >> 11) & 1;
-                if flag == 0 {
-                    let base_r = (instr >> 6) & 0x7;
-                    REG[Register::RPC] = REG[base_r];
-                } else {
-                    let pc_offset = sign_extend(instr & 0x7FF, 11);
-                    REG[Register::RPC] += pc_offset;
+        }
+    }
+    // where a linked program starts
+    let mut entry = None;
+    if options.asm.len() > 1 {
+        let mut objects = Vec::new();
+        for path in &options.asm {
+            let object = read_source(path)
+                .map_err(|e| e.to_string())
+                .and_then(|source| assemble_object(&source).map_err(|e| e.to_string()));
+            match object {
+                Ok(object) => objects.push(object),
+                Err(e) => {
+                    eprintln!("failed to assemble {}: {}", path, e);
+                    std::process::exit(1);
                 }
             }
-            Instruction::LD => {
-                let dr = (instr >> 9) & 0x7;
-                let pc_offset = sign_extend(instr & 0x1FF, 9);
-                REG[dr] = mem_read(REG[Register::RPC] + pc_offset);
-                update_flags(dr);
-            }
-            Instruction::LDI => {
-                let dr = (instr >> 9) & 0x7;
-                let pc_offset = sign_extend(instr & 0x1FF, 9);
-                REG[dr] = mem_read(mem_read(REG[Register::RPC] + pc_offset));
-                update_flags(dr);
-            }
-            Instruction::LDR => {
-                let dr = (instr >> 9) & 0x7;
-                let base_r = (instr >> 6) & 0x7;
-                let offset = sign_extend(instr & 0x3F, 6);
-                REG[dr] = mem_read(REG[base_r] + offset);
-                update_flags(dr);
-            }
-            Instruction::LEA => {
-                let dr = (instr >> 9) & 0x7;
-                let pc_offset = sign_extend(instr & 0x1FF, 9);
-                REG[dr] = REG[Register::RPC] + pc_offset;
-                update_flags(dr);
-            }
-            Instruction::ST => {
-                let sr = (instr >> 9) & 0x7;
-                let pc_offset = sign_extend(instr & 0x1FF, 9);
-                mem_write(REG[Register::RPC] + pc_offset, REG[sr]);
-            }
-            Instruction::STI => {
-                let sr = (instr >> 9) & 0x7;
-                let pc_offset = sign_extend(instr & 0x1FF, 9);
-                mem_write(mem_read(REG[Register::RPC] + pc_offset), REG[sr]);
-            }
-            Instruction::STR => {
-                let sr = (instr >> 9) & 0x7;
-                let base_r = (instr >> 6) & 0x7;
-                let offset = sign_extend(instr & 0x3F, 6);
-                mem_write(REG[base_r] + offset, REG[sr]);
-            }
-            Instruction::TRAP => {
-                match instr & 0xFF {
-                    Trap::GETC => {
-                        REG[Register::RR0] = read_char();
-                    }
-                    Trap::OUT => {
-                        put_char(REG[Register::RR0] as u8);
-                    }
-                    Trap::PUTS => {
-                        let mut c = mem_read(REG[Register::RR0]);
-                        while c != 0 {
-                            put_char(c as u8);
-                            REG[Register::RR0] += 1;
-                            c = mem_read(REG[Register::RR0]);
-                        }
-                    }
-                    Trap::IN => {
-                        print!("Enter a character: ");
-                        let c = read_char();
-                        put_char(c);
-                        REG[Register::RR0] = c as u16;
-                    }
-                    Trap::PUTSP => {
-                        let mut c = mem_read(REG[Register::RR0]);
-                        while c != 0 {
-                            let c1 = (c & 0xFF) as u8;
-                            put_char(c1);
-                            let c2 = (c >> 8) as u8;
-                            if c2 != 0 {
-                                put_char(c2);
-                            }
-                            REG[Register::RR0] += 1;
-                            c = mem_read(REG[Register::RR0]);
-                        }
-                    }
-                    Trap::HALT => {
-                        println!("HALT");
-                        running = 0;
-                    }
-                    _ => {
-                        println!("Unknown trap code");
-                        running = 0;
-                    }
+        }
+        entry = objects.first().map(|object| object.origin);
+        // each object is loaded on its own, leaving the memory between them
+        let loaded = link(objects).map_err(|e| e.to_string()).and_then(|image| {
+            image
+                .segments
+                .iter()
+                .map(|(origin, words)| vm.load_words(*origin, words).map_err(|e| e.to_string()))
+                .collect::<Result<Vec<_>, _>>()
+        });
+        match loaded {
+            Ok(segments) => regions.extend(segments),
+            Err(e) => {
+                eprintln!("failed to link: {}", e);
+                std::process::exit(1);
+            }
+        }
+    } else if let Some(path) = options.asm.first() {
+        let assembled = read_source(path)
+            .map_err(|e| e.to_string())
+            .and_then(|source| {
+                let assembly = assemble_program(&source).map_err(|e| e.to_string())?;
+                if let Some(listing) = &options.listing {
+                    std::fs::write(listing, assembly.listing(&source))
+                        .map_err(|e| format!("{}: {}", listing, e))?;
                 }
+                vm.load_words(assembly.origin, &assembly.words)
+                    .map_err(|e| e.to_string())
+            });
+        match assembled {
+            Ok(region) => regions.push(region),
+            Err(e) => {
+                eprintln!("failed to assemble {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    #[cfg(feature = "elf")]
+    if let Some(path) = &options.elf {
+        let loaded = std::fs::read(path)
+            .map_err(rust_vm::LoadError::from)
+            .and_then(|bytes| vm.load_elf(&bytes));
+        match loaded {
+            Ok((start, segments)) => {
+                entry = entry.or(Some(start));
+                regions.extend(segments);
+            }
+            Err(e) => {
+                eprintln!("failed to load {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    for path in &options.fill_mem {
+        let filled = std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| vm.load_memory_dump(&text).map_err(|e| e.to_string()));
+        if let Err(e) = filled {
+            eprintln!("failed to load {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+
+    for &(code, addr) in &options.trap_vectors {
+        vm.set_trap_vector(code, addr);
+    }
+
+    // start at the origin of the last image loaded unless told otherwise
+    let last_origin = regions.last().map(|region| region.start as u16);
+    if let Some(pc) = options.pc.or(entry).or(last_origin) {
+        vm.reg[Register::RPC as usize] = pc;
+    }
+
+    let loaded = regions.clone();
+    if options.trace_disasm {
+        let pc = vm.reg[Register::RPC as usize];
+        for region in regions {
+            // regions the program does not start in are followed from
+            // their first word
+            let entry = if region.contains(&(pc as usize)) {
+                pc
+            } else {
+                region.start as u16
+            };
+            print!(
+                "{}",
+                disasm::disassemble_reachable(
+                    &vm.memory,
+                    region,
+                    entry,
+                    &vm.symbols,
+                    vm.overflow_flag,
+                )
+            );
+        }
+        return;
+    }
+    if options.disasm {
+        for region in regions {
+            print!(
+                "{}",
+                disasm::disassemble_range(
+                    &vm.memory,
+                    region,
+                    options.data_as_hex,
+                    &vm.symbols,
+                    vm.overflow_flag,
+                )
+            );
+        }
+        return;
+    }
+    if options.lint {
+        let mut found = false;
+        for region in regions {
+            for lint in lint::analyze(&vm.memory, region, vm.overflow_flag) {
+                println!("{}", lint);
+                found = true;
+            }
+        }
+        std::process::exit(found as i32);
+    }
+    if let Some(path) = &options.log_json {
+        let mut log = match std::fs::File::create(path) {
+            Ok(file) => ExecLog::new(std::io::BufWriter::new(file)),
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                std::process::exit(1);
+            }
+        };
+        let mut failed = false;
+        // report the first write error and stop logging
+        vm.set_hook(Box::new(move |event| {
+            if failed {
+                return;
+            }
+            if let Err(e) = log.event(event) {
+                eprintln!("execution log: {}", e);
+                failed = true;
+            }
+        }));
+    }
+    if let Some(port) = options.gdb {
+        if let Err(e) = gdbstub::serve(&mut vm, port) {
+            eprintln!("gdb stub: {}", e);
+            std::process::exit(1);
+        }
+        if let Some(path) = &options.dump_obj {
+            write_loaded_obj(&vm, &loaded, path);
+        }
+        return;
+    }
+    if options.debug {
+        // the prompt needs line editing and echo, so stay in cooked mode
+        debugger::run(&mut vm);
+        if let Some(path) = &options.dump_obj {
+            write_loaded_obj(&vm, &loaded, path);
+        }
+        return;
+    }
+    // --dump-on-halt marks the registers the program changed
+    let initial = vm.reg;
+    let raw_mode = terminal::RawMode::enable();
+    let mut exit_code = 0;
+    let halted = match options.max_instructions {
+        Some(limit) => match vm.run_with_limit(limit) {
+            RunOutcome::Halted => true,
+            RunOutcome::LimitReached => {
+                eprintln!("instruction limit of {} reached", limit);
+                exit_code = 1;
+                false
             }
-            Instruction::RES => {
-                println!("RES");
-                running = 0;
+            RunOutcome::Stopped(_) => false,
+            RunOutcome::Error(e) => {
+                report_error(&vm, e);
+                exit_code = 1;
+                false
             }
-            Instruction::RTI => {
-                println!("RTI");
-                running = 0;
+        },
+        None => match vm.run() {
+            Ok(result) => result == StepResult::Halted,
+            Err(e) => {
+                report_error(&vm, e);
+                exit_code = 1;
+                false
             }
-            Instruction::BAD => {
-                println!("BAD");
-                running = 0;
+        },
+    };
+    vm.flush_output();
+    // dropping the hook writes out the rest of the execution log
+    vm.clear_hook();
+    if halted && options.dump_on_halt {
+        print!("{}", vm.dump_registers_since(&initial));
+    }
+    if halted && options.exit_from_r0 {
+        exit_code = (vm.reg[Register::RR0 as usize] & 0xFF) as i32;
+    }
+    if options.stats {
+        eprint!("{}", vm.stats());
+    }
+    if options.cycles {
+        eprintln!("cycles: {}", vm.cycles());
+    }
+    if options.coverage {
+        eprintln!("executed:");
+        for (first, last) in vm.executed_ranges() {
+            if first == last {
+                eprintln!("  x{:04X}", first);
+            } else {
+                eprintln!("  x{:04X}-x{:04X}", first, last);
             }
         }
     }
+    if let Some(n) = options.profile {
+        eprintln!("hot addresses:");
+        for (addr, count) in vm.hot_addresses(n) {
+            let instr = vm.memory[addr as usize];
+            eprintln!(
+                "  x{:04X}  {:>10}  {}",
+                addr,
+                count,
+                disasm::disassemble_with_symbols(instr, addr, &vm.symbols, vm.overflow_flag)
+            );
+        }
+    }
+    #[cfg(feature = "display")]
+    if options.display {
+        print!("{}", vm.render_display());
+    }
+    if let Some((start, len)) = options.dump_mem {
+        print!("{}", vm.dump_memory(start, len));
+    }
+    if let Some(path) = &options.dump_obj {
+        write_loaded_obj(&vm, &loaded, path);
+    }
+    drop(raw_mode);
+    std::process::exit(exit_code);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> impl Iterator<Item = String> {
+        list.iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn parse_args_collects_images_in_order() {
+        let options = parse_args(args(&["os.obj", "--endian", "little", "user.obj"])).unwrap();
+        assert_eq!(options.images, vec!["os.obj", "user.obj"]);
+        assert_eq!(options.load.endian, Some(Endian::Little));
+    }
+
+    #[test]
+    fn parse_args_requires_an_image() {
+        assert!(parse_args(args(&[])).is_err());
+        assert!(parse_args(args(&["--bogus", "a.obj"])).is_err());
+    }
+
+    #[test]
+    fn parse_args_reads_the_instruction_limit() {
+        let options = parse_args(args(&["--max-instructions", "1000", "a.obj"])).unwrap();
+        assert_eq!(options.max_instructions, Some(1000));
+        assert!(parse_args(args(&["--max-instructions", "lots", "a.obj"])).is_err());
+    }
+
+    #[test]
+    fn parse_args_reads_the_load_address() {
+        let options = parse_args(args(&["--load-at", "x4000", "--raw", "a.bin"])).unwrap();
+        assert_eq!(options.load.load_at, Some(0x4000));
+        assert!(options.load.raw);
+        assert!(parse_args(args(&["--load-at", "xZZ", "a.bin"])).is_err());
+    }
+
+    #[test]
+    fn raw_images_do_not_need_a_load_address() {
+        let options = parse_args(args(&["--raw", "a.bin"])).unwrap();
+        assert!(options.load.raw);
+        assert_eq!(options.load.load_at, None);
+    }
+
+    #[test]
+    fn parse_args_reads_the_dump_range() {
+        let options = parse_args(args(&["--dump-mem", "x4000:x10", "a.obj"])).unwrap();
+        assert_eq!(options.dump_mem, Some((0x4000, 0x10)));
+        assert!(parse_args(args(&["--dump-mem", "x4000", "a.obj"])).is_err());
+    }
+
+    #[test]
+    fn parse_args_reads_the_unsigned_flag() {
+        assert!(!parse_args(args(&["a.obj"])).unwrap().unsigned);
+        assert!(parse_args(args(&["--unsigned", "a.obj"])).unwrap().unsigned);
+    }
+
+    #[test]
+    fn parse_args_reads_the_cycles_flag() {
+        assert!(!parse_args(args(&["a.obj"])).unwrap().cycles);
+        assert!(parse_args(args(&["--cycles", "a.obj"])).unwrap().cycles);
+    }
+
+    #[test]
+    fn parse_args_reads_the_gdb_port() {
+        let options = parse_args(args(&["--gdb", "1234", "a.obj"])).unwrap();
+        assert_eq!(options.gdb, Some(1234));
+        assert!(parse_args(args(&["--gdb", "x", "a.obj"])).is_err());
+    }
+
+    #[test]
+    fn parse_args_accepts_asm_without_an_image() {
+        let options = parse_args(args(&["--asm", "prog.asm"])).unwrap();
+        assert_eq!(options.asm, vec!["prog.asm"]);
+        assert!(options.images.is_empty());
+        assert!(parse_args(args(&["--asm"])).is_err());
+    }
+
+    #[test]
+    fn parse_args_reads_the_seed() {
+        let options = parse_args(args(&["--seed", "1234", "a.obj"])).unwrap();
+        assert_eq!(options.seed, Some(1234));
+        assert!(parse_args(args(&["--seed", "-1", "a.obj"])).is_err());
+    }
+
+    #[test]
+    fn address_flags_take_any_number_format() {
+        let options =
+            parse_args(args(&["--pc", "0x3000", "--load-at", "#12288", "a.obj"])).unwrap();
+        assert_eq!(options.pc, Some(0x3000));
+        assert_eq!(options.load.load_at, Some(0x3000));
+        let options = parse_args(args(&["--dump-mem", "b100:#16", "a.obj"])).unwrap();
+        assert_eq!(options.dump_mem, Some((4, 16)));
+        assert!(parse_args(args(&["--pc", "x10000", "a.obj"])).is_err());
+    }
+
+    #[test]
+    fn address_flags_refuse_bare_numbers() {
+        let e = parse_args(args(&["--pc", "3000", "a.obj"])).err().unwrap();
+        assert!(
+            e.contains("write x3000 for hex or #3000 for decimal"),
+            "{}",
+            e
+        );
+        for flags in [
+            ["--load-at", "12288"],
+            ["--dump-mem", "x3000:10"],
+            ["--dump-mem", "3000:x10"],
+            ["--trap-vector", "25=x4000"],
+            ["--trap-vector", "x25=4000"],
+        ] {
+            let e = parse_args(args(&[flags[0], flags[1], "a.obj"]))
+                .err()
+                .unwrap();
+            assert!(e.contains("has no prefix"), "{}", e);
+        }
+    }
+
+    #[test]
+    fn parse_args_reads_the_start_address() {
+        let options = parse_args(args(&["--pc", "x0200", "os.obj"])).unwrap();
+        assert_eq!(options.pc, Some(0x0200));
+        assert!(parse_args(args(&["--pc", "xZZZZ", "os.obj"])).is_err());
+    }
+
+    #[test]
+    fn parse_args_reads_the_eof_behavior() {
+        let options = parse_args(args(&["--on-eof", "zero", "a.obj"])).unwrap();
+        assert_eq!(options.on_eof, EofBehavior::Zero);
+        assert!(parse_args(args(&["--on-eof", "panic", "a.obj"])).is_err());
+    }
+
+    #[test]
+    fn parse_args_reads_the_exit_from_r0_flag() {
+        assert!(
+            parse_args(args(&["--exit-from-r0", "a.obj"]))
+                .unwrap()
+                .exit_from_r0
+        );
+        assert!(!parse_args(args(&["a.obj"])).unwrap().exit_from_r0);
+    }
+
+    #[test]
+    fn parse_args_needs_asm_for_a_listing() {
+        let options = parse_args(args(&["--asm", "p.asm", "--listing", "p.lst"])).unwrap();
+        assert_eq!(options.listing.as_deref(), Some("p.lst"));
+        assert!(parse_args(args(&["--listing", "p.lst", "a.obj"])).is_err());
+        let linked = [
+            "--asm",
+            "main.asm",
+            "--asm",
+            "lib.asm",
+            "--listing",
+            "p.lst",
+        ];
+        assert!(parse_args(args(&linked)).is_err());
+    }
+
+    #[test]
+    fn parse_args_reads_lea_sets_cc() {
+        assert!(!parse_args(args(&["a.obj"])).unwrap().lea_sets_cc);
+        let options = parse_args(args(&["--lea-sets-cc", "on", "a.obj"])).unwrap();
+        assert!(options.lea_sets_cc);
+        assert!(parse_args(args(&["--lea-sets-cc", "yes", "a.obj"])).is_err());
+    }
+
+    #[test]
+    fn parse_args_reads_the_no_flush_flag() {
+        assert!(!parse_args(args(&["a.obj"])).unwrap().no_flush);
+        assert!(parse_args(args(&["--no-flush", "a.obj"])).unwrap().no_flush);
+    }
+
+    #[test]
+    fn parse_args_reads_the_protect_vectors_flag() {
+        assert!(!parse_args(args(&["a.obj"])).unwrap().protect_vectors);
+        assert!(
+            parse_args(args(&["--protect-vectors", "a.obj"]))
+                .unwrap()
+                .protect_vectors
+        );
+    }
+
+    #[test]
+    fn parse_args_reads_the_no_color_flag() {
+        assert!(!parse_args(args(&["a.obj"])).unwrap().no_color);
+        assert!(parse_args(args(&["--no-color", "a.obj"])).unwrap().no_color);
+    }
+
+    #[test]
+    fn parse_args_collects_every_asm_file() {
+        let options = parse_args(args(&["--asm", "main.asm", "--asm", "lib.asm"])).unwrap();
+        assert_eq!(options.asm, vec!["main.asm", "lib.asm"]);
+    }
+
+    #[test]
+    fn parse_args_reads_trap_vectors() {
+        let options = parse_args(args(&[
+            "--trap-vector",
+            "x25=x1000",
+            "--trap-vector",
+            "x21=x1100",
+            "a.obj",
+        ]))
+        .unwrap();
+        assert_eq!(options.trap_vectors, vec![(0x25, 0x1000), (0x21, 0x1100)]);
+        assert!(parse_args(args(&["--trap-vector", "x100=x1000", "a.obj"])).is_err());
+        assert!(parse_args(args(&["--trap-vector", "x25", "a.obj"])).is_err());
+    }
+
+    #[test]
+    fn parse_args_reads_the_diff_files() {
+        let options = parse_args(args(&["--diff", "a.snapshot", "b.snapshot"])).unwrap();
+        assert_eq!(
+            options.diff,
+            Some(("a.snapshot".to_string(), "b.snapshot".to_string()))
+        );
+        assert!(parse_args(args(&["--diff", "a.snapshot"])).is_err());
+    }
+
+    #[cfg(feature = "elf")]
+    #[test]
+    fn parse_args_accepts_elf_without_an_image() {
+        let options = parse_args(args(&["--elf", "prog.elf"])).unwrap();
+        assert_eq!(options.elf.as_deref(), Some("prog.elf"));
+        assert!(parse_args(args(&["--elf"])).is_err());
+    }
+
+    #[test]
+    fn parse_args_reads_the_memory_width() {
+        let options = parse_args(args(&["--mem-width", "4", "a.obj"])).unwrap();
+        assert_eq!(options.mem_width, Some(4));
+        assert!(parse_args(args(&["--mem-width", "0", "a.obj"])).is_err());
+        assert!(parse_args(args(&["--mem-width", "wide", "a.obj"])).is_err());
+    }
+
+    #[test]
+    fn parse_args_reads_the_quiet_flag() {
+        assert!(!parse_args(args(&["a.obj"])).unwrap().quiet);
+        assert!(parse_args(args(&["--quiet", "a.obj"])).unwrap().quiet);
+        assert!(parse_args(args(&["-q", "a.obj"])).unwrap().quiet);
+    }
+
+    #[test]
+    fn parse_args_collects_fill_mem_files() {
+        let options = parse_args(args(&[
+            "--fill-mem",
+            "a.hex",
+            "--fill-mem",
+            "b.hex",
+            "p.obj",
+        ]))
+        .unwrap();
+        assert_eq!(options.fill_mem, vec!["a.hex", "b.hex"]);
+        assert!(parse_args(args(&["p.obj", "--fill-mem"])).is_err());
+    }
+
+    #[test]
+    fn parse_args_reads_the_newline_translation() {
+        assert_eq!(parse_args(args(&["a.obj"])).unwrap().newline, Newline::AsIs);
+        let options = parse_args(args(&["--newline", "crlf", "a.obj"])).unwrap();
+        assert_eq!(options.newline, Newline::Crlf);
+        assert!(parse_args(args(&["--newline", "cr", "a.obj"])).is_err());
+    }
+
+    #[test]
+    fn parse_args_reads_the_unknown_trap_behavior() {
+        let options = parse_args(args(&["a.obj"])).unwrap();
+        assert_eq!(options.on_unknown_trap, UnknownTrap::Halt);
+        let options = parse_args(args(&["--unknown-trap", "ignore", "a.obj"])).unwrap();
+        assert_eq!(options.on_unknown_trap, UnknownTrap::Ignore);
+        let options = parse_args(args(&["--strict", "a.obj"])).unwrap();
+        assert_eq!(options.on_unknown_trap, UnknownTrap::Error);
+        assert!(parse_args(args(&["--unknown-trap", "skip", "a.obj"])).is_err());
+    }
+
+    #[test]
+    fn parse_args_reads_the_profile_size() {
+        let options = parse_args(args(&["--profile", "5", "prog.obj"])).unwrap();
+        assert_eq!(options.profile, Some(5));
+        assert!(parse_args(args(&["--profile", "lots", "prog.obj"])).is_err());
+    }
+
+    #[test]
+    fn parse_args_takes_stdin_for_at_most_one_asm() {
+        let options = parse_args(args(&["--asm", "-"])).unwrap();
+        assert_eq!(options.asm, vec!["-"]);
+        assert!(parse_args(args(&["--asm", "-", "--asm", "-"])).is_err());
+    }
+
+    #[test]
+    fn parse_args_enables_the_overflow_flag() {
+        assert!(!parse_args(args(&["prog.obj"])).unwrap().overflow_flag);
+        let options = parse_args(args(&["--enable-overflow-flag", "prog.obj"])).unwrap();
+        assert!(options.overflow_flag);
+    }
+
+    #[test]
+    fn parse_args_reads_trace_disasm() {
+        assert!(
+            parse_args(args(&["--trace-disasm", "prog.obj"]))
+                .unwrap()
+                .trace_disasm
+        );
+    }
+
+    #[test]
+    fn step_on_start_starts_the_debugger() {
+        assert!(
+            parse_args(args(&["--step-on-start", "prog.obj"]))
+                .unwrap()
+                .debug
+        );
+    }
+
+    #[test]
+    fn parse_args_reads_abi_names() {
+        assert!(
+            parse_args(args(&["--abi-names", "prog.obj"]))
+                .unwrap()
+                .abi_names
+        );
+    }
+
+    #[test]
+    fn parse_args_reads_the_dump_obj_path() {
+        let options = parse_args(args(&["--dump-obj", "out.obj", "prog.obj"])).unwrap();
+        assert_eq!(options.dump_obj.as_deref(), Some("out.obj"));
+        assert!(parse_args(args(&["prog.obj", "--dump-obj"])).is_err());
+    }
 }
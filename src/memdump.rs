@@ -0,0 +1,153 @@
+// Plain-text memory listings: one `xADDR: xWORD xWORD ... |gutter|` line per
+// row of `Vm::mem_width` words, the gutter showing each word's low byte as a
+// character like a hex editor does. The loader accepts the same format,
+// with or without the gutter.
+
+use crate::isa::Register;
+use crate::style::{paint, Style};
+use crate::vm::{Vm, MEMORY_SIZE};
+
+pub const DEFAULT_MEM_WIDTH: usize = 8;
+
+// the low byte as a printable character, or `.`
+fn gutter_char(word: u16) -> char {
+    match (word & 0xFF) as u8 {
+        c @ 0x20..=0x7E => c as char,
+        _ => '.',
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct DumpParseError {
+    pub line: usize,
+}
+
+impl std::fmt::Display for DumpParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "malformed memory dump on line {}", self.line)
+    }
+}
+
+fn parse_hex(s: &str) -> Option<u16> {
+    u16::from_str_radix(s.strip_prefix('x')?, 16).ok()
+}
+
+impl Vm {
+    // the word at the PC is highlighted when color is on
+    pub fn dump_memory(&self, start: u16, len: usize) -> String {
+        let width = self.mem_width.max(1);
+        let start = start as usize;
+        let end = (start + len).min(MEMORY_SIZE);
+        let pc = self.reg[Register::RPC as usize] as usize;
+        let mut out = String::new();
+        for row in (start..end).step_by(width) {
+            let addrs = row..(row + width).min(end);
+            let mut words: Vec<String> = addrs
+                .clone()
+                .map(|addr| {
+                    let word = format!("x{:04X}", self.memory[addr]);
+                    if addr == pc {
+                        paint(&word, Style::Current, self.color)
+                    } else {
+                        word
+                    }
+                })
+                .collect();
+            // keep the gutters of a short last row in line
+            words.resize(width, "     ".to_string());
+            let gutter: String = addrs.map(|addr| gutter_char(self.memory[addr])).collect();
+            out.push_str(&format!("x{:04X}: {} |{}|\n", row, words.join(" "), gutter));
+        }
+        out
+    }
+
+    // write the words of a `dump_memory` listing back into memory
+    pub fn load_memory_dump(&mut self, text: &str) -> Result<(), DumpParseError> {
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = || DumpParseError { line: i + 1 };
+            let (addr, words) = line.split_once(':').ok_or_else(error)?;
+            let words = words.split('|').next().unwrap_or_default();
+            let addr = parse_hex(addr.trim()).ok_or_else(error)? as usize;
+            for (addr, word) in (addr..).zip(words.split_whitespace()) {
+                if addr >= MEMORY_SIZE {
+                    return Err(error());
+                }
+                self.memory[addr] = parse_hex(word).ok_or_else(error)?;
+                #[cfg(feature = "debug_mem")]
+                {
+                    self.initialized[addr] = true;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dump_memory_lists_eight_words_per_line() {
+        let mut vm = Vm::new();
+        for i in 0..10 {
+            vm.memory[0x4000 + i] = 0x1000 + i as u16;
+        }
+        assert_eq!(
+            vm.dump_memory(0x4000, 10),
+            "x4000: x1000 x1001 x1002 x1003 x1004 x1005 x1006 x1007 |........|\n\
+             x4008: x1008 x1009                                     |..|\n"
+        );
+        // the missing words are padded so the gutter lines up
+        assert_eq!(
+            vm.dump_memory(0xFFFF, 4),
+            format!("xFFFF: x0000{} |.|\n", " ".repeat(7 * 6))
+        );
+    }
+
+    #[test]
+    fn dump_memory_honors_the_width_and_shows_printable_bytes() {
+        let mut vm = Vm::new();
+        vm.mem_width = 3;
+        for (i, c) in "Hi, LC-3".bytes().enumerate() {
+            vm.memory[0x4000 + i] = c as u16;
+        }
+        vm.memory[0x4001] |= 0x7F00; // only the low byte shows
+        assert_eq!(
+            vm.dump_memory(0x4000, 8),
+            "x4000: x0048 x7F69 x002C |Hi,|\n\
+             x4003: x0020 x004C x0043 | LC|\n\
+             x4006: x002D x0033       |-3|\n"
+        );
+    }
+
+    #[test]
+    fn memory_dumps_round_trip_through_the_loader() {
+        let mut vm = Vm::new();
+        for i in 0..20 {
+            vm.memory[0x3100 + i] = (i as u16).wrapping_mul(0x0F0F);
+        }
+        let text = vm.dump_memory(0x3100, 20);
+        let mut copy = Vm::new();
+        copy.load_memory_dump(&text).unwrap();
+        assert_eq!(&copy.memory[0x3100..0x3114], &vm.memory[0x3100..0x3114]);
+        assert_eq!(copy.memory[0x3114], 0);
+
+        // a gutter character that looks like a word is not read as one
+        copy.load_memory_dump("x5000: x0031 x0078 |1x|\n").unwrap();
+        assert_eq!(copy.memory[0x5000..0x5003], [0x0031, 0x0078, 0]);
+    }
+
+    #[test]
+    fn load_memory_dump_reports_the_bad_line() {
+        let mut vm = Vm::new();
+        let result = vm.load_memory_dump("x3000: x0001\n\nx3001 x0002\n");
+        assert_eq!(result, Err(DumpParseError { line: 3 }));
+        let result = vm.load_memory_dump("xFFFF: x0001 x0002\n");
+        assert_eq!(result, Err(DumpParseError { line: 1 }));
+    }
+}
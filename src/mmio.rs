@@ -0,0 +1,130 @@
+// Memory-mapped devices. The VM hands loads and stores in a device's range
+// to the device instead of RAM, checking the built-in keyboard first and
+// then the devices registered with `Vm::add_device` in the order they were
+// added. The last value read or written through a device is kept in
+// `Vm::memory` as well, so dumps and snapshots show the device registers.
+
+use std::ops::Range;
+
+use crate::isa::{MemoryMappedRegister, KBSR_READY};
+
+pub trait MmioDevice {
+    // addresses the device answers for
+    fn range(&self) -> Range<u16>;
+    fn read(&mut self, addr: u16) -> u16;
+    fn write(&mut self, addr: u16, val: u16);
+}
+
+// the keyboard status and data registers. The VM latches keys from its
+// input into `data` with `latch`, since where they come from (the terminal
+// or a headless buffer) is up to the VM.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct Keyboard {
+    pub(crate) status: u16,
+    pub(crate) data: u16,
+}
+
+impl Keyboard {
+    pub(crate) fn ready(&self) -> bool {
+        self.status & KBSR_READY != 0
+    }
+
+    pub(crate) fn latch(&mut self, c: u8) {
+        self.data = c as u16;
+        self.status |= KBSR_READY;
+    }
+}
+
+impl MmioDevice for Keyboard {
+    fn range(&self) -> Range<u16> {
+        MemoryMappedRegister::KBSR as u16..MemoryMappedRegister::KBDR as u16 + 1
+    }
+
+    fn read(&mut self, addr: u16) -> u16 {
+        if addr == MemoryMappedRegister::KBSR as u16 {
+            self.status
+        } else if addr == MemoryMappedRegister::KBDR as u16 {
+            // reading the data register consumes the key
+            self.status &= !KBSR_READY;
+            self.data
+        } else {
+            0
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u16) {
+        if addr == MemoryMappedRegister::KBSR as u16 {
+            self.status = val;
+        } else if addr == MemoryMappedRegister::KBDR as u16 {
+            self.data = val;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::vm::Vm;
+
+    // answers reads with 0x0100 plus the address offset and logs every access
+    struct Mock {
+        log: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl MmioDevice for Mock {
+        fn range(&self) -> Range<u16> {
+            0xFE20..0xFE22
+        }
+
+        fn read(&mut self, addr: u16) -> u16 {
+            self.log.borrow_mut().push(format!("read x{:04X}", addr));
+            0x0100 + (addr - 0xFE20)
+        }
+
+        fn write(&mut self, addr: u16, val: u16) {
+            self.log
+                .borrow_mut()
+                .push(format!("write x{:04X} x{:04X}", addr, val));
+        }
+    }
+
+    #[test]
+    fn loads_and_stores_in_a_device_range_go_to_the_device() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut vm = Vm::new();
+        vm.add_device(Box::new(Mock { log: log.clone() }));
+        vm.reg[1] = 0xFE20;
+        vm.reg[2] = 0x00AB;
+        // STR R2, R1, #1; LDR R0, R1, #0; LDR R3, R1, #2
+        vm.memory[0x3000..0x3003].copy_from_slice(&[0x7441, 0x6040, 0x6642]);
+        vm.memory[0xFE22] = 0x5555;
+        for _ in 0..3 {
+            vm.step().unwrap();
+        }
+        assert_eq!(*log.borrow(), ["write xFE21 x00AB", "read xFE20"]);
+        assert_eq!(vm.reg[0], 0x0100);
+        // xFE22 is outside the device and still RAM
+        assert_eq!(vm.reg[3], 0x5555);
+        // memory mirrors what went through the device
+        assert_eq!(vm.memory[0xFE20..0xFE22], [0x0100, 0x00AB]);
+    }
+
+    #[test]
+    fn the_keyboard_clears_ready_when_its_data_is_read() {
+        let mut keyboard = Keyboard::default();
+        assert!(!keyboard.ready());
+        keyboard.latch(b'a');
+        assert!(keyboard.ready());
+        assert_eq!(keyboard.read(MemoryMappedRegister::KBSR as u16), KBSR_READY);
+        assert_eq!(
+            keyboard.read(MemoryMappedRegister::KBDR as u16),
+            b'a' as u16
+        );
+        assert!(!keyboard.ready());
+        keyboard.write(MemoryMappedRegister::KBSR as u16, 0x4000);
+        assert_eq!(keyboard.status, 0x4000);
+    }
+}
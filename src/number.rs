@@ -0,0 +1,98 @@
+// Numeric literals shared by the assembler, the debugger and the command
+// line: `x3000`/`0x3000` hex, `b1010`/`0b1010` binary, and `#-5` or bare
+// `12` decimal. A minus sign may follow the prefix, as in `x-1`.
+
+#[derive(Debug, PartialEq)]
+pub enum NumberError {
+    Invalid,
+    // does not fit in 16 bits, signed or unsigned
+    OutOfRange,
+}
+
+impl std::fmt::Display for NumberError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            NumberError::Invalid => write!(f, "not a number"),
+            NumberError::OutOfRange => write!(f, "out of range for 16 bits"),
+        }
+    }
+}
+
+// the literal's value, keeping its sign; accepts -32768 up to 65535
+pub fn parse_signed(s: &str) -> Result<i32, NumberError> {
+    let prefixed = |prefixes: &[&str]| prefixes.iter().find_map(|p| s.strip_prefix(p));
+    let (digits, radix) = if let Some(hex) = prefixed(&["0x", "0X", "x", "X"]) {
+        (hex, 16)
+    } else if let Some(bin) = prefixed(&["0b", "0B", "b", "B"]) {
+        (bin, 2)
+    } else {
+        (s.strip_prefix('#').unwrap_or(s), 10)
+    };
+    let (negative, digits) = match digits.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, digits),
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return Err(NumberError::Invalid);
+    }
+    let magnitude = i64::from_str_radix(digits, radix).map_err(|_| NumberError::OutOfRange)?;
+    let value = if negative { -magnitude } else { magnitude };
+    if (-0x8000..=0xFFFF).contains(&value) {
+        Ok(value as i32)
+    } else {
+        Err(NumberError::OutOfRange)
+    }
+}
+
+// the literal as a 16-bit word, negative values in two's complement
+pub fn parse_number(s: &str) -> Result<u16, NumberError> {
+    parse_signed(s).map(|value| value as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hex() {
+        assert_eq!(parse_number("x3000"), Ok(0x3000));
+        assert_eq!(parse_number("0x3000"), Ok(0x3000));
+        assert_eq!(parse_number("XFFFF"), Ok(0xFFFF));
+        assert_eq!(parse_number("x-1"), Ok(0xFFFF));
+    }
+
+    #[test]
+    fn parses_decimal() {
+        assert_eq!(parse_number("#12"), Ok(12));
+        assert_eq!(parse_number("12"), Ok(12));
+        assert_eq!(parse_number("#-5"), Ok(0xFFFB));
+        assert_eq!(parse_signed("-5"), Ok(-5));
+        assert_eq!(parse_number("65535"), Ok(0xFFFF));
+        assert_eq!(parse_number("#-32768"), Ok(0x8000));
+    }
+
+    #[test]
+    fn parses_binary() {
+        assert_eq!(parse_number("b1010"), Ok(10));
+        assert_eq!(parse_number("0b1010"), Ok(10));
+        assert_eq!(parse_signed("b-1"), Ok(-1));
+    }
+
+    #[test]
+    fn rejects_values_outside_16_bits() {
+        assert_eq!(parse_number("x10000"), Err(NumberError::OutOfRange));
+        assert_eq!(parse_number("65536"), Err(NumberError::OutOfRange));
+        assert_eq!(parse_number("#-32769"), Err(NumberError::OutOfRange));
+        assert_eq!(
+            parse_number("99999999999999999999"),
+            Err(NumberError::OutOfRange)
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_literals() {
+        for s in ["", "x", "#", "-", "b102", "xG", "1.5", "R1", "LOOP"] {
+            assert_eq!(parse_number(s), Err(NumberError::Invalid), "{:?}", s);
+        }
+    }
+}
@@ -0,0 +1,52 @@
+// ANSI colors for register and memory dumps. Everything is painted through
+// `paint` so that a single switch, `Vm::color`, turns it all off.
+
+use crate::terminal::stdout_is_tty;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Style {
+    // a register the last instruction changed
+    Changed,
+    // a value with bit 15 set
+    Negative,
+    // the word at the PC
+    Current,
+}
+
+impl Style {
+    fn code(self) -> &'static str {
+        match self {
+            Style::Changed => "\x1b[33m",
+            Style::Negative => "\x1b[31m",
+            Style::Current => "\x1b[7m",
+        }
+    }
+}
+
+pub fn paint(text: &str, style: Style, enabled: bool) -> String {
+    if enabled {
+        format!("{}{}\x1b[0m", style.code(), text)
+    } else {
+        text.to_string()
+    }
+}
+
+// color by default, unless NO_COLOR is set to something or stdout is
+// redirected
+pub fn color_wanted() -> bool {
+    std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()) && stdout_is_tty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paint_wraps_text_only_when_enabled() {
+        assert_eq!(
+            paint("x8000", Style::Negative, true),
+            "\x1b[31mx8000\x1b[0m"
+        );
+        assert_eq!(paint("x8000", Style::Negative, false), "x8000");
+    }
+}
@@ -0,0 +1,85 @@
+// Reader for the symbol tables lc3as writes next to its object files:
+//
+//     // Symbol table
+//     // Scope level 0:
+//     //	Symbol Name       Page Address
+//     //	----------------  ------------
+//     //	LOOP              3002
+
+use std::collections::HashMap;
+use std::fs;
+
+#[derive(Debug)]
+pub enum SymError {
+    Io(std::io::Error),
+    Malformed { line: usize },
+}
+
+impl From<std::io::Error> for SymError {
+    fn from(e: std::io::Error) -> SymError {
+        SymError::Io(e)
+    }
+}
+
+impl std::fmt::Display for SymError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SymError::Io(e) => write!(f, "{}", e),
+            SymError::Malformed { line } => write!(f, "malformed symbol entry on line {}", line),
+        }
+    }
+}
+
+// map of address to label
+pub fn parse_symbols(text: &str) -> Result<HashMap<u16, String>, SymError> {
+    let mut symbols = HashMap::new();
+    let mut in_table = false;
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim_start_matches('/').trim();
+        if line.is_empty() {
+            continue;
+        }
+        if !in_table {
+            // everything up to the dashed separator is header
+            in_table = line.starts_with('-');
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let entry = match (fields.next(), fields.next(), fields.next()) {
+            (Some(name), Some(addr), None) => u16::from_str_radix(addr, 16)
+                .ok()
+                .map(|addr| (addr, name.to_string())),
+            _ => None,
+        };
+        let (addr, name) = entry.ok_or(SymError::Malformed { line: i + 1 })?;
+        symbols.insert(addr, name);
+    }
+    Ok(symbols)
+}
+
+pub fn load_symbols(path: &str) -> Result<HashMap<u16, String>, SymError> {
+    parse_symbols(&fs::read_to_string(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_symbols_reads_an_lc3as_table() {
+        let path = format!("{}/tests/fixtures/hello.sym", env!("CARGO_MANIFEST_DIR"));
+        let symbols = load_symbols(&path).unwrap();
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols[&0x3000], "MAIN");
+        assert_eq!(symbols[&0x3003], "MSG");
+    }
+
+    #[test]
+    fn parse_symbols_reports_the_malformed_line() {
+        let text = "//\tSymbol Name  Page Address\n//\t-----  -----\n//\tLOOP\n";
+        assert!(matches!(
+            parse_symbols(text),
+            Err(SymError::Malformed { line: 3 })
+        ));
+    }
+}
@@ -0,0 +1,268 @@
+// Terminal handling for the keyboard device and the GETC/IN/OUT traps.
+//
+// When stdin is a TTY it is switched to non-canonical, no-echo mode so that
+// single key presses reach the VM immediately. When stdin is not a TTY (piped
+// input, files, test harnesses) the terminal settings are left untouched and
+// bytes are read from the stream as they are: `check_key` reports a key as
+// soon as there is unread input (or EOF), and `read_char` consumes one byte.
+// Input is read a block at a time into a shared buffer that both of them,
+// and `read_line`, look at first.
+//
+// wasm32 has no terminal: there is never a key waiting and input is always
+// at EOF, so the VM must run with buffered I/O (`Vm::set_input`) there.
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::{collections::VecDeque, sync::Mutex};
+
+#[cfg(not(target_arch = "wasm32"))]
+pub struct RawMode {
+    original: Option<libc::termios>,
+}
+
+#[cfg(target_arch = "wasm32")]
+pub struct RawMode;
+
+#[cfg(target_arch = "wasm32")]
+impl RawMode {
+    pub fn enable() -> RawMode {
+        RawMode
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl RawMode {
+    // Switches stdin to raw mode. The previous settings are restored when the
+    // returned guard is dropped, which also happens while unwinding a panic.
+    pub fn enable() -> RawMode {
+        unsafe {
+            if libc::isatty(libc::STDIN_FILENO) != 1 {
+                return RawMode { original: None };
+            }
+            let mut tio: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut tio) != 0 {
+                return RawMode { original: None };
+            }
+            let original = tio;
+            tio.c_lflag &= !(libc::ICANON | libc::ECHO);
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &tio);
+            RawMode {
+                original: Some(original),
+            }
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        if let Some(original) = self.original {
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &original);
+            }
+        }
+    }
+}
+
+// bytes read from stdin but not consumed yet; filled a block at a time so
+// piped input costs one syscall per block rather than per byte
+#[cfg(not(target_arch = "wasm32"))]
+static INPUT: Mutex<VecDeque<u8>> = Mutex::new(VecDeque::new());
+
+#[cfg(not(target_arch = "wasm32"))]
+const INPUT_BLOCK: usize = 4096;
+
+// non-blocking check for pending input on stdin
+#[cfg(not(target_arch = "wasm32"))]
+pub fn check_key() -> bool {
+    if !INPUT.lock().unwrap().is_empty() {
+        return true;
+    }
+    let mut fds = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    unsafe { libc::poll(&mut fds, 1, 0) > 0 }
+}
+
+// read whatever stdin has ready, up to a block, into `buf`; a TTY in raw
+// mode hands over a key press at a time, a pipe or file a full block. Only
+// a 0 return is end of input: a read interrupted by a signal is retried,
+// and a non-blocking stdin is waited on until it has something
+#[cfg(not(target_arch = "wasm32"))]
+fn read_block(buf: &mut [u8]) -> std::io::Result<usize> {
+    read_fd(libc::STDIN_FILENO, buf)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_fd(fd: libc::c_int, buf: &mut [u8]) -> std::io::Result<usize> {
+    loop {
+        let n = unsafe { libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if n >= 0 {
+            return Ok(n as usize);
+        }
+        let err = std::io::Error::last_os_error();
+        match err.kind() {
+            std::io::ErrorKind::Interrupted => {}
+            std::io::ErrorKind::WouldBlock => {
+                let mut fds = libc::pollfd {
+                    fd,
+                    events: libc::POLLIN,
+                    revents: 0,
+                };
+                unsafe { libc::poll(&mut fds, 1, -1) };
+            }
+            _ => return Err(err),
+        }
+    }
+}
+
+// next byte of stdin, refilling the buffer when it runs dry; std's own
+// buffering is bypassed so that `check_key` knows about every unread byte
+#[cfg(not(target_arch = "wasm32"))]
+fn read_byte() -> Option<u8> {
+    next_byte(&mut INPUT.lock().unwrap(), read_block).unwrap()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn next_byte(
+    buffer: &mut VecDeque<u8>,
+    mut fill: impl FnMut(&mut [u8]) -> std::io::Result<usize>,
+) -> std::io::Result<Option<u8>> {
+    if buffer.is_empty() {
+        let mut block = [0u8; INPUT_BLOCK];
+        let n = fill(&mut block)?;
+        buffer.extend(&block[..n]);
+    }
+    Ok(buffer.pop_front())
+}
+
+// whether stdout is an interactive terminal
+#[cfg(not(target_arch = "wasm32"))]
+pub fn stdout_is_tty() -> bool {
+    unsafe { libc::isatty(libc::STDOUT_FILENO) == 1 }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn stdout_is_tty() -> bool {
+    false
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn check_key() -> bool {
+    false
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read_byte() -> Option<u8> {
+    None
+}
+
+// next byte of input, or None at end of input
+pub fn read_char() -> Option<u8> {
+    read_byte()
+}
+
+// read a line without its terminator, or None at end of input
+pub fn read_line() -> Option<String> {
+    let mut line = Vec::new();
+    loop {
+        match read_byte() {
+            Some(b'\n') => break,
+            Some(byte) => line.push(byte),
+            None if line.is_empty() => return None,
+            None => break,
+        }
+    }
+    Some(
+        String::from_utf8_lossy(&line)
+            .trim_end_matches('\r')
+            .to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the local mode flags of stdin, or None when it is not a terminal
+    fn local_flags() -> Option<libc::tcflag_t> {
+        unsafe {
+            let mut tio: libc::termios = std::mem::zeroed();
+            (libc::tcgetattr(libc::STDIN_FILENO, &mut tio) == 0).then_some(tio.c_lflag)
+        }
+    }
+
+    #[test]
+    fn raw_mode_guard_restores_the_settings() {
+        let before = local_flags();
+        let guard = RawMode::enable();
+        // a non-TTY stdin (as under most test runners) is left alone
+        assert_eq!(guard.original.map(|tio| tio.c_lflag), before);
+        drop(guard);
+        assert_eq!(local_flags(), before);
+    }
+
+    #[test]
+    fn next_byte_reads_a_block_at_a_time() {
+        let input: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+        let mut unread = &input[..];
+        let mut reads = 0;
+        let mut fill = |block: &mut [u8]| {
+            reads += 1;
+            let n = block.len().min(unread.len());
+            block[..n].copy_from_slice(&unread[..n]);
+            unread = &unread[n..];
+            Ok(n)
+        };
+
+        let mut buffer = VecDeque::new();
+        let mut consumed = Vec::new();
+        while let Some(byte) = next_byte(&mut buffer, &mut fill).unwrap() {
+            consumed.push(byte);
+        }
+        assert_eq!(consumed, input);
+        // three blocks and the read that found the end
+        assert_eq!(reads, 10_000usize.div_ceil(INPUT_BLOCK) + 1);
+    }
+
+    #[test]
+    fn read_errors_are_not_end_of_input() {
+        let mut buffer = VecDeque::new();
+        let failed = next_byte(&mut buffer, |_| Err(std::io::ErrorKind::Other.into()));
+        assert!(failed.is_err());
+        assert_eq!(next_byte(&mut buffer, |_| Ok(0)).unwrap(), None);
+    }
+
+    #[test]
+    fn reads_interrupted_by_a_signal_are_retried() {
+        extern "C" fn ignore(_: libc::c_int) {}
+        unsafe {
+            let mut fds = [0; 2];
+            assert_eq!(libc::pipe(fds.as_mut_ptr()), 0);
+            // no SA_RESTART, so the blocked read fails with EINTR
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = ignore as *const () as usize;
+            let mut previous: libc::sigaction = std::mem::zeroed();
+            libc::sigaction(libc::SIGUSR1, &action, &mut previous);
+
+            let reader = libc::pthread_self();
+            let writer = fds[1];
+            let feeder = std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                libc::pthread_kill(reader, libc::SIGUSR1);
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                libc::write(writer, b"k".as_ptr() as *const libc::c_void, 1);
+            });
+            let mut block = [0u8; 4];
+            let read = read_fd(fds[0], &mut block);
+            feeder.join().unwrap();
+
+            libc::sigaction(libc::SIGUSR1, &previous, std::ptr::null_mut());
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+            assert_eq!(read.unwrap(), 1);
+            assert_eq!(block[0], b'k');
+        }
+    }
+}
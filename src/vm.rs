@@ -0,0 +1,2903 @@
+// The LC-3 virtual machine: memory, registers and the fetch/execute loop.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{BufWriter, Stdout, Write};
+
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::disasm;
+use crate::expr::{Condition, Expr};
+use crate::isa::{
+    decode, decode_trap, sign_extend, ConditionFlag, Exception, Instruction, MemoryMappedRegister,
+    Register, TrapCode, CYCLE_COSTS, KBSR_INTERRUPT_ENABLE, KEYBOARD_PRIORITY, KEYBOARD_VECTOR,
+};
+use crate::memdump::DEFAULT_MEM_WIDTH;
+use crate::mmio::{Keyboard, MmioDevice};
+use crate::style::{paint, Style};
+use crate::terminal::{check_key, read_char};
+
+pub const PC_START: u16 = 0x3000;
+pub const MEMORY_SIZE: usize = 1 << 16;
+
+// processor status register: bit 15 is the privilege mode (1 = user),
+// bits 10-8 the priority level and bits 2-0 the condition codes. Bit 3 is
+// the overflow flag of the `overflow_flag` extension.
+const PSR_USER: u16 = 1 << 15;
+const PSR_PRIORITY: u16 = 0x7 << 8;
+const PSR_OVERFLOW: u16 = 1 << 3;
+const PSR_COND: u16 = 0x7;
+const SSP_START: u16 = 0x3000;
+const INTERRUPT_VECTOR_TABLE: u16 = 0x0100;
+// end of the trap and interrupt vector tables, and start of the device
+// registers; user mode may not write below or above these with
+// `protect_vectors` set
+const VECTORS_END: u16 = 0x0200;
+const DEVICES_START: u16 = 0xFE00;
+// code below this is the OS's: trap service routines run in user mode and
+// return with RET, so their stores are exempt from `protect_vectors`
+const USER_SPACE_START: u16 = 0x3000;
+
+// most instructions `step_back` can undo
+const HISTORY_LIMIT: usize = 10_000;
+
+const SNAPSHOT_MAGIC: &[u8; 4] = b"LC3S";
+const SNAPSHOT_VERSION: u8 = 2;
+
+#[derive(Debug, PartialEq)]
+pub enum SnapshotError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+}
+
+impl std::fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SnapshotError::BadMagic => write!(f, "not a VM snapshot"),
+            SnapshotError::UnsupportedVersion(v) => write!(f, "unsupported snapshot version {}", v),
+            SnapshotError::Truncated => write!(f, "snapshot is truncated"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StepResult {
+    Continued,
+    Halted,
+    Breakpoint(u16),
+    Watchpoint { addr: u16, old: u16, new: u16 },
+    // the instruction at `pc` is a TRAP to a vector with a trap breakpoint
+    TrapBreakpoint { pc: u16, vector: u8 },
+    Trap(TrapCode),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VmError {
+    // `op` is the full instruction word fetched from `pc`
+    IllegalOpcode { op: u16, pc: u16 },
+    PrivilegeViolation { pc: u16 },
+    // GETC/IN at `pc` reached the end of input with EofBehavior::Error
+    InputExhausted { pc: u16 },
+    // the instruction at `pc` wrote to protected `addr` in user mode
+    AccessViolation { pc: u16, addr: u16 },
+    // TRAP at `pc` to a vector with neither a built-in handler nor an
+    // installed service routine, with UnknownTrap::Error
+    UnknownTrap { pc: u16, vector: u8 },
+}
+
+impl std::fmt::Display for VmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            VmError::IllegalOpcode { op, pc } => {
+                write!(f, "illegal opcode x{:04X} at x{:04X}", op, pc)
+            }
+            VmError::PrivilegeViolation { pc } => {
+                write!(f, "privilege mode violation at x{:04X}", pc)
+            }
+            VmError::InputExhausted { pc } => write!(f, "input exhausted at x{:04X}", pc),
+            VmError::AccessViolation { pc, addr } => {
+                write!(f, "access violation writing x{:04X} at x{:04X}", addr, pc)
+            }
+            VmError::UnknownTrap { pc, vector } => {
+                write!(f, "unknown trap vector x{:02X} at x{:04X}", vector, pc)
+            }
+        }
+    }
+}
+
+// state needed to undo one instruction: the registers before it ran and
+// the old value of every memory word it wrote
+struct HistoryEntry {
+    reg: [u16; Register::RCOUNT as usize],
+    psr: u16,
+    saved_usp: u16,
+    saved_ssp: u16,
+    instr_count: u64,
+    cycle_count: u64,
+    writes: Vec<(u16, u16)>,
+    // the call stack before a JSR, JSRR or RET changed it
+    call_stack: Option<Vec<Frame>>,
+}
+
+// machine state saved by `Vm::checkpoint`, with the counters and call
+// stack a snapshot does not hold
+struct Checkpoint {
+    snapshot: Vec<u8>,
+    instr_count: u64,
+    cycle_count: u64,
+    call_stack: Vec<Frame>,
+}
+
+// a subroutine call still waiting for its RET
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frame {
+    // address of the JSR or JSRR
+    pub caller: u16,
+    pub target: u16,
+}
+
+impl Frame {
+    pub fn return_addr(&self) -> u16 {
+        self.caller.wrapping_add(1)
+    }
+}
+
+// what GETC and IN do when there is no more input
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EofBehavior {
+    Halt,
+    // return 0 in R0 and carry on
+    Zero,
+    // stop with VmError::InputExhausted
+    Error,
+}
+
+impl EofBehavior {
+    pub fn parse(s: &str) -> Option<EofBehavior> {
+        match s {
+            "halt" => Some(EofBehavior::Halt),
+            "zero" => Some(EofBehavior::Zero),
+            "error" => Some(EofBehavior::Error),
+            _ => None,
+        }
+    }
+}
+
+// what a TRAP to a vector with no handler does
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnknownTrap {
+    // print a message and halt
+    Halt,
+    // carry on with the next instruction
+    Ignore,
+    // stop with VmError::UnknownTrap
+    Error,
+}
+
+impl UnknownTrap {
+    pub fn parse(s: &str) -> Option<UnknownTrap> {
+        match s {
+            "halt" => Some(UnknownTrap::Halt),
+            "ignore" => Some(UnknownTrap::Ignore),
+            "error" => Some(UnknownTrap::Error),
+            _ => None,
+        }
+    }
+}
+
+// how bytes printed by the program are written out
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputEncoding {
+    // bytes as they are
+    Raw,
+    // bytes above 0x7F become `?`
+    Ascii,
+    // the byte stream decoded as UTF-8, invalid sequences replaced
+    Utf8Lossy,
+}
+
+impl OutputEncoding {
+    pub fn parse(s: &str) -> Option<OutputEncoding> {
+        match s {
+            "raw" => Some(OutputEncoding::Raw),
+            "ascii" => Some(OutputEncoding::Ascii),
+            "utf8-lossy" => Some(OutputEncoding::Utf8Lossy),
+            _ => None,
+        }
+    }
+}
+
+// line ending translation applied to everything the program prints
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Newline {
+    // bytes as they are
+    AsIs,
+    // CR and CR LF become LF
+    Lf,
+    // LF not already preceded by CR becomes CR LF
+    Crlf,
+}
+
+impl Newline {
+    pub fn parse(s: &str) -> Option<Newline> {
+        match s {
+            "none" => Some(Newline::AsIs),
+            "lf" => Some(Newline::Lf),
+            "crlf" => Some(Newline::Crlf),
+            _ => None,
+        }
+    }
+}
+
+// what an execution hook is told about
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HookEvent {
+    InstructionFetched { pc: u16, instr: u16 },
+    MemWrite { addr: u16, value: u16 },
+    // a general purpose register whose value changed during the instruction
+    RegWrite { reg: u16, value: u16 },
+    TrapExecuted { vector: u16 },
+}
+
+pub type Hook = Box<dyn FnMut(&HookEvent)>;
+
+// how a bounded run ended
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RunOutcome {
+    Halted,
+    LimitReached,
+    // stopped early by a breakpoint or watchpoint
+    Stopped(StepResult),
+    Error(VmError),
+}
+
+pub struct Vm {
+    pub memory: [u16; MEMORY_SIZE],
+    pub reg: [u16; Register::RCOUNT as usize],
+    pub(crate) psr: u16,
+    // stack pointer of the mode that is not currently active
+    pub(crate) saved_usp: u16,
+    pub(crate) saved_ssp: u16,
+    pub(crate) breakpoints: HashSet<u16>,
+    // breakpoints that only stop when their condition holds
+    pub(crate) conditions: HashMap<u16, Condition>,
+    // debugger watch expressions with the text they were entered as
+    pub(crate) watch_exprs: Vec<(String, Expr)>,
+    pub(crate) watchpoints: HashSet<u16>,
+    // trap vectors to stop in front of
+    pub(crate) trap_breakpoints: HashSet<u8>,
+    // last write to a watched address during the current step
+    pub(crate) watch_hit: Option<(u16, u16, u16)>,
+    // standard trap executed during the current step
+    trap: Option<TrapCode>,
+    // first protected address the current step tried to write
+    access_violation: Option<u16>,
+    // the current instruction was fetched from below USER_SPACE_START
+    system_code: bool,
+    pub protect_vectors: bool,
+    pub trace: bool,
+    // show the signed decimal column in register dumps
+    pub show_signed: bool,
+    // name R6 and R7 after their roles in the calling convention, R6/SP
+    // and R7/LR, in register dumps and traces
+    pub abi_names: bool,
+    // ANSI colors in register and memory dumps
+    pub color: bool,
+    // leave stdout to the program: no HALT banner, and the VM's own
+    // messages go to stderr
+    pub quiet: bool,
+    // words per row in memory dumps
+    pub mem_width: usize,
+    // addresses below the device registers are masked with this;
+    // `with_memory_bits` makes it smaller than xFFFF
+    memory_mask: u16,
+    // longest string PUTS prints before giving up on finding its
+    // terminator; None allows up to the top of memory
+    pub max_string_len: Option<usize>,
+    // LEA sets the condition codes as in the second edition of Patt & Patel
+    // (and lc3sim); the third edition ISA and LC3Tools leave them alone
+    pub lea_sets_cc: bool,
+    // course extension, off in the standard ISA: ADD sets PSR bit 3 on
+    // signed overflow and clears it otherwise, and BR with no n/z/p bits
+    // (BRv) branches when it is set instead of never
+    pub overflow_flag: bool,
+    // labels for disassembly in traces
+    pub symbols: HashMap<u16, String>,
+    pub(crate) instr_count: u64,
+    pub(crate) cycle_count: u64,
+    pub(crate) op_counts: HashMap<Instruction, u64>,
+    // calls made by JSR/JSRR and not yet returned from with RET
+    call_stack: Vec<Frame>,
+    // keep the last HISTORY_LIMIT steps so `step_back` can undo them
+    pub record_history: bool,
+    history: VecDeque<HistoryEntry>,
+    // entry for the step in progress
+    pending: Option<HistoryEntry>,
+    checkpoint: Option<Checkpoint>,
+    // how many times each address was fetched as an instruction, once
+    // enabled
+    coverage: Option<Vec<u64>>,
+    // addresses written by the program or a loader, to flag reads of
+    // uninitialized memory
+    #[cfg(feature = "debug_mem")]
+    pub(crate) initialized: Vec<bool>,
+    pub output_encoding: OutputEncoding,
+    pub newline: Newline,
+    // the last byte printed was a CR
+    after_cr: bool,
+    pub on_eof: EofBehavior,
+    pub on_unknown_trap: UnknownTrap,
+    // embedder callback for execution events
+    hook: Option<Hook>,
+    keyboard: Keyboard,
+    // devices added by an embedder, consulted in order after the keyboard
+    devices: Vec<Box<dyn MmioDevice>>,
+    // state of the LCG behind the RNG register
+    rng_state: u32,
+    // start of a UTF-8 sequence not yet written in utf8-lossy mode
+    utf8_pending: Vec<u8>,
+    // a framebuffer word was written since the last `take_display`
+    #[cfg(feature = "display")]
+    pub(crate) display_dirty: bool,
+    // headless I/O: when set, input comes from this buffer instead of the
+    // terminal and output is collected instead of written to stdout
+    input: Option<VecDeque<u8>>,
+    output: Option<Vec<u8>>,
+    // terminal output, flushed after every output trap when `auto_flush`
+    // is set and always before waiting for input or halting
+    stdout: BufWriter<Stdout>,
+    pub auto_flush: bool,
+}
+
+impl Default for Vm {
+    fn default() -> Vm {
+        Vm::new()
+    }
+}
+
+impl Vm {
+    pub fn new() -> Vm {
+        let mut vm = Vm {
+            memory: [0; MEMORY_SIZE],
+            reg: [0; Register::RCOUNT as usize],
+            psr: PSR_USER | ConditionFlag::ZRO as u16,
+            saved_usp: 0,
+            saved_ssp: SSP_START,
+            breakpoints: HashSet::new(),
+            conditions: HashMap::new(),
+            watch_exprs: Vec::new(),
+            watchpoints: HashSet::new(),
+            trap_breakpoints: HashSet::new(),
+            watch_hit: None,
+            trap: None,
+            access_violation: None,
+            system_code: false,
+            protect_vectors: false,
+            trace: false,
+            show_signed: true,
+            abi_names: false,
+            color: false,
+            quiet: false,
+            mem_width: DEFAULT_MEM_WIDTH,
+            memory_mask: 0xFFFF,
+            max_string_len: None,
+            lea_sets_cc: false,
+            overflow_flag: false,
+            symbols: HashMap::new(),
+            instr_count: 0,
+            cycle_count: 0,
+            op_counts: HashMap::new(),
+            call_stack: Vec::new(),
+            record_history: false,
+            history: VecDeque::new(),
+            pending: None,
+            checkpoint: None,
+            coverage: None,
+            #[cfg(feature = "debug_mem")]
+            initialized: vec![false; MEMORY_SIZE],
+            output_encoding: OutputEncoding::Raw,
+            newline: Newline::AsIs,
+            after_cr: false,
+            on_eof: EofBehavior::Halt,
+            on_unknown_trap: UnknownTrap::Halt,
+            hook: None,
+            keyboard: Keyboard::default(),
+            devices: Vec::new(),
+            rng_state: 1,
+            utf8_pending: Vec::new(),
+            #[cfg(feature = "display")]
+            display_dirty: false,
+            input: None,
+            output: None,
+            stdout: BufWriter::new(std::io::stdout()),
+            auto_flush: true,
+        };
+        vm.memory[MemoryMappedRegister::MCR as usize] = 1 << 15;
+        vm.reg[Register::RPC as usize] = PC_START;
+        vm
+    }
+
+    // a machine with 2^bits words of RAM (1-16), for exercising modular
+    // addressing: with 12 bits, x1001 is the same word as x0001. The device
+    // registers stay at xFE00 and up, and images must fit below the top
+    // of RAM.
+    pub fn with_memory_bits(bits: u8) -> Vm {
+        assert!((1..=16).contains(&bits), "memory bits must be 1-16");
+        let mut vm = Vm::new();
+        vm.memory_mask = (((1u32 << bits) - 1) & 0xFFFF) as u16;
+        vm
+    }
+
+    // a machine that starts executing at `pc` instead of PC_START
+    pub fn with_pc(pc: u16) -> Vm {
+        let mut vm = Vm::new();
+        vm.reg[Register::RPC as usize] = pc;
+        vm
+    }
+
+    // warm reset: registers, PSR, stacks and device registers go back to
+    // their power-on values and counters restart; memory, breakpoints and
+    // settings are kept so a loaded program can be run again
+    pub fn reset(&mut self) {
+        self.reg = [0; Register::RCOUNT as usize];
+        self.reg[Register::RPC as usize] = PC_START;
+        self.psr = PSR_USER | ConditionFlag::ZRO as u16;
+        self.saved_usp = 0;
+        self.saved_ssp = SSP_START;
+        self.keyboard = Keyboard::default();
+        self.sync_keyboard();
+        self.memory[MemoryMappedRegister::MCR as usize] = 1 << 15;
+        self.watch_hit = None;
+        self.trap = None;
+        self.instr_count = 0;
+        self.cycle_count = 0;
+        self.op_counts.clear();
+        self.call_stack.clear();
+        self.history.clear();
+        self.pending = None;
+        if let Some(coverage) = &mut self.coverage {
+            coverage.fill(0);
+        }
+        self.utf8_pending.clear();
+        self.after_cr = false;
+    }
+
+    // cold reset: clear all of memory as well, leaving nothing loaded
+    pub fn cold_reset(&mut self) {
+        self.memory = [0; MEMORY_SIZE];
+        #[cfg(feature = "debug_mem")]
+        self.initialized.fill(false);
+        #[cfg(feature = "display")]
+        {
+            self.display_dirty = true;
+        }
+        self.reset();
+    }
+
+    fn update_flags(&mut self, r: u16) {
+        let value = self.reg[r as usize];
+        let flag = if value == 0 {
+            ConditionFlag::ZRO
+        } else if value >> 15 == 1 {
+            ConditionFlag::NEG
+        } else {
+            ConditionFlag::POS
+        };
+        self.psr = (self.psr & !PSR_COND) | flag as u16;
+    }
+
+    // privilege bit 15, priority 10-8 and condition codes 2-0
+    pub fn psr(&self) -> u16 {
+        self.psr
+    }
+
+    fn push(&mut self, val: u16) {
+        let sp = self.reg[Register::RR6 as usize].wrapping_sub(1);
+        self.reg[Register::RR6 as usize] = sp;
+        self.mem_write(sp, val);
+    }
+
+    fn pop(&mut self) -> u16 {
+        let sp = self.reg[Register::RR6 as usize];
+        self.reg[Register::RR6 as usize] = sp.wrapping_add(1);
+        self.mem_read(sp)
+    }
+
+    // enter supervisor mode and jump through the interrupt vector table,
+    // saving PSR and PC on the supervisor stack; returns false without
+    // touching any state when no service routine is installed
+    fn raise_exception(&mut self, vector: Exception) -> bool {
+        self.enter_service_routine(vector as u16, None)
+    }
+
+    // returns false without touching any state when the vector is empty
+    fn enter_service_routine(&mut self, vector: u16, priority: Option<u16>) -> bool {
+        let handler = self.memory[(INTERRUPT_VECTOR_TABLE + vector) as usize];
+        if handler == 0 {
+            return false;
+        }
+        let old_psr = self.psr;
+        if old_psr & PSR_USER != 0 {
+            self.saved_usp = self.reg[Register::RR6 as usize];
+            self.reg[Register::RR6 as usize] = self.saved_ssp;
+        }
+        self.psr &= !PSR_USER;
+        if let Some(priority) = priority {
+            self.psr = (self.psr & !PSR_PRIORITY) | (priority << 8);
+        }
+        self.push(old_psr);
+        self.push(self.reg[Register::RPC as usize]);
+        self.reg[Register::RPC as usize] = handler;
+        true
+    }
+
+    // switch to headless I/O: GETC/IN and the keyboard read from `bytes`,
+    // and output is kept for `take_output`
+    pub fn set_input(&mut self, bytes: Vec<u8>) {
+        self.input = Some(bytes.into());
+        self.output.get_or_insert_with(Vec::new);
+    }
+
+    // output collected since the last call, in headless mode
+    pub fn take_output(&mut self) -> Vec<u8> {
+        self.output.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    fn key_available(&mut self) -> bool {
+        match &self.input {
+            Some(input) => !input.is_empty(),
+            None => {
+                self.flush_output();
+                check_key()
+            }
+        }
+    }
+
+    // next input byte, or None once the input is exhausted
+    fn read_input(&mut self) -> Option<u8> {
+        match &mut self.input {
+            Some(input) => input.pop_front(),
+            None => {
+                // a prompt without a newline must show before we block
+                self.flush_output();
+                read_char()
+            }
+        }
+    }
+
+    // write out terminal output still held in the buffer
+    pub fn flush_output(&mut self) {
+        self.stdout.flush().unwrap();
+    }
+
+    // GETC/IN found no more input
+    fn input_exhausted(&mut self) -> Result<(), VmError> {
+        match self.on_eof {
+            EofBehavior::Halt => self.halt(),
+            EofBehavior::Zero => self.reg[Register::RR0 as usize] = 0,
+            EofBehavior::Error => {
+                return Err(VmError::InputExhausted {
+                    pc: self.reg[Register::RPC as usize].wrapping_sub(1),
+                })
+            }
+        }
+        Ok(())
+    }
+
+    fn write_byte(&mut self, c: u8) {
+        match &mut self.output {
+            Some(output) => output.push(c),
+            None => self.stdout.write_all(&[c]).unwrap(),
+        }
+    }
+
+    // every character the program prints goes through here
+    fn emit_byte(&mut self, c: u8) {
+        let after_cr = std::mem::replace(&mut self.after_cr, c == b'\r');
+        match (self.newline, c) {
+            (Newline::Lf, b'\r') => self.encode_byte(b'\n'),
+            (Newline::Lf, b'\n') if after_cr => {}
+            (Newline::Crlf, b'\n') if !after_cr => {
+                self.encode_byte(b'\r');
+                self.encode_byte(b'\n');
+            }
+            _ => self.encode_byte(c),
+        }
+    }
+
+    fn encode_byte(&mut self, c: u8) {
+        match self.output_encoding {
+            OutputEncoding::Raw => self.write_byte(c),
+            OutputEncoding::Ascii => self.write_byte(if c.is_ascii() { c } else { b'?' }),
+            OutputEncoding::Utf8Lossy => {
+                self.utf8_pending.push(c);
+                self.flush_utf8();
+            }
+        }
+    }
+
+    // write out the complete characters held back for utf8-lossy output,
+    // replacing invalid sequences with U+FFFD and keeping an unfinished one
+    fn flush_utf8(&mut self) {
+        while !self.utf8_pending.is_empty() {
+            let (valid, invalid) = match std::str::from_utf8(&self.utf8_pending) {
+                Ok(_) => (self.utf8_pending.len(), 0),
+                Err(e) => match e.error_len() {
+                    Some(len) => (e.valid_up_to(), len),
+                    // the tail may still be completed by later bytes
+                    None if e.valid_up_to() == 0 => return,
+                    None => (e.valid_up_to(), 0),
+                },
+            };
+            let mut bytes: Vec<u8> = self.utf8_pending.drain(..valid + invalid).collect();
+            if invalid > 0 {
+                bytes.truncate(valid);
+                bytes.extend_from_slice("\u{FFFD}".as_bytes());
+            }
+            for c in bytes {
+                self.write_byte(c);
+            }
+        }
+    }
+
+    fn put_str(&mut self, s: &str) {
+        for &c in s.as_bytes() {
+            self.emit_byte(c);
+        }
+    }
+
+    // latch a pending key into KBDR if the previous one has been consumed
+    fn poll_keyboard(&mut self) {
+        if !self.keyboard.ready() && self.key_available() {
+            if let Some(c) = self.read_input() {
+                self.keyboard.latch(c);
+                self.sync_keyboard();
+            }
+        }
+    }
+
+    // copy the keyboard registers into memory for dumps and snapshots
+    fn sync_keyboard(&mut self) {
+        self.memory[MemoryMappedRegister::KBSR as usize] = self.keyboard.status;
+        self.memory[MemoryMappedRegister::KBDR as usize] = self.keyboard.data;
+    }
+
+    // raise the keyboard interrupt when enabled, a key is waiting and the
+    // current priority is below the keyboard's
+    fn check_interrupts(&mut self) {
+        if self.keyboard.status & KBSR_INTERRUPT_ENABLE == 0
+            || (self.psr & PSR_PRIORITY) >> 8 >= KEYBOARD_PRIORITY
+        {
+            return;
+        }
+        self.poll_keyboard();
+        if self.keyboard.ready() {
+            self.enter_service_routine(KEYBOARD_VECTOR, Some(KEYBOARD_PRIORITY));
+        }
+    }
+
+    pub fn set_hook(&mut self, hook: Hook) {
+        self.hook = Some(hook);
+    }
+
+    pub fn clear_hook(&mut self) {
+        self.hook = None;
+    }
+
+    fn emit(&mut self, event: HookEvent) {
+        if let Some(hook) = &mut self.hook {
+            hook(&event);
+        }
+    }
+
+    // make TRAP `code` run the service routine at `addr` by pointing its
+    // trap vector table entry there; an address of 0 restores the built-in
+    // handler
+    pub fn set_trap_vector(&mut self, code: u8, addr: u16) {
+        self.memory[code as usize] = addr;
+        #[cfg(feature = "debug_mem")]
+        {
+            self.initialized[code as usize] = true;
+        }
+    }
+
+    // restart the RNG register's sequence from `seed`
+    pub fn seed_rng(&mut self, seed: u32) {
+        self.rng_state = seed;
+    }
+
+    // the machine runs while the MCR clock-enable bit is set
+    pub fn is_running(&self) -> bool {
+        self.memory[MemoryMappedRegister::MCR as usize] >> 15 == 1
+    }
+
+    pub fn halt(&mut self) {
+        self.record_write(MemoryMappedRegister::MCR as u16);
+        self.memory[MemoryMappedRegister::MCR as usize] &= !(1 << 15);
+        self.flush_output();
+    }
+
+    fn record_write(&mut self, address: u16) {
+        if let Some(entry) = &mut self.pending {
+            entry.writes.push((address, self.memory[address as usize]));
+        }
+    }
+
+    // the subroutine calls in progress, outermost first
+    pub fn call_stack(&self) -> &[Frame] {
+        &self.call_stack
+    }
+
+    fn record_call_stack(&mut self) {
+        if let Some(entry) = &mut self.pending {
+            entry
+                .call_stack
+                .get_or_insert_with(|| self.call_stack.clone());
+        }
+    }
+
+    fn push_frame(&mut self, frame: Frame) {
+        self.record_call_stack();
+        self.call_stack.push(frame);
+    }
+
+    // a RET to `addr` unwinds to the innermost frame returning there, so a
+    // routine that skips its caller's RET does not leave stale frames; a
+    // RET that matches no frame (a computed jump through R7, or the end of
+    // a trap service routine) leaves the stack alone
+    fn pop_frame(&mut self, addr: u16) {
+        if let Some(depth) = self
+            .call_stack
+            .iter()
+            .rposition(|frame| frame.return_addr() == addr)
+        {
+            self.record_call_stack();
+            self.call_stack.truncate(depth);
+        }
+    }
+
+    pub fn mem_write(&mut self, address: u16, val: u16) {
+        let address = self.physical(address);
+        if self.protect_vectors
+            && self.psr & PSR_USER != 0
+            && !self.system_code
+            && !(VECTORS_END..DEVICES_START).contains(&address)
+        {
+            self.access_violation.get_or_insert(address);
+            return;
+        }
+        self.record_write(address);
+        if self.hook.is_some() {
+            self.emit(HookEvent::MemWrite {
+                addr: address,
+                value: val,
+            });
+        }
+        if self.watchpoints.contains(&address) {
+            self.watch_hit = Some((address, self.memory[address as usize], val));
+        }
+        #[cfg(feature = "debug_mem")]
+        {
+            self.initialized[address as usize] = true;
+        }
+        #[cfg(feature = "display")]
+        if crate::display::in_display(address) {
+            self.display_dirty = true;
+        }
+        self.memory[address as usize] = val;
+        if self.keyboard.range().contains(&address) {
+            self.keyboard.write(address, val);
+            self.sync_keyboard();
+        } else if let Some(device) = self.device_at(address) {
+            device.write(address, val);
+        }
+    }
+
+    // the word of RAM an address reaches: with fewer than 16 memory bits,
+    // addresses below the device registers wrap at the top of memory
+    fn physical(&self, address: u16) -> u16 {
+        if address >= DEVICES_START {
+            address
+        } else {
+            address & self.memory_mask
+        }
+    }
+
+    // words of addressable memory, MEMORY_SIZE unless the machine was made
+    // with `with_memory_bits`
+    pub fn memory_size(&self) -> usize {
+        self.memory_mask as usize + 1
+    }
+
+    // add a memory-mapped device; it takes precedence over RAM and over
+    // devices added later for the addresses in its range
+    pub fn add_device(&mut self, device: Box<dyn MmioDevice>) {
+        self.devices.push(device);
+    }
+
+    fn device_at(&mut self, address: u16) -> Option<&mut Box<dyn MmioDevice>> {
+        self.devices
+            .iter_mut()
+            .find(|device| device.range().contains(&address))
+    }
+
+    pub fn mem_read(&mut self, address: u16) -> u16 {
+        let address = self.physical(address);
+        #[cfg(feature = "debug_mem")]
+        self.check_initialized(address);
+        if self.keyboard.range().contains(&address) {
+            if address == MemoryMappedRegister::KBSR as u16 {
+                self.poll_keyboard();
+            }
+            let value = self.keyboard.read(address);
+            self.sync_keyboard();
+            return value;
+        }
+        if let Some(device) = self.device_at(address) {
+            let value = device.read(address);
+            self.memory[address as usize] = value;
+            return value;
+        }
+        if address == MemoryMappedRegister::TMR as u16 {
+            self.memory[MemoryMappedRegister::TMR as usize] = self.instr_count as u16;
+        } else if address == MemoryMappedRegister::RNG as u16 {
+            self.rng_state = self
+                .rng_state
+                .wrapping_mul(1_103_515_245)
+                .wrapping_add(12_345);
+            self.memory[MemoryMappedRegister::RNG as usize] = (self.rng_state >> 16) as u16;
+        }
+        self.memory[address as usize]
+    }
+
+    // warn once about a read of a word nothing has written and report
+    // whether it did; the vector tables and device registers are exempt
+    #[cfg(feature = "debug_mem")]
+    fn check_initialized(&mut self, address: u16) -> bool {
+        let program_space = INTERRUPT_VECTOR_TABLE + 0x100..0xFE00;
+        if program_space.contains(&address) && !self.initialized[address as usize] {
+            eprintln!("warning: read of uninitialized memory at x{:04X}", address);
+            self.initialized[address as usize] = true;
+            return true;
+        }
+        false
+    }
+
+    // registers as hex and signed decimal, one per line
+    pub fn dump_registers(&self) -> String {
+        self.dump_registers_since(&self.reg)
+    }
+
+    // like `dump_registers`, marking the registers that differ from
+    // `before` when color is on
+    pub fn dump_registers_since(&self, before: &[u16; Register::RCOUNT as usize]) -> String {
+        let mut out = String::new();
+        let line = |r: usize, name: String| {
+            let val = self.reg[r];
+            let mut text = format!("x{:04X}", val);
+            if self.show_signed {
+                text = format!("{}  {}", text, val as i16);
+            }
+            if val >> 15 == 1 {
+                text = paint(&text, Style::Negative, self.color);
+            }
+            let width = if self.abi_names { 5 } else { 2 };
+            let line = format!("{:<width$} {}", name, text);
+            if val != before[r] {
+                format!("{}\n", paint(&line, Style::Changed, self.color))
+            } else {
+                format!("{}\n", line)
+            }
+        };
+        for r in 0..8 {
+            out.push_str(&line(r, self.register_name(r)));
+        }
+        out.push_str(&line(Register::RPC as usize, "PC".to_string()));
+        let cond = self.psr & PSR_COND;
+        let flag = if cond == ConditionFlag::NEG as u16 {
+            "n"
+        } else if cond == ConditionFlag::ZRO as u16 {
+            "z"
+        } else {
+            "p"
+        };
+        let overflow = if self.psr & PSR_OVERFLOW != 0 {
+            " v"
+        } else {
+            ""
+        };
+        let width = if self.abi_names { 5 } else { 2 };
+        out.push_str(&format!("{:<width$} {}{}\n", "CC", flag, overflow));
+        out
+    }
+
+    // the registers before the last step recorded in the history
+    pub fn previous_registers(&self) -> Option<[u16; Register::RCOUNT as usize]> {
+        self.history.back().map(|entry| entry.reg)
+    }
+
+    pub fn add_watchpoint(&mut self, addr: u16) {
+        self.watchpoints.insert(addr);
+    }
+
+    pub fn remove_watchpoint(&mut self, addr: u16) -> bool {
+        self.watchpoints.remove(&addr)
+    }
+
+    // cycles spent so far under the `CYCLE_COSTS` model
+    pub fn cycles(&self) -> u64 {
+        self.cycle_count
+    }
+
+    // total instruction count and a per-opcode breakdown
+    pub fn stats(&self) -> String {
+        let mut out = format!("instructions executed: {}\n", self.instr_count);
+        let mut counts: Vec<(&Instruction, &u64)> = self.op_counts.iter().collect();
+        counts.sort_by_key(|(op, _)| **op as u16);
+        for (op, count) in counts {
+            out.push_str(&format!("  {:<5} {}\n", disasm::mnemonic(*op), count));
+        }
+        out
+    }
+
+    // start recording which addresses are executed
+    pub fn enable_coverage(&mut self) {
+        self.coverage.get_or_insert_with(|| vec![0; MEMORY_SIZE]);
+    }
+
+    // executed addresses merged into inclusive (first, last) ranges
+    pub fn executed_ranges(&self) -> Vec<(u16, u16)> {
+        let mut ranges: Vec<(u16, u16)> = Vec::new();
+        let counts = match &self.coverage {
+            Some(counts) => counts,
+            None => return ranges,
+        };
+        for (addr, &count) in counts.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            match ranges.last_mut() {
+                Some((_, last)) if *last as usize + 1 == addr => *last = addr as u16,
+                _ => ranges.push((addr as u16, addr as u16)),
+            }
+        }
+        ranges
+    }
+
+    // the `n` most executed addresses with their counts, most executed
+    // first and lower addresses first among equals
+    pub fn hot_addresses(&self, n: usize) -> Vec<(u16, u64)> {
+        let mut hot: Vec<(u16, u64)> = match &self.coverage {
+            Some(counts) => (0..=u16::MAX)
+                .zip(counts.iter().copied())
+                .filter(|&(_, count)| count != 0)
+                .collect(),
+            None => return Vec::new(),
+        };
+        hot.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        hot.truncate(n);
+        hot
+    }
+
+    // serialize memory, registers, PSR and run state as
+    // magic, version, big-endian words, running flag
+    pub fn save_snapshot(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(SNAPSHOT_MAGIC.len() + 1 + (MEMORY_SIZE + 16) * 2);
+        out.extend_from_slice(SNAPSHOT_MAGIC);
+        out.push(SNAPSHOT_VERSION);
+        let words = self.memory.iter().chain(self.reg.iter()).copied().chain([
+            self.psr,
+            self.saved_usp,
+            self.saved_ssp,
+        ]);
+        for word in words {
+            out.extend_from_slice(&word.to_be_bytes());
+        }
+        out.push(self.is_running() as u8);
+        out
+    }
+
+    pub fn load_snapshot(&mut self, data: &[u8]) -> Result<(), SnapshotError> {
+        let header = SNAPSHOT_MAGIC.len() + 1;
+        if data.len() < header || &data[..SNAPSHOT_MAGIC.len()] != SNAPSHOT_MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        if data[SNAPSHOT_MAGIC.len()] != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(
+                data[SNAPSHOT_MAGIC.len()],
+            ));
+        }
+        let word_count = MEMORY_SIZE + self.reg.len() + 3;
+        if data.len() != header + word_count * 2 + 1 {
+            return Err(SnapshotError::Truncated);
+        }
+        let mut words = data[header..header + word_count * 2]
+            .chunks(2)
+            .map(BigEndian::read_u16);
+        for cell in self.memory.iter_mut().chain(self.reg.iter_mut()) {
+            *cell = words.next().unwrap();
+        }
+        self.psr = words.next().unwrap();
+        self.saved_usp = words.next().unwrap();
+        self.saved_ssp = words.next().unwrap();
+        if data[data.len() - 1] == 0 {
+            self.halt();
+        }
+        self.keyboard.status = self.memory[MemoryMappedRegister::KBSR as usize];
+        self.keyboard.data = self.memory[MemoryMappedRegister::KBDR as usize];
+        // the snapshot does not record calls in progress
+        self.call_stack.clear();
+        self.history.clear();
+        #[cfg(feature = "debug_mem")]
+        self.initialized.fill(true);
+        Ok(())
+    }
+
+    // remember the current state for `restore_checkpoint`, replacing any
+    // earlier checkpoint; the debugger takes one at every breakpoint
+    pub fn checkpoint(&mut self) {
+        self.checkpoint = Some(Checkpoint {
+            snapshot: self.save_snapshot(),
+            instr_count: self.instr_count,
+            cycle_count: self.cycle_count,
+            call_stack: self.call_stack.clone(),
+        });
+    }
+
+    // go back to the state saved by `checkpoint`, undoing `set` commands
+    // as well as executed instructions; false when there is none. The
+    // history recorded before the checkpoint is kept, so `step_back` can
+    // continue from there.
+    pub fn restore_checkpoint(&mut self) -> bool {
+        let checkpoint = match self.checkpoint.take() {
+            Some(checkpoint) => checkpoint,
+            None => return false,
+        };
+        let mut history = std::mem::take(&mut self.history);
+        history.retain(|entry| entry.instr_count < checkpoint.instr_count);
+        self.load_snapshot(&checkpoint.snapshot)
+            .expect("checkpoints are valid snapshots");
+        self.history = history;
+        self.instr_count = checkpoint.instr_count;
+        self.cycle_count = checkpoint.cycle_count;
+        self.call_stack = checkpoint.call_stack.clone();
+        self.checkpoint = Some(checkpoint);
+        true
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+        self.conditions.remove(&addr);
+    }
+
+    pub fn add_conditional_breakpoint(&mut self, addr: u16, condition: Condition) {
+        self.breakpoints.insert(addr);
+        self.conditions.insert(addr, condition);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.conditions.remove(&addr);
+        self.breakpoints.remove(&addr)
+    }
+
+    fn breakpoint_hit(&self, pc: u16) -> bool {
+        self.breakpoints.contains(&pc) && self.conditions.get(&pc).is_none_or(|c| c.holds(self))
+    }
+
+    pub fn add_trap_breakpoint(&mut self, vector: u8) {
+        self.trap_breakpoints.insert(vector);
+    }
+
+    pub fn remove_trap_breakpoint(&mut self, vector: u8) -> bool {
+        self.trap_breakpoints.remove(&vector)
+    }
+
+    // the vector of the TRAP at `pc` when it has a trap breakpoint
+    fn trap_breakpoint_hit(&self, pc: u16) -> Option<u8> {
+        let instr = self.memory[pc as usize];
+        let vector = (instr & 0xFF) as u8;
+        (decode(instr >> 12) == Instruction::TRAP && self.trap_breakpoints.contains(&vector))
+            .then_some(vector)
+    }
+
+    fn trace_before(&self, pc: u16, instr: u16) {
+        let (reads, _) = disasm::registers_used(instr);
+        let values: Vec<String> = reads
+            .iter()
+            .map(|&r| format!("R{}=x{:04X}", r, self.reg[r as usize]))
+            .collect();
+        let line = format!(
+            "x{:04X}  x{:04X}  {:<28} {}",
+            pc,
+            instr,
+            disasm::disassemble_with_symbols(instr, pc, &self.symbols, self.overflow_flag),
+            values.join(" ")
+        );
+        eprintln!("{}", line.trim_end());
+    }
+
+    // `R6`, or `R6/SP` with `abi_names`
+    fn register_name(&self, r: usize) -> String {
+        match r {
+            6 if self.abi_names => "R6/SP".to_string(),
+            7 if self.abi_names => "R7/LR".to_string(),
+            _ => format!("R{}", r),
+        }
+    }
+
+    // the general purpose registers the instruction changed
+    fn trace_after(&self, before: &[u16]) {
+        for (r, &old) in before.iter().enumerate().take(8) {
+            if self.reg[r] != old {
+                eprintln!(
+                    "    {}: x{:04X} -> x{:04X}",
+                    self.register_name(r),
+                    old,
+                    self.reg[r]
+                );
+            }
+        }
+    }
+
+    // the trap service routines implemented by the VM itself
+    fn builtin_trap(&mut self, vector: u16) -> Result<(), VmError> {
+        match decode_trap(vector) {
+            Some(TrapCode::GETC) => match self.read_input() {
+                Some(c) => self.reg[Register::RR0 as usize] = c as u16,
+                None => self.input_exhausted()?,
+            },
+            Some(TrapCode::OUT) => {
+                self.emit_byte(self.reg[Register::RR0 as usize] as u8);
+            }
+            Some(TrapCode::PUTS) => {
+                // stop at the top of memory or after `max_string_len`
+                // characters rather than wrapping around. R0 is preserved.
+                let start = self.reg[Register::RR0 as usize];
+                let mut len = MEMORY_SIZE - start as usize;
+                if let Some(max) = self.max_string_len {
+                    len = len.min(max);
+                }
+                let mut terminated = false;
+                for address in (start as usize..).take(len) {
+                    let c = self.mem_read(address as u16);
+                    if c == 0 {
+                        terminated = true;
+                        break;
+                    }
+                    self.emit_byte(c as u8);
+                }
+                if !terminated {
+                    eprintln!("warning: PUTS string at x{:04X} is not terminated", start);
+                }
+            }
+            Some(TrapCode::IN) => {
+                self.put_str("Enter a character: ");
+                match self.read_input() {
+                    Some(c) => {
+                        self.emit_byte(c);
+                        self.reg[Register::RR0 as usize] = c as u16;
+                    }
+                    None => self.input_exhausted()?,
+                }
+            }
+            Some(TrapCode::PUTSP) => {
+                // two characters per word, low byte first; a zero word
+                // or a zero high byte ends the string. R0 is preserved.
+                let mut address = self.reg[Register::RR0 as usize];
+                loop {
+                    let c = self.mem_read(address);
+                    if c == 0 {
+                        break;
+                    }
+                    self.emit_byte((c & 0xFF) as u8);
+                    let c2 = (c >> 8) as u8;
+                    if c2 == 0 {
+                        break;
+                    }
+                    self.emit_byte(c2);
+                    address = address.wrapping_add(1);
+                }
+            }
+            Some(TrapCode::HALT) => {
+                if !self.quiet {
+                    self.put_str("HALT\n");
+                }
+                self.halt();
+            }
+            None => match self.on_unknown_trap {
+                UnknownTrap::Halt => {
+                    if self.quiet {
+                        eprintln!("unknown trap code");
+                    } else {
+                        self.put_str("Unknown trap code\n");
+                    }
+                    self.halt();
+                }
+                UnknownTrap::Ignore => {}
+                UnknownTrap::Error => {
+                    return Err(VmError::UnknownTrap {
+                        pc: self.reg[Register::RPC as usize].wrapping_sub(1),
+                        vector: vector as u8,
+                    })
+                }
+            },
+        }
+        if self.auto_flush {
+            self.flush_output();
+        }
+        Ok(())
+    }
+
+    // run until the program halts, a breakpoint is reached or an
+    // instruction faults
+    pub fn run(&mut self) -> Result<StepResult, VmError> {
+        while self.mem_read(MemoryMappedRegister::MCR as u16) >> 15 == 1 {
+            match self.step()? {
+                StepResult::Continued | StepResult::Trap(_) => {}
+                result => return Ok(result),
+            }
+        }
+        Ok(StepResult::Halted)
+    }
+
+    // like `run`, but give up after `max_instrs` instructions
+    pub fn run_with_limit(&mut self, max_instrs: u64) -> RunOutcome {
+        for _ in 0..max_instrs {
+            match self.step() {
+                Ok(StepResult::Continued | StepResult::Trap(_)) => {}
+                Ok(StepResult::Halted) => return RunOutcome::Halted,
+                Ok(result) => return RunOutcome::Stopped(result),
+                Err(e) => return RunOutcome::Error(e),
+            }
+        }
+        if self.is_running() {
+            RunOutcome::LimitReached
+        } else {
+            RunOutcome::Halted
+        }
+    }
+
+    // execute a single instruction, recording how to undo it when
+    // `record_history` is set
+    pub fn step(&mut self) -> Result<StepResult, VmError> {
+        if !self.record_history || !self.is_running() {
+            return self.execute();
+        }
+        self.pending = Some(HistoryEntry {
+            reg: self.reg,
+            psr: self.psr,
+            saved_usp: self.saved_usp,
+            saved_ssp: self.saved_ssp,
+            instr_count: self.instr_count,
+            cycle_count: self.cycle_count,
+            writes: Vec::new(),
+            call_stack: None,
+        });
+        let result = self.execute();
+        if let Some(entry) = self.pending.take() {
+            if self.history.len() == HISTORY_LIMIT {
+                self.history.pop_front();
+            }
+            self.history.push_back(entry);
+        }
+        result
+    }
+
+    // undo the most recent recorded instruction; false when the history is
+    // empty. Console input already consumed and output already written stay
+    // as they are.
+    pub fn step_back(&mut self) -> bool {
+        let entry = match self.history.pop_back() {
+            Some(entry) => entry,
+            None => return false,
+        };
+        for &(address, old) in entry.writes.iter().rev() {
+            self.memory[address as usize] = old;
+        }
+        self.reg = entry.reg;
+        self.psr = entry.psr;
+        self.saved_usp = entry.saved_usp;
+        self.saved_ssp = entry.saved_ssp;
+        self.instr_count = entry.instr_count;
+        self.cycle_count = entry.cycle_count;
+        if let Some(call_stack) = entry.call_stack {
+            self.call_stack = call_stack;
+        }
+        true
+    }
+
+    fn execute(&mut self) -> Result<StepResult, VmError> {
+        if !self.is_running() {
+            return Ok(StepResult::Halted);
+        }
+        self.trap = None;
+        self.access_violation = None;
+        self.watch_hit = None;
+        self.check_interrupts();
+        let instr_pc = self.reg[Register::RPC as usize];
+        let instr = self.mem_read(instr_pc);
+        self.system_code = instr_pc < USER_SPACE_START;
+        if let Some(counts) = &mut self.coverage {
+            counts[instr_pc as usize] += 1;
+        }
+        // registers before the instruction, to report the ones it changes
+        let regs_before = (self.trace || self.hook.is_some()).then_some(self.reg);
+        if self.hook.is_some() {
+            self.emit(HookEvent::InstructionFetched {
+                pc: instr_pc,
+                instr,
+            });
+        }
+        if self.trace {
+            self.trace_before(instr_pc, instr);
+        }
+        self.reg[Register::RPC as usize] = self.reg[Register::RPC as usize].wrapping_add(1);
+        let op = decode(instr >> 12);
+        self.instr_count += 1;
+        self.cycle_count += CYCLE_COSTS[(instr >> 12) as usize] as u64;
+        *self.op_counts.entry(op).or_insert(0) += 1;
+
+        DISPATCH[(instr >> 12) as usize](self, instr)?;
+        if let Some(addr) = self.access_violation {
+            if !self.raise_exception(Exception::AccessViolation) {
+                return Err(VmError::AccessViolation { pc: instr_pc, addr });
+            }
+        }
+
+        if let Some(before) = regs_before {
+            if self.trace {
+                self.trace_after(&before);
+            }
+            if self.hook.is_some() {
+                for (r, &old) in before.iter().enumerate().take(8) {
+                    if self.reg[r] != old {
+                        self.emit(HookEvent::RegWrite {
+                            reg: r as u16,
+                            value: self.reg[r],
+                        });
+                    }
+                }
+            }
+        }
+
+        let pc = self.reg[Register::RPC as usize];
+        if !self.is_running() {
+            Ok(StepResult::Halted)
+        } else if let Some((addr, old, new)) = self.watch_hit {
+            Ok(StepResult::Watchpoint { addr, old, new })
+        } else if self.breakpoint_hit(pc) {
+            Ok(StepResult::Breakpoint(pc))
+        } else if let Some(vector) = self.trap_breakpoint_hit(pc) {
+            Ok(StepResult::TrapBreakpoint { pc, vector })
+        } else if let Some(code) = self.trap {
+            Ok(StepResult::Trap(code))
+        } else {
+            Ok(StepResult::Continued)
+        }
+    }
+}
+
+// instruction handlers, indexed by opcode
+type Handler = fn(&mut Vm, u16) -> Result<(), VmError>;
+
+const DISPATCH: [Handler; 16] = [
+    op_br, op_add, op_ld, op_st, op_jsr, op_and, op_ldr, op_str, op_rti, op_not, op_ldi, op_sti,
+    op_jmp, op_res, op_lea, op_trap,
+];
+
+fn op_br(vm: &mut Vm, instr: u16) -> Result<(), VmError> {
+    let pc_offset = sign_extend(instr & 0x1FF, 9);
+    // n = 4, z = 2, p = 1; no bits set never branches, or tests the
+    // overflow flag when that extension is on
+    let cond_flag = (instr >> 9) & 0x7;
+    let taken = if cond_flag == 0 && vm.overflow_flag {
+        vm.psr & PSR_OVERFLOW != 0
+    } else {
+        cond_flag & vm.psr & PSR_COND != 0
+    };
+    if taken {
+        vm.reg[Register::RPC as usize] = vm.reg[Register::RPC as usize].wrapping_add(pc_offset);
+    }
+    Ok(())
+}
+
+fn op_add(vm: &mut Vm, instr: u16) -> Result<(), VmError> {
+    let dr = (instr >> 9) & 0x7;
+    let sr1 = (instr >> 6) & 0x7;
+    let imm_flag = (instr >> 5) & 0x1;
+    let a = vm.reg[sr1 as usize];
+    let b = if imm_flag == 1 {
+        sign_extend(instr & 0x1F, 5)
+    } else {
+        vm.reg[(instr & 0x7) as usize]
+    };
+    let sum = a.wrapping_add(b);
+    vm.reg[dr as usize] = sum;
+    vm.update_flags(dr);
+    if vm.overflow_flag {
+        // both operands have the same sign and the sum the other one
+        let overflow = (a ^ sum) & (b ^ sum) & 0x8000 != 0;
+        vm.psr = (vm.psr & !PSR_OVERFLOW) | if overflow { PSR_OVERFLOW } else { 0 };
+    }
+    Ok(())
+}
+
+fn op_ld(vm: &mut Vm, instr: u16) -> Result<(), VmError> {
+    let dr = (instr >> 9) & 0x7;
+    let pc_offset = sign_extend(instr & 0x1FF, 9);
+    vm.reg[dr as usize] = vm.mem_read(vm.reg[Register::RPC as usize].wrapping_add(pc_offset));
+    vm.update_flags(dr);
+    Ok(())
+}
+
+fn op_st(vm: &mut Vm, instr: u16) -> Result<(), VmError> {
+    let sr = (instr >> 9) & 0x7;
+    let pc_offset = sign_extend(instr & 0x1FF, 9);
+    vm.mem_write(
+        vm.reg[Register::RPC as usize].wrapping_add(pc_offset),
+        vm.reg[sr as usize],
+    );
+    Ok(())
+}
+
+fn op_jsr(vm: &mut Vm, instr: u16) -> Result<(), VmError> {
+    // compute the target before R7 is overwritten so that
+    // JSRR R7 jumps to R7's old value
+    let return_addr = vm.reg[Register::RPC as usize];
+    let flag = (instr >> 11) & 1;
+    let target = if flag == 0 {
+        let base_r = (instr >> 6) & 0x7;
+        vm.reg[base_r as usize]
+    } else {
+        let pc_offset = sign_extend(instr & 0x7FF, 11);
+        return_addr.wrapping_add(pc_offset)
+    };
+    vm.reg[Register::RR7 as usize] = return_addr;
+    vm.reg[Register::RPC as usize] = target;
+    vm.push_frame(Frame {
+        caller: return_addr.wrapping_sub(1),
+        target,
+    });
+    Ok(())
+}
+
+fn op_and(vm: &mut Vm, instr: u16) -> Result<(), VmError> {
+    let dr = (instr >> 9) & 0x7;
+    let sr1 = (instr >> 6) & 0x7;
+    let imm_flag = (instr >> 5) & 0x1;
+    if imm_flag == 1 {
+        let imm5 = sign_extend(instr & 0x1F, 5);
+        vm.reg[dr as usize] = vm.reg[sr1 as usize] & imm5;
+    } else {
+        let sr2 = instr & 0x7;
+        vm.reg[dr as usize] = vm.reg[sr1 as usize] & vm.reg[sr2 as usize];
+    }
+    vm.update_flags(dr);
+    Ok(())
+}
+
+fn op_ldr(vm: &mut Vm, instr: u16) -> Result<(), VmError> {
+    let dr = (instr >> 9) & 0x7;
+    let base_r = (instr >> 6) & 0x7;
+    // signed: -32..=31 words from the base, wrapping around memory
+    let offset = sign_extend(instr & 0x3F, 6);
+    vm.reg[dr as usize] = vm.mem_read(vm.reg[base_r as usize].wrapping_add(offset));
+    vm.update_flags(dr);
+    Ok(())
+}
+
+fn op_str(vm: &mut Vm, instr: u16) -> Result<(), VmError> {
+    let sr = (instr >> 9) & 0x7;
+    let base_r = (instr >> 6) & 0x7;
+    // signed: -32..=31 words from the base, wrapping around memory
+    let offset = sign_extend(instr & 0x3F, 6);
+    vm.mem_write(
+        vm.reg[base_r as usize].wrapping_add(offset),
+        vm.reg[sr as usize],
+    );
+    Ok(())
+}
+
+fn op_rti(vm: &mut Vm, _instr: u16) -> Result<(), VmError> {
+    let instr_pc = vm.reg[Register::RPC as usize].wrapping_sub(1);
+    if vm.psr & PSR_USER != 0 {
+        if !vm.raise_exception(Exception::PrivilegeViolation) {
+            return Err(VmError::PrivilegeViolation { pc: instr_pc });
+        }
+    } else {
+        vm.reg[Register::RPC as usize] = vm.pop();
+        let psr = vm.pop();
+        vm.psr = psr;
+        if psr & PSR_USER != 0 {
+            vm.saved_ssp = vm.reg[Register::RR6 as usize];
+            vm.reg[Register::RR6 as usize] = vm.saved_usp;
+        }
+    }
+    Ok(())
+}
+
+fn op_not(vm: &mut Vm, instr: u16) -> Result<(), VmError> {
+    let dr = (instr >> 9) & 0x7;
+    let sr1 = (instr >> 6) & 0x7;
+    vm.reg[dr as usize] = !vm.reg[sr1 as usize];
+    vm.update_flags(dr);
+    Ok(())
+}
+
+fn op_ldi(vm: &mut Vm, instr: u16) -> Result<(), VmError> {
+    let dr = (instr >> 9) & 0x7;
+    let pc_offset = sign_extend(instr & 0x1FF, 9);
+    // the word at PC + offset (wrapping) holds the operand's address
+    let address = vm.mem_read(vm.reg[Register::RPC as usize].wrapping_add(pc_offset));
+    vm.reg[dr as usize] = vm.mem_read(address);
+    vm.update_flags(dr);
+    Ok(())
+}
+
+fn op_sti(vm: &mut Vm, instr: u16) -> Result<(), VmError> {
+    let sr = (instr >> 9) & 0x7;
+    let pc_offset = sign_extend(instr & 0x1FF, 9);
+    // the word at PC + offset (wrapping) holds the target address
+    let address = vm.mem_read(vm.reg[Register::RPC as usize].wrapping_add(pc_offset));
+    vm.mem_write(address, vm.reg[sr as usize]);
+    Ok(())
+}
+
+fn op_jmp(vm: &mut Vm, instr: u16) -> Result<(), VmError> {
+    let base_r = (instr >> 6) & 0x7;
+    vm.reg[Register::RPC as usize] = vm.reg[base_r as usize];
+    if base_r == Register::RR7 as u16 {
+        vm.pop_frame(vm.reg[base_r as usize]);
+    }
+    Ok(())
+}
+
+fn op_res(vm: &mut Vm, instr: u16) -> Result<(), VmError> {
+    let instr_pc = vm.reg[Register::RPC as usize].wrapping_sub(1);
+    if !vm.raise_exception(Exception::IllegalOpcode) {
+        return Err(VmError::IllegalOpcode {
+            op: instr,
+            pc: instr_pc,
+        });
+    }
+    Ok(())
+}
+
+fn op_lea(vm: &mut Vm, instr: u16) -> Result<(), VmError> {
+    let dr = (instr >> 9) & 0x7;
+    let pc_offset = sign_extend(instr & 0x1FF, 9);
+    vm.reg[dr as usize] = vm.reg[Register::RPC as usize].wrapping_add(pc_offset);
+    if vm.lea_sets_cc {
+        vm.update_flags(dr);
+    }
+    Ok(())
+}
+
+fn op_trap(vm: &mut Vm, instr: u16) -> Result<(), VmError> {
+    let trap = decode_trap(instr & 0xFF);
+    vm.trap = trap;
+    if vm.hook.is_some() {
+        vm.emit(HookEvent::TrapExecuted {
+            vector: instr & 0xFF,
+        });
+    }
+    // a service routine installed in the trap vector table takes
+    // precedence over the built-in handlers
+    let routine = vm.mem_read(instr & 0xFF);
+    if routine != 0 {
+        vm.reg[Register::RR7 as usize] = vm.reg[Register::RPC as usize];
+        vm.reg[Register::RPC as usize] = routine;
+    } else {
+        vm.builtin_trap(instr & 0xFF)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::{LoadError, LoadOptions};
+
+    #[test]
+    fn new_vm_starts_at_x3000_with_zero_flag() {
+        let vm = Vm::new();
+        assert_eq!(vm.reg[Register::RPC as usize], 0x3000);
+        assert_eq!(vm.psr() & PSR_COND, ConditionFlag::ZRO as u16);
+        assert!(vm.is_running());
+    }
+
+    #[test]
+    fn step_executes_the_instruction_at_pc() {
+        let mut vm = Vm::new();
+        vm.memory[0x3000] = 0x1021; // ADD R0, R0, #1
+        vm.step().unwrap();
+        assert_eq!(vm.reg[Register::RR0 as usize], 1);
+        assert_eq!(vm.reg[Register::RPC as usize], 0x3001);
+    }
+
+    #[test]
+    fn run_stops_at_halt() {
+        let mut vm = Vm::new();
+        vm.memory[0x3000] = 0xF025; // HALT
+        vm.run().unwrap();
+        assert!(!vm.is_running());
+    }
+
+    #[test]
+    fn add_wraps_to_zero_and_sets_zero_flag() {
+        let mut vm = Vm::new();
+        vm.reg[Register::RR1 as usize] = 0xFFFF;
+        vm.reg[Register::RR2 as usize] = 0x0001;
+        vm.memory[0x3000] = 0x1042; // ADD R0, R1, R2
+        vm.step().unwrap();
+        assert_eq!(vm.reg[Register::RR0 as usize], 0x0000);
+        assert_eq!(vm.psr() & PSR_COND, ConditionFlag::ZRO as u16);
+    }
+
+    #[test]
+    fn pc_relative_load_wraps_to_low_memory() {
+        let mut vm = Vm::new();
+        vm.reg[Register::RPC as usize] = 0xFFF0;
+        vm.memory[0xFFF0] = 0x2020; // LD R0, #32 -> x0011
+        vm.memory[0x0011] = 0x1234;
+        vm.step().unwrap();
+        assert_eq!(vm.reg[Register::RR0 as usize], 0x1234);
+    }
+
+    #[test]
+    fn update_flags_reads_the_register_value() {
+        let mut vm = Vm::new();
+        vm.reg[Register::RR3 as usize] = 0x8000;
+        vm.update_flags(Register::RR3 as u16);
+        assert_eq!(vm.psr() & PSR_COND, ConditionFlag::NEG as u16);
+        vm.reg[Register::RR3 as usize] = 0;
+        vm.update_flags(Register::RR3 as u16);
+        assert_eq!(vm.psr() & PSR_COND, ConditionFlag::ZRO as u16);
+        vm.reg[Register::RR3 as usize] = 1;
+        vm.update_flags(Register::RR3 as u16);
+        assert_eq!(vm.psr() & PSR_COND, ConditionFlag::POS as u16);
+    }
+
+    #[test]
+    fn rti_restores_pc_and_psr_from_the_supervisor_stack() {
+        let mut vm = Vm::new();
+        vm.psr = 0x0000;
+        vm.saved_usp = 0xF000;
+        // frame pushed by an interrupt taken in user mode at x3050
+        vm.reg[Register::RR6 as usize] = 0x2FFE;
+        vm.memory[0x2FFE] = 0x3050;
+        vm.memory[0x2FFF] = PSR_USER | ConditionFlag::POS as u16;
+        vm.reg[Register::RPC as usize] = 0x1000;
+        vm.memory[0x1000] = 0x8000; // RTI
+        vm.step().unwrap();
+        assert_eq!(vm.reg[Register::RPC as usize], 0x3050);
+        assert_eq!(vm.psr(), PSR_USER | ConditionFlag::POS as u16);
+        assert_eq!(vm.reg[Register::RR6 as usize], 0xF000);
+        assert_eq!(vm.saved_ssp, 0x3000);
+    }
+
+    #[test]
+    fn rti_in_user_mode_raises_a_privilege_violation() {
+        let mut vm = Vm::new();
+        vm.memory[INTERRUPT_VECTOR_TABLE as usize] = 0x1000;
+        vm.reg[Register::RR6 as usize] = 0xF000;
+        vm.memory[0x3000] = 0x8000; // RTI
+        vm.step().unwrap();
+        assert_eq!(vm.reg[Register::RPC as usize], 0x1000);
+        assert_eq!(vm.psr & PSR_USER, 0);
+        assert_eq!(vm.saved_usp, 0xF000);
+        assert_eq!(vm.reg[Register::RR6 as usize], SSP_START - 2);
+        assert_eq!(vm.memory[(SSP_START - 2) as usize], 0x3001);
+        assert_eq!(
+            vm.memory[(SSP_START - 1) as usize],
+            PSR_USER | ConditionFlag::ZRO as u16
+        );
+    }
+
+    #[test]
+    fn run_stops_at_a_breakpoint() {
+        let mut vm = Vm::new();
+        // ADD R0, R0, #1 three times, then HALT
+        vm.memory[0x3000..0x3004].copy_from_slice(&[0x1021, 0x1021, 0x1021, 0xF025]);
+        vm.add_breakpoint(0x3002);
+        assert_eq!(vm.run(), Ok(StepResult::Breakpoint(0x3002)));
+        assert_eq!(vm.reg[Register::RPC as usize], 0x3002);
+        assert_eq!(vm.reg[Register::RR0 as usize], 2);
+
+        assert!(vm.remove_breakpoint(0x3002));
+        assert_eq!(vm.run(), Ok(StepResult::Halted));
+        assert_eq!(vm.reg[Register::RR0 as usize], 3);
+        assert_eq!(vm.step(), Ok(StepResult::Halted));
+    }
+
+    #[test]
+    fn step_reports_service_routine_traps() {
+        let mut vm = Vm::new();
+        vm.memory[0x3000] = 0xF021; // OUT
+        assert_eq!(vm.step(), Ok(StepResult::Trap(TrapCode::OUT)));
+    }
+
+    #[test]
+    fn dump_registers_shows_hex_and_signed_values() {
+        let mut vm = Vm::new();
+        // AND R0, R0, #0; ADD R0, R0, #5; ADD R1, R0, #-6; HALT
+        vm.memory[0x3000..0x3004].copy_from_slice(&[0x5020, 0x1025, 0x123A, 0xF025]);
+        assert_eq!(vm.run(), Ok(StepResult::Halted));
+        let dump = vm.dump_registers();
+        assert!(dump.contains("R0 x0005  5\n"), "{}", dump);
+        assert!(dump.contains("R1 xFFFF  -1\n"), "{}", dump);
+        assert!(dump.contains("PC x3004  12292\n"), "{}", dump);
+        assert!(dump.ends_with("CC n\n"), "{}", dump);
+    }
+
+    #[test]
+    fn dump_registers_can_hide_the_signed_column() {
+        let mut vm = Vm::new();
+        vm.reg[1] = 0xFFFF;
+        assert!(vm.dump_registers().contains("R1 xFFFF  -1\n"));
+        vm.show_signed = false;
+        let dump = vm.dump_registers();
+        assert!(dump.contains("R1 xFFFF\n"), "{}", dump);
+        assert!(!dump.contains("-1"), "{}", dump);
+    }
+
+    #[test]
+    fn counts_executed_instructions_per_opcode() {
+        let mut vm = Vm::new();
+        // R1 = 5; loop: ADD R0, R0, #1; ADD R1, R1, #-1; BRp loop; HALT
+        let program = [0x5260, 0x1265, 0x1021, 0x127F, 0x03FD, 0xF025];
+        vm.memory[0x3000..0x3006].copy_from_slice(&program);
+        assert_eq!(vm.run(), Ok(StepResult::Halted));
+        assert_eq!(vm.reg[Register::RR0 as usize], 5);
+        assert_eq!(vm.op_counts[&Instruction::ADD], 11);
+        assert_eq!(vm.op_counts[&Instruction::BR], 5);
+        assert_eq!(vm.instr_count, 18);
+        assert!(vm.stats().starts_with("instructions executed: 18\n"));
+    }
+
+    #[test]
+    fn watchpoint_reports_old_and_new_values() {
+        let mut vm = Vm::new();
+        vm.reg[Register::RPC as usize] = 0x3FF0;
+        vm.reg[Register::RR0 as usize] = 7;
+        vm.memory[0x3FF0] = 0x300F; // ST R0, #15 -> x4000
+        vm.memory[0x3FF1] = 0xF025; // HALT
+        vm.memory[0x4000] = 3;
+        vm.add_watchpoint(0x4000);
+        assert_eq!(
+            vm.run(),
+            Ok(StepResult::Watchpoint {
+                addr: 0x4000,
+                old: 3,
+                new: 7
+            })
+        );
+        assert_eq!(vm.reg[Register::RPC as usize], 0x3FF1);
+        assert_eq!(vm.memory[0x4000], 7);
+        assert_eq!(vm.run(), Ok(StepResult::Halted));
+    }
+
+    #[test]
+    fn snapshot_round_trips_the_machine_state() {
+        let mut vm = Vm::new();
+        vm.memory[0x3000..0x3003].copy_from_slice(&[0x1021, 0x1021, 0xF025]);
+        vm.step().unwrap();
+        vm.saved_usp = 0xBEEF;
+        let snapshot = vm.save_snapshot();
+        let (memory, reg, psr) = (vm.memory, vm.reg, vm.psr);
+
+        vm.memory[0x4000] = 0x1234;
+        vm.saved_usp = 0;
+        assert_eq!(vm.run(), Ok(StepResult::Halted));
+
+        vm.load_snapshot(&snapshot).unwrap();
+        assert!(vm.memory == memory);
+        assert_eq!(vm.reg, reg);
+        assert_eq!(vm.psr, psr);
+        assert_eq!(vm.saved_usp, 0xBEEF);
+        assert_eq!(vm.saved_ssp, SSP_START);
+        assert!(vm.is_running());
+    }
+
+    #[test]
+    fn load_snapshot_rejects_bad_input() {
+        let mut vm = Vm::new();
+        let mut snapshot = vm.save_snapshot();
+        assert_eq!(vm.load_snapshot(b"LC3"), Err(SnapshotError::BadMagic));
+        assert_eq!(
+            vm.load_snapshot(&snapshot[..snapshot.len() - 1]),
+            Err(SnapshotError::Truncated)
+        );
+        snapshot[4] = 9;
+        assert_eq!(
+            vm.load_snapshot(&snapshot),
+            Err(SnapshotError::UnsupportedVersion(9))
+        );
+    }
+
+    #[test]
+    fn timer_advances_with_each_instruction() {
+        let mut vm = Vm::new();
+        // LDI R0, TMR; three ADDs; LDI R1, TMR; HALT; TMR .FILL xFE08
+        let program = [0xA005, 0x14A1, 0x14A1, 0x14A1, 0xA201, 0xF025, 0xFE08];
+        vm.memory[0x3000..0x3007].copy_from_slice(&program);
+        assert_eq!(vm.run(), Ok(StepResult::Halted));
+        let (first, second) = (
+            vm.reg[Register::RR0 as usize],
+            vm.reg[Register::RR1 as usize],
+        );
+        assert_eq!(second.wrapping_sub(first), 4);
+    }
+
+    #[test]
+    fn clearing_the_mcr_stops_the_machine() {
+        let mut vm = Vm::new();
+        assert_eq!(vm.memory[MemoryMappedRegister::MCR as usize], 0x8000);
+        // AND R0, R0, #0; STI R0, MCR; ADD R1, R1, #1; MCR .FILL xFFFE
+        vm.memory[0x3000..0x3004].copy_from_slice(&[0x5020, 0xB001, 0x1261, 0xFFFE]);
+        assert_eq!(vm.run(), Ok(StepResult::Halted));
+        assert_eq!(vm.reg[Register::RPC as usize], 0x3002);
+        assert_eq!(vm.reg[Register::RR1 as usize], 0);
+    }
+
+    #[test]
+    fn br_branches_only_on_matching_condition_codes() {
+        let flags = [
+            ConditionFlag::NEG as u16,
+            ConditionFlag::ZRO as u16,
+            ConditionFlag::POS as u16,
+        ];
+        for mask in 0..8u16 {
+            for flag in flags {
+                let mut vm = Vm::new();
+                vm.psr = PSR_USER | flag;
+                vm.memory[0x3000] = (mask << 9) | 0x10; // BR<mask> #16
+                vm.step().unwrap();
+                let expected = if mask & flag != 0 { 0x3011 } else { 0x3001 };
+                assert_eq!(
+                    vm.reg[Register::RPC as usize],
+                    expected,
+                    "mask {:03b}, flag {:03b}",
+                    mask,
+                    flag
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn and_sets_the_condition_codes() {
+        let mut vm = Vm::new();
+        vm.reg[Register::RR1 as usize] = 0x8001;
+        vm.memory[0x3000] = 0x5061; // AND R0, R1, #1
+        vm.memory[0x3001] = 0x507F; // AND R0, R1, #-1
+        vm.step().unwrap();
+        assert_eq!(vm.psr() & PSR_COND, ConditionFlag::POS as u16);
+        vm.step().unwrap();
+        assert_eq!(vm.psr() & PSR_COND, ConditionFlag::NEG as u16);
+    }
+
+    #[test]
+    fn trap_jumps_to_an_installed_service_routine() {
+        let mut vm = Vm::new();
+        vm.memory[0x0025] = 0x1000;
+        // routine: ADD R1, R1, #1; RET
+        vm.memory[0x1000..0x1002].copy_from_slice(&[0x1261, 0xC1C0]);
+        vm.memory[0x3000] = 0xF025; // HALT
+        vm.step().unwrap();
+        assert!(vm.is_running());
+        assert_eq!(vm.reg[Register::RPC as usize], 0x1000);
+        assert_eq!(vm.reg[Register::RR7 as usize], 0x3001);
+        vm.step().unwrap();
+        vm.step().unwrap();
+        assert_eq!(vm.reg[Register::RR1 as usize], 1);
+        assert_eq!(vm.reg[Register::RPC as usize], 0x3001);
+    }
+
+    #[test]
+    fn keyboard_interrupt_enters_the_service_routine() {
+        let mut vm = Vm::new();
+        vm.reg[Register::RR6 as usize] = 0xF000;
+        vm.memory[(INTERRUPT_VECTOR_TABLE + KEYBOARD_VECTOR) as usize] = 0x2000;
+        vm.memory[0x2000] = 0x1261; // ADD R1, R1, #1
+
+        // a key is latched with interrupts enabled
+        vm.keyboard.status = KBSR_INTERRUPT_ENABLE;
+        vm.keyboard.latch(b'a');
+        vm.step().unwrap();
+        assert_eq!(vm.reg[Register::RPC as usize], 0x2001);
+        assert_eq!(vm.reg[Register::RR1 as usize], 1);
+        assert_eq!(vm.psr & PSR_USER, 0);
+        assert_eq!((vm.psr & PSR_PRIORITY) >> 8, KEYBOARD_PRIORITY);
+        assert_eq!(vm.reg[Register::RR6 as usize], SSP_START - 2);
+        assert_eq!(vm.memory[(SSP_START - 2) as usize], 0x3000);
+    }
+
+    #[test]
+    fn psr_holds_privilege_priority_and_condition_codes() {
+        let mut vm = Vm::new();
+        assert_eq!(vm.psr(), 0x8002);
+        vm.reg[1] = 0x8000;
+        vm.memory[0x3000] = 0x1060; // ADD R0, R1, #0
+        vm.step().unwrap();
+        assert_eq!(vm.psr(), 0x8004);
+
+        // a keyboard interrupt drops to supervisor mode at priority 4 and
+        // saves the user PSR on the supervisor stack
+        vm.memory[(INTERRUPT_VECTOR_TABLE + KEYBOARD_VECTOR) as usize] = 0x2000;
+        vm.keyboard.status = KBSR_INTERRUPT_ENABLE;
+        vm.keyboard.latch(b'a');
+        vm.memory[0x2000] = 0x5020; // AND R0, R0, #0
+        vm.step().unwrap();
+        assert_eq!(vm.reg[Register::RPC as usize], 0x2001);
+        assert_eq!(vm.psr(), 0x0402);
+        assert_eq!(vm.memory[(SSP_START - 1) as usize], 0x8004);
+    }
+
+    #[test]
+    fn keyboard_interrupt_waits_for_a_lower_priority() {
+        let mut vm = Vm::new();
+        vm.memory[(INTERRUPT_VECTOR_TABLE + KEYBOARD_VECTOR) as usize] = 0x2000;
+        vm.keyboard.status = KBSR_INTERRUPT_ENABLE;
+        vm.keyboard.latch(b'a');
+        vm.psr = (vm.psr & !PSR_PRIORITY) | (KEYBOARD_PRIORITY << 8);
+        vm.step().unwrap();
+        assert_eq!(vm.reg[Register::RPC as usize], 0x3001);
+    }
+
+    #[test]
+    fn echoes_scripted_input_headlessly() {
+        let mut vm = Vm::new();
+        // loop: GETC; OUT; BRnzp loop
+        vm.memory[0x3000..0x3003].copy_from_slice(&[0xF020, 0xF021, 0x0FFD]);
+        vm.set_input(b"hi!".to_vec());
+        assert_eq!(vm.run(), Ok(StepResult::Halted));
+        assert_eq!(vm.take_output(), b"hi!");
+        assert!(vm.take_output().is_empty());
+    }
+
+    #[test]
+    fn headless_input_raises_the_keyboard_interrupt() {
+        let mut vm = Vm::new();
+        vm.memory[(INTERRUPT_VECTOR_TABLE + KEYBOARD_VECTOR) as usize] = 0x2000;
+        vm.keyboard.status = KBSR_INTERRUPT_ENABLE;
+        vm.memory[0x3000] = 0x0FFF; // BRnzp #-1
+        vm.set_input(Vec::new());
+        vm.step().unwrap();
+        assert_eq!(vm.reg[Register::RPC as usize], 0x3000);
+        vm.set_input(b"k".to_vec());
+        vm.step().unwrap();
+        assert_eq!(vm.reg[Register::RPC as usize], 0x2001);
+        assert_eq!(vm.memory[MemoryMappedRegister::KBDR as usize], b'k' as u16);
+    }
+
+    #[test]
+    fn run_with_limit_gives_up_on_a_self_loop() {
+        let mut vm = Vm::new();
+        vm.memory[0x3000] = 0x0FFF; // BRnzp #-1
+        assert_eq!(vm.run_with_limit(1000), RunOutcome::LimitReached);
+        assert_eq!(vm.instr_count, 1000);
+        assert_eq!(vm.reg[Register::RPC as usize], 0x3000);
+    }
+
+    #[test]
+    fn run_with_limit_reports_halts_and_breakpoints() {
+        let mut vm = Vm::new();
+        vm.memory[0x3000..0x3002].copy_from_slice(&[0x1021, 0xF025]);
+        vm.add_breakpoint(0x3001);
+        assert_eq!(
+            vm.run_with_limit(10),
+            RunOutcome::Stopped(StepResult::Breakpoint(0x3001))
+        );
+        assert_eq!(vm.run_with_limit(10), RunOutcome::Halted);
+    }
+
+    #[test]
+    fn reserved_opcode_is_an_illegal_opcode_error() {
+        let mut vm = Vm::new();
+        vm.memory[0x3000] = 0x1021;
+        vm.memory[0x3001] = 0xD123;
+        vm.step().unwrap();
+        assert_eq!(
+            vm.step(),
+            Err(VmError::IllegalOpcode {
+                op: 0xD123,
+                pc: 0x3001
+            })
+        );
+    }
+
+    #[test]
+    fn user_mode_rti_without_a_handler_is_a_privilege_violation() {
+        let mut vm = Vm::new();
+        vm.memory[0x3000] = 0x8000; // RTI
+        assert_eq!(vm.run(), Err(VmError::PrivilegeViolation { pc: 0x3000 }));
+    }
+
+    #[test]
+    fn reserved_opcode_enters_its_exception_handler() {
+        let mut vm = Vm::new();
+        vm.reg[Register::RR6 as usize] = 0xF000;
+        vm.memory[(INTERRUPT_VECTOR_TABLE + Exception::IllegalOpcode as u16) as usize] = 0x1000;
+        vm.memory[0x3000] = 0xD000; // RES
+        vm.step().unwrap();
+        assert_eq!(vm.reg[Register::RPC as usize], 0x1000);
+        assert_eq!(vm.psr() & PSR_USER, 0);
+        // the saved PC points past the faulting instruction
+        assert_eq!(vm.reg[Register::RR6 as usize], SSP_START - 2);
+        assert_eq!(vm.memory[(SSP_START - 2) as usize], 0x3001);
+        assert_eq!(vm.memory[(SSP_START - 1) as usize], 0x8002);
+        assert_eq!(vm.saved_usp, 0xF000);
+    }
+
+    #[test]
+    fn jsrr_r7_jumps_to_the_old_r7() {
+        let mut vm = Vm::new();
+        vm.reg[Register::RR7 as usize] = 0x4000;
+        vm.memory[0x3000] = 0x41C0; // JSRR R7
+        vm.step().unwrap();
+        assert_eq!(vm.reg[Register::RPC as usize], 0x4000);
+        assert_eq!(vm.reg[Register::RR7 as usize], 0x3001);
+    }
+
+    #[test]
+    fn jsr_saves_the_return_address() {
+        let mut vm = Vm::new();
+        vm.memory[0x3000] = 0x4C00; // JSR #-1024
+        vm.step().unwrap();
+        assert_eq!(vm.reg[Register::RPC as usize], 0x2C01);
+        assert_eq!(vm.reg[Register::RR7 as usize], 0x3001);
+    }
+
+    #[test]
+    fn cycles_follow_the_cost_table() {
+        let mut vm = Vm::new();
+        // AND R0, R0, #0; ADD R0, R0, #7; LD R1, #2; ST R0, #1; LDI R2, #0
+        vm.memory[0x3000..0x3005].copy_from_slice(&[0x5020, 0x1027, 0x2202, 0x3001, 0xA400]);
+        for _ in 0..5 {
+            vm.step().unwrap();
+        }
+        // 1 + 1 + 2 + 2 + 3
+        assert_eq!(vm.cycles(), 9);
+        vm.memory[0x3005] = 0xF025; // HALT
+        assert_eq!(vm.run(), Ok(StepResult::Halted));
+        assert_eq!(vm.cycles(), 11);
+    }
+
+    #[test]
+    fn step_back_restores_the_earlier_state() {
+        let mut vm = Vm::new();
+        vm.record_history = true;
+        // ADD R0, R0, #3; ST R0, #2; ADD R0, R0, #-1
+        vm.memory[0x3000..0x3003].copy_from_slice(&[0x1023, 0x3002, 0x103F]);
+        vm.step().unwrap();
+        let reg = vm.reg;
+        let memory = vm.memory;
+        let cycles = vm.cycles();
+        vm.step().unwrap();
+        vm.step().unwrap();
+        assert_eq!(vm.memory[0x3004], 3);
+        assert!(vm.step_back());
+        assert!(vm.step_back());
+        assert_eq!(vm.reg, reg);
+        assert!(vm.memory == memory);
+        assert_eq!(vm.cycles(), cycles);
+        assert!(vm.step_back());
+        assert!(!vm.step_back());
+        assert_eq!(vm.reg[Register::RPC as usize], 0x3000);
+    }
+
+    #[test]
+    fn step_back_needs_recorded_history() {
+        let mut vm = Vm::new();
+        vm.memory[0x3000] = 0x1023;
+        vm.step().unwrap();
+        assert!(!vm.step_back());
+        assert_eq!(vm.reg[0], 3);
+    }
+
+    #[test]
+    fn buffered_runs_never_touch_stdio() {
+        // the calls `WasmVm` makes: load from bytes, step, read registers
+        // and memory, drain output
+        let image = [
+            0x30, 0x00, 0xE0, 0x02, 0xF0, 0x22, 0xF0, 0x25, 0x00, 0x48, 0x00, 0x00,
+        ];
+        let mut vm = Vm::new();
+        vm.set_input(Vec::new());
+        let range = vm
+            .read_image_file(&mut &image[..], LoadOptions::default())
+            .unwrap();
+        assert_eq!(range, 0x3000..0x3005);
+        vm.reg[Register::RPC as usize] = range.start as u16;
+        vm.step().unwrap();
+        assert_eq!(vm.reg[0], 0x3003);
+        assert_eq!(vm.memory[0x3003], 0x48);
+        while vm.is_running() {
+            vm.step().unwrap();
+        }
+        assert_eq!(vm.take_output(), b"HHALT\n");
+        assert!(vm.take_output().is_empty());
+    }
+
+    #[cfg(feature = "debug_mem")]
+    #[test]
+    fn reads_of_unwritten_memory_warn_once() {
+        let mut vm = Vm::new();
+        assert!(vm.check_initialized(0x4000));
+        assert!(!vm.check_initialized(0x4000));
+        vm.mem_write(0x4001, 7);
+        assert!(!vm.check_initialized(0x4001));
+        vm.load_words(0x5000, &[1, 2]).unwrap();
+        assert!(!vm.check_initialized(0x5001));
+        assert!(vm.check_initialized(0x5002));
+        // the vector tables and device registers are never flagged
+        assert!(!vm.check_initialized(0x0025));
+        assert!(!vm.check_initialized(0xFE00));
+    }
+
+    #[test]
+    fn coverage_records_only_the_taken_side_of_a_branch() {
+        let mut vm = Vm::new();
+        vm.enable_coverage();
+        // AND R0, R0, #0; BRz skip; ADD R0, R0, #1; skip: HALT
+        vm.memory[0x3000..0x3004].copy_from_slice(&[0x5020, 0x0401, 0x1021, 0xF025]);
+        assert_eq!(vm.run(), Ok(StepResult::Halted));
+        assert_eq!(
+            vm.executed_ranges(),
+            vec![(0x3000, 0x3001), (0x3003, 0x3003)]
+        );
+
+        // with the flag positive the branch falls through instead
+        let mut vm = Vm::new();
+        vm.enable_coverage();
+        vm.memory[0x3000..0x3004].copy_from_slice(&[0x1021, 0x0401, 0x1021, 0xF025]);
+        assert_eq!(vm.run(), Ok(StepResult::Halted));
+        assert_eq!(vm.executed_ranges(), vec![(0x3000, 0x3003)]);
+    }
+
+    #[test]
+    fn coverage_is_off_by_default() {
+        let mut vm = Vm::new();
+        vm.memory[0x3000] = 0xF025;
+        assert_eq!(vm.run(), Ok(StepResult::Halted));
+        assert!(vm.executed_ranges().is_empty());
+    }
+
+    #[test]
+    fn puts_stops_at_the_top_of_memory() {
+        let mut vm = Vm::new();
+        // LD R0, #2; PUTS; HALT; .FILL xFFFD
+        vm.memory[0x3000..0x3004].copy_from_slice(&[0x2002, 0xF022, 0xF025, 0xFFFD]);
+        // no terminator before the end of memory: xFFFE is the MCR, and the
+        // string must not wrap around to x0000
+        vm.memory[0xFFFD] = b'z' as u16;
+        vm.memory[0xFFFF] = b'!' as u16;
+        vm.memory[0x0000] = b'X' as u16;
+        vm.set_input(Vec::new());
+        assert_eq!(vm.run(), Ok(StepResult::Halted));
+        assert_eq!(vm.take_output(), b"z\0!HALT\n");
+        assert_eq!(vm.reg[0], 0xFFFD);
+    }
+
+    #[test]
+    fn puts_honours_max_string_len() {
+        let mut vm = Vm::new();
+        // LEA R0, #2; PUTS; HALT; then an unterminated run of 'a'
+        vm.memory[0x3000..0x3003].copy_from_slice(&[0xE002, 0xF022, 0xF025]);
+        vm.memory[0x3003..0x3100].fill(b'a' as u16);
+        vm.max_string_len = Some(4);
+        vm.set_input(Vec::new());
+        assert_eq!(vm.run(), Ok(StepResult::Halted));
+        assert_eq!(vm.take_output(), b"aaaaHALT\n");
+
+        // well-formed strings are unaffected
+        let mut vm = Vm::new();
+        vm.memory[0x3000..0x3006].copy_from_slice(&[0xE002, 0xF022, 0xF025, 0x48, 0x69, 0]);
+        vm.set_input(Vec::new());
+        assert_eq!(vm.run(), Ok(StepResult::Halted));
+        assert_eq!(vm.take_output(), b"HiHALT\n");
+        assert_eq!(vm.reg[0], 0x3003);
+    }
+
+    // what the program's OUTs of `bytes` print under `encoding`
+    fn printed(encoding: OutputEncoding, bytes: &[u8]) -> Vec<u8> {
+        let mut vm = Vm::new();
+        vm.set_input(Vec::new());
+        vm.output_encoding = encoding;
+        for (i, &b) in bytes.iter().enumerate() {
+            // LD R0, <byte i>; OUT, with the bytes stored after the HALT
+            vm.memory[0x3000 + 2 * i] = 0x2000 | (2 * bytes.len() - i) as u16;
+            vm.memory[0x3001 + 2 * i] = 0xF021;
+            vm.memory[0x3000 + 2 * bytes.len() + 1 + i] = b as u16;
+        }
+        vm.memory[0x3000 + 2 * bytes.len()] = 0xF025;
+        assert_eq!(vm.run(), Ok(StepResult::Halted));
+        let output = vm.take_output();
+        output[..output.len() - b"HALT\n".len()].to_vec()
+    }
+
+    #[test]
+    fn raw_output_writes_bytes_unchanged() {
+        assert_eq!(printed(OutputEncoding::Raw, &[0xE9, b'A']), [0xE9, b'A']);
+    }
+
+    #[test]
+    fn ascii_output_replaces_high_bytes() {
+        assert_eq!(printed(OutputEncoding::Ascii, &[0xE9, b'A']), b"?A");
+    }
+
+    #[test]
+    fn utf8_lossy_output_replaces_invalid_sequences() {
+        assert_eq!(
+            printed(OutputEncoding::Utf8Lossy, &[0xE9, b'A']),
+            "\u{FFFD}A".as_bytes()
+        );
+        // a complete multi-byte character passes through
+        assert_eq!(
+            printed(OutputEncoding::Utf8Lossy, "é".as_bytes()),
+            "é".as_bytes()
+        );
+    }
+
+    #[test]
+    fn output_encoding_parses_its_option_values() {
+        assert_eq!(OutputEncoding::parse("raw"), Some(OutputEncoding::Raw));
+        assert_eq!(OutputEncoding::parse("ascii"), Some(OutputEncoding::Ascii));
+        assert_eq!(
+            OutputEncoding::parse("utf8-lossy"),
+            Some(OutputEncoding::Utf8Lossy)
+        );
+        assert_eq!(OutputEncoding::parse("latin1"), None);
+    }
+
+    #[test]
+    fn rng_sequences_repeat_for_the_same_seed() {
+        let sequence = |seed: u32| {
+            let mut vm = Vm::new();
+            vm.seed_rng(seed);
+            (0..8)
+                .map(|_| vm.mem_read(MemoryMappedRegister::RNG as u16))
+                .collect::<Vec<u16>>()
+        };
+        let first = sequence(42);
+        assert_eq!(first, sequence(42));
+        assert_ne!(first, sequence(43));
+        // the values actually vary
+        assert!(first.windows(2).any(|w| w[0] != w[1]));
+    }
+
+    #[test]
+    fn hook_counts_memory_writes() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        let writes = Rc::new(Cell::new(0));
+        let mut vm = Vm::new();
+        let counter = Rc::clone(&writes);
+        vm.set_hook(Box::new(move |event| {
+            if let HookEvent::MemWrite { .. } = event {
+                counter.set(counter.get() + 1);
+            }
+        }));
+        // ST R0, #4; ST R0, #4; ST R0, #4; HALT
+        vm.memory[0x3000..0x3004].copy_from_slice(&[0x3004, 0x3004, 0x3004, 0xF025]);
+        vm.step().unwrap();
+        vm.step().unwrap();
+        vm.step().unwrap();
+        assert_eq!(writes.get(), 3);
+        vm.clear_hook();
+        vm.memory[0x3003] = 0x3004;
+        vm.step().unwrap();
+        assert_eq!(writes.get(), 3);
+    }
+
+    #[test]
+    fn hook_sees_fetches_register_writes_and_traps() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let mut vm = Vm::new();
+        vm.set_input(Vec::new());
+        let log = Rc::clone(&events);
+        vm.set_hook(Box::new(move |event| log.borrow_mut().push(*event)));
+        // ADD R2, R2, #3; OUT
+        vm.memory[0x3000..0x3002].copy_from_slice(&[0x14A3, 0xF021]);
+        vm.step().unwrap();
+        vm.step().unwrap();
+        assert_eq!(
+            *events.borrow(),
+            vec![
+                HookEvent::InstructionFetched {
+                    pc: 0x3000,
+                    instr: 0x14A3
+                },
+                HookEvent::RegWrite { reg: 2, value: 3 },
+                HookEvent::InstructionFetched {
+                    pc: 0x3001,
+                    instr: 0xF021
+                },
+                HookEvent::TrapExecuted { vector: 0x21 },
+            ]
+        );
+    }
+
+    #[test]
+    fn ldi_follows_the_pointer_and_sets_the_flags() {
+        for (data, flag) in [
+            (0x8000, ConditionFlag::NEG),
+            (0x0000, ConditionFlag::ZRO),
+            (0x0042, ConditionFlag::POS),
+        ] {
+            let mut vm = Vm::new();
+            vm.memory[0x3000] = 0xA201; // LDI R1, #1
+            vm.memory[0x3002] = 0x4000; // pointer
+            vm.memory[0x4000] = data;
+            vm.reg[1] = 0x1234;
+            vm.step().unwrap();
+            assert_eq!(vm.reg[1], data);
+            assert_eq!(vm.psr() & PSR_COND, flag as u16);
+        }
+    }
+
+    #[test]
+    fn sti_stores_through_the_pointer() {
+        let mut vm = Vm::new();
+        vm.memory[0x3000] = 0xB201; // STI R1, #1
+        vm.memory[0x3002] = 0x4000;
+        vm.reg[1] = 0xBEEF;
+        vm.step().unwrap();
+        assert_eq!(vm.memory[0x4000], 0xBEEF);
+        assert_eq!(vm.memory[0x3002], 0x4000);
+    }
+
+    #[test]
+    fn indirect_addresses_wrap_around_memory() {
+        let mut vm = Vm::new();
+        // LDI R0, #-256 from x0000 reads its pointer at xFF01
+        vm.reg[Register::RPC as usize] = 0x0000;
+        vm.memory[0x0000] = 0xA100;
+        vm.memory[0xFF01] = 0x5000;
+        vm.memory[0x5000] = 7;
+        vm.step().unwrap();
+        assert_eq!(vm.reg[0], 7);
+
+        // STI R0, #255 from xFFFF stores through x00FF
+        vm.reg[Register::RPC as usize] = 0xFFFF;
+        vm.memory[0xFFFF] = 0xB0FF;
+        vm.memory[0x00FF] = 0x5001;
+        vm.step().unwrap();
+        assert_eq!(vm.memory[0x5001], 7);
+    }
+
+    #[test]
+    fn dispatch_table_slots_match_their_opcodes() {
+        let handlers: [(Instruction, Handler); 16] = [
+            (Instruction::BR, op_br),
+            (Instruction::ADD, op_add),
+            (Instruction::LD, op_ld),
+            (Instruction::ST, op_st),
+            (Instruction::JSR, op_jsr),
+            (Instruction::AND, op_and),
+            (Instruction::LDR, op_ldr),
+            (Instruction::STR, op_str),
+            (Instruction::RTI, op_rti),
+            (Instruction::NOT, op_not),
+            (Instruction::LDI, op_ldi),
+            (Instruction::STI, op_sti),
+            (Instruction::JMP, op_jmp),
+            (Instruction::RES, op_res),
+            (Instruction::LEA, op_lea),
+            (Instruction::TRAP, op_trap),
+        ];
+        for (opcode, (op, handler)) in handlers.into_iter().enumerate() {
+            assert_eq!(decode(opcode as u16), op);
+            assert!(
+                std::ptr::fn_addr_eq(DISPATCH[opcode], handler),
+                "slot {} is not the {:?} handler",
+                opcode,
+                op
+            );
+        }
+    }
+
+    #[test]
+    fn each_handler_executes_its_instruction() {
+        // (instruction, check) pairs run through the table from a fresh VM
+        // with R1 = 5, R2 = x4000 and x3006 -> x1234 -> 9
+        type Check = fn(&Vm) -> bool;
+        let cases: [(u16, Check); 16] = [
+            (0x0E05, |vm| vm.reg[Register::RPC as usize] == 0x3006), // BRnzp #5
+            (0x1061, |vm| vm.reg[0] == 6),                           // ADD R0, R1, #1
+            (0x2005, |vm| vm.reg[0] == 0x1234),                      // LD R0, #5
+            (0x3205, |vm| vm.memory[0x3006] == 5),                   // ST R1, #5
+            (0x4805, |vm| vm.reg[Register::RR7 as usize] == 0x3001), // JSR #5
+            (0x5063, |vm| vm.reg[0] == 1),                           // AND R0, R1, #3
+            (0x6080, |vm| vm.reg[0] == 0x4001),                      // LDR R0, R2, #0
+            (0x7281, |vm| vm.memory[0x4001] == 5),                   // STR R1, R2, #1
+            (0x8000, |vm| vm.reg[Register::RPC as usize] == 0x1000), // RTI in user mode
+            (0x907F, |vm| vm.reg[0] == 0xFFFA),                      // NOT R0, R1
+            (0xA005, |vm| vm.reg[0] == 9),                           // LDI R0, #5 -> x1234
+            (0xB205, |vm| vm.memory[0x1234] == 5),                   // STI R1, #5
+            (0xC080, |vm| vm.reg[Register::RPC as usize] == 0x4000), // JMP R2
+            (0xD000, |vm| vm.reg[Register::RPC as usize] == 0x1100), // RES
+            (0xE005, |vm| vm.reg[0] == 0x3006),                      // LEA R0, #5
+            (0xF040, |vm| vm.reg[Register::RPC as usize] == 0x2000), // TRAP x40
+        ];
+        for (instr, check) in cases {
+            let mut vm = Vm::new();
+            vm.reg[1] = 5;
+            vm.reg[2] = 0x4000;
+            vm.memory[0x4000] = 0x4001;
+            vm.memory[0x3006] = 0x1234;
+            vm.memory[0x1234] = 9;
+            vm.memory[0x0040] = 0x2000;
+            vm.memory[(INTERRUPT_VECTOR_TABLE + Exception::PrivilegeViolation as u16) as usize] =
+                0x1000;
+            vm.memory[(INTERRUPT_VECTOR_TABLE + Exception::IllegalOpcode as u16) as usize] = 0x1100;
+            // as `step` leaves it after the fetch
+            vm.reg[Register::RPC as usize] = 0x3001;
+            DISPATCH[(instr >> 12) as usize](&mut vm, instr).unwrap();
+            assert!(check(&vm), "x{:04X}", instr);
+        }
+    }
+
+    #[test]
+    fn with_pc_fetches_the_first_instruction_from_there() {
+        let mut vm = Vm::with_pc(0x0200);
+        vm.memory[0x0200] = 0x1025; // ADD R0, R0, #5
+        vm.step().unwrap();
+        assert_eq!(vm.reg[0], 5);
+        assert_eq!(vm.reg[Register::RPC as usize], 0x0201);
+    }
+
+    #[test]
+    fn getc_at_the_end_of_input_follows_on_eof() {
+        // GETC; ADD R1, R1, #1; HALT
+        let program = [0xF020, 0x1261, 0xF025];
+        let run = |on_eof| {
+            let mut vm = Vm::new();
+            vm.set_input(Vec::new());
+            vm.on_eof = on_eof;
+            vm.reg[0] = 7;
+            vm.memory[0x3000..0x3003].copy_from_slice(&program);
+            let result = vm.run();
+            (vm, result)
+        };
+
+        let (vm, result) = run(EofBehavior::Halt);
+        assert!(result.is_ok());
+        assert!(!vm.is_running());
+        assert_eq!(vm.reg[1], 0);
+
+        let (vm, result) = run(EofBehavior::Zero);
+        assert!(result.is_ok());
+        assert_eq!(vm.reg[0], 0);
+        assert_eq!(vm.reg[1], 1);
+
+        let (_, result) = run(EofBehavior::Error);
+        assert_eq!(result, Err(VmError::InputExhausted { pc: 0x3000 }));
+    }
+
+    #[test]
+    fn reset_restores_registers_and_keeps_memory() {
+        let mut vm = Vm::new();
+        vm.memory[0x3000..0x3003].copy_from_slice(&[0x1025, 0x127F, 0xF025]);
+        vm.run().unwrap();
+        assert_eq!(vm.reg[1], 0xFFFF);
+        vm.reset();
+        assert_eq!(vm.reg[..8], [0; 8]);
+        assert_eq!(vm.reg[Register::RPC as usize], PC_START);
+        assert_eq!(vm.psr(), PSR_USER | ConditionFlag::ZRO as u16);
+        assert_eq!(vm.memory[MemoryMappedRegister::MCR as usize], 1 << 15);
+        assert_eq!(vm.instr_count, 0);
+        assert_eq!(vm.memory[0x3000], 0x1025);
+
+        // and the program runs again
+        vm.run().unwrap();
+        assert_eq!(vm.reg[0], 5);
+    }
+
+    #[test]
+    fn cold_reset_clears_memory_too() {
+        let mut vm = Vm::new();
+        vm.memory[0x3000..0x3002].copy_from_slice(&[0x1025, 0xF025]);
+        vm.run().unwrap();
+        vm.cold_reset();
+        assert_eq!(vm.reg[..8], [0; 8]);
+        assert_eq!(vm.reg[Register::RPC as usize], PC_START);
+        assert_eq!(vm.psr(), PSR_USER | ConditionFlag::ZRO as u16);
+        assert_eq!(vm.memory[MemoryMappedRegister::MCR as usize], 1 << 15);
+        assert_eq!(vm.memory[0x3000], 0);
+        assert_eq!(vm.memory[0x3001], 0);
+    }
+
+    #[test]
+    fn lea_sets_the_condition_codes_only_when_asked() {
+        for (lea_sets_cc, flag) in [(false, ConditionFlag::ZRO), (true, ConditionFlag::NEG)] {
+            let mut vm = Vm::with_pc(0x8000);
+            vm.lea_sets_cc = lea_sets_cc;
+            vm.memory[0x8000] = 0xE000; // LEA R0, #0
+            vm.step().unwrap();
+            assert_eq!(vm.reg[0], 0x8001);
+            assert_eq!(vm.psr() & PSR_COND, flag as u16);
+        }
+    }
+
+    #[test]
+    fn protected_vector_writes_fault_in_user_mode() {
+        let store = |protect_vectors| {
+            let mut vm = Vm::new();
+            vm.protect_vectors = protect_vectors;
+            vm.reg[0] = 0x1234;
+            vm.reg[1] = 0x0010;
+            vm.memory[0x3000] = 0x7040; // STR R0, R1, #0
+            (vm.step(), vm.memory[0x0010])
+        };
+        assert_eq!(
+            store(true),
+            (
+                Err(VmError::AccessViolation {
+                    pc: 0x3000,
+                    addr: 0x0010
+                }),
+                0
+            )
+        );
+        assert_eq!(store(false), (Ok(StepResult::Continued), 0x1234));
+    }
+
+    #[test]
+    fn protected_vectors_let_os_trap_routines_write() {
+        let mut vm = Vm::new();
+        vm.protect_vectors = true;
+        vm.reg[6] = 0xFD00;
+        // HALT at x1000: STI R0, MCRP; MCRP .FILL xFFFE
+        vm.memory[0x25] = 0x1000;
+        vm.memory[0x1000] = 0xB001;
+        vm.memory[0x1002] = MemoryMappedRegister::MCR as u16;
+        vm.memory[0x3000] = 0xF025;
+        vm.step().unwrap();
+        assert_eq!(vm.reg[Register::RPC as usize], 0x1000);
+        assert_eq!(vm.step(), Ok(StepResult::Halted));
+    }
+
+    #[test]
+    fn protected_trap_routines_return_with_ret_in_user_mode() {
+        let mut vm = Vm::new();
+        vm.protect_vectors = true;
+        vm.reg[6] = 0xFD00;
+        vm.reg[0] = 0x1234;
+        // TRAP x30 at x1010: STI R0, PTR; RET; PTR .FILL x0010
+        vm.memory[0x30] = 0x1010;
+        vm.memory[0x1010..0x1013].copy_from_slice(&[0xB001, 0xC1C0, 0x0010]);
+        // then the same store from user code: TRAP x30; STI R0, PTR; PTR .FILL x0010
+        vm.memory[0x3000..0x3003].copy_from_slice(&[0xF030, 0xB000, 0x0010]);
+        for _ in 0..3 {
+            vm.step().unwrap();
+        }
+        assert_eq!(vm.memory[0x0010], 0x1234);
+        assert_eq!(vm.reg[Register::RPC as usize], 0x3001);
+        assert_eq!(vm.psr() & PSR_USER, PSR_USER);
+        assert_eq!(vm.reg[6], 0xFD00);
+        assert_eq!(
+            vm.step(),
+            Err(VmError::AccessViolation {
+                pc: 0x3001,
+                addr: 0x0010
+            })
+        );
+    }
+
+    #[test]
+    fn dumps_are_colored_only_when_color_is_on() {
+        let mut vm = Vm::new();
+        let before = vm.reg;
+        vm.reg[1] = 0xFFFF;
+        vm.reg[2] = 5;
+
+        let plain = vm.dump_registers_since(&before);
+        assert!(!plain.contains('\x1b'), "{:?}", plain);
+        assert!(!vm.dump_memory(0x3000, 8).contains('\x1b'));
+
+        vm.color = true;
+        let colored = vm.dump_registers_since(&before);
+        assert!(
+            colored.contains("\x1b[33mR2 x0005  5\x1b[0m"),
+            "{:?}",
+            colored
+        );
+        assert!(
+            colored.contains("\x1b[31mxFFFF  -1\x1b[0m"),
+            "{:?}",
+            colored
+        );
+        assert!(colored.contains("R0 x0000  0\n"));
+        assert!(vm.dump_memory(0x3000, 8).contains("\x1b[7mx0000\x1b[0m"));
+    }
+
+    #[test]
+    fn remapped_trap_vectors_jump_to_their_routine() {
+        let mut vm = Vm::new();
+        vm.set_trap_vector(0x25, 0x1000);
+        vm.memory[0x3000] = 0xF025; // HALT
+        vm.step().unwrap();
+        assert!(vm.is_running());
+        assert_eq!(vm.reg[Register::RPC as usize], 0x1000);
+        assert_eq!(vm.reg[7], 0x3001);
+
+        // 0 goes back to the built-in HALT
+        vm.set_trap_vector(0x25, 0);
+        vm.reg[Register::RPC as usize] = 0x3000;
+        vm.step().unwrap();
+        assert!(!vm.is_running());
+    }
+
+    #[test]
+    fn ldr_and_str_offsets_are_signed() {
+        for (offset, addr) in [(31i16, 0x401F), (-32, 0x3FE0), (-1, 0x3FFF)] {
+            let field = offset as u16 & 0x3F;
+            let mut vm = Vm::new();
+            vm.reg[1] = 0x4000;
+            vm.reg[2] = 0xBEEF;
+            vm.memory[addr] = 0x1234;
+            // LDR R0, R1, #offset; STR R2, R1, #offset
+            vm.memory[0x3000..0x3002].copy_from_slice(&[0x6040 | field, 0x7440 | field]);
+            vm.step().unwrap();
+            assert_eq!(vm.reg[0], 0x1234, "LDR with offset {}", offset);
+            vm.step().unwrap();
+            assert_eq!(vm.memory[addr], 0xBEEF, "STR with offset {}", offset);
+        }
+    }
+
+    #[test]
+    fn ldr_and_str_addresses_wrap_around_memory() {
+        let mut vm = Vm::new();
+        vm.reg[1] = 0xFFFF;
+        vm.reg[2] = 0x0042;
+        // STR R2, R1, #1; LDR R0, R1, #1
+        vm.memory[0x3000..0x3002].copy_from_slice(&[0x7441, 0x6041]);
+        vm.step().unwrap();
+        vm.step().unwrap();
+        assert_eq!(vm.memory[0x0000], 0x0042);
+        assert_eq!(vm.reg[0], 0x0042);
+    }
+
+    #[test]
+    fn quiet_machines_print_no_halt_banner() {
+        let mut vm = Vm::new();
+        vm.quiet = true;
+        vm.set_input(Vec::new());
+        // LEA R0, #2; PUTS; HALT; "ok"
+        vm.memory[0x3000..0x3006].copy_from_slice(&[0xE002, 0xF022, 0xF025, 0x006F, 0x006B, 0]);
+        vm.run().unwrap();
+        assert!(!vm.is_running());
+        assert_eq!(vm.take_output(), b"ok");
+    }
+
+    // main calls A at x3010, which saves R7 in R5 and calls B at x3020
+    fn nested_calls() -> Vm {
+        let mut vm = Vm::new();
+        vm.memory[0x3000..0x3002].copy_from_slice(&[0x480F, 0xF025]);
+        vm.memory[0x3010..0x3014].copy_from_slice(&[0x1BE0, 0x480E, 0x1F60, 0xC1C0]);
+        vm.memory[0x3020] = 0xC1C0;
+        vm
+    }
+
+    #[test]
+    fn call_stack_follows_nested_subroutines() {
+        let mut vm = nested_calls();
+        for _ in 0..3 {
+            vm.step().unwrap();
+        }
+        assert_eq!(vm.reg[Register::RPC as usize], 0x3020);
+        assert_eq!(
+            vm.call_stack(),
+            [
+                Frame {
+                    caller: 0x3000,
+                    target: 0x3010
+                },
+                Frame {
+                    caller: 0x3011,
+                    target: 0x3020
+                },
+            ]
+        );
+        assert_eq!(vm.call_stack()[1].return_addr(), 0x3012);
+
+        vm.step().unwrap();
+        assert_eq!(vm.call_stack().len(), 1);
+        vm.step().unwrap();
+        vm.step().unwrap();
+        assert_eq!(vm.reg[Register::RPC as usize], 0x3001);
+        assert!(vm.call_stack().is_empty());
+    }
+
+    #[test]
+    fn unmatched_returns_leave_the_call_stack_alone() {
+        let mut vm = nested_calls();
+        vm.step().unwrap();
+        // RET somewhere no call returns to
+        vm.reg[7] = 0x3013;
+        vm.reg[Register::RPC as usize] = 0x3020;
+        vm.step().unwrap();
+        assert_eq!(vm.call_stack().len(), 1);
+    }
+
+    #[test]
+    fn step_back_restores_the_call_stack() {
+        let mut vm = nested_calls();
+        vm.record_history = true;
+        for _ in 0..4 {
+            vm.step().unwrap();
+        }
+        assert_eq!(vm.call_stack().len(), 1);
+        assert!(vm.step_back());
+        assert_eq!(vm.call_stack().len(), 2);
+        assert!(vm.step_back());
+        assert!(vm.step_back());
+        assert_eq!(vm.call_stack().len(), 1);
+    }
+
+    // what PUTS prints for `text` with the given line ending translation
+    fn translated(newline: Newline, text: &[u8]) -> Vec<u8> {
+        let mut vm = Vm::new();
+        vm.set_input(Vec::new());
+        vm.quiet = true;
+        vm.newline = newline;
+        // LEA R0, #2; PUTS; HALT; the string
+        vm.memory[0x3000..0x3003].copy_from_slice(&[0xE002, 0xF022, 0xF025]);
+        for (i, &b) in text.iter().enumerate() {
+            vm.memory[0x3003 + i] = b as u16;
+        }
+        vm.run().unwrap();
+        vm.take_output()
+    }
+
+    #[test]
+    fn crlf_turns_line_feeds_into_cr_lf() {
+        assert_eq!(translated(Newline::Crlf, b"a\nb\r\n"), b"a\r\nb\r\n");
+    }
+
+    #[test]
+    fn lf_turns_cr_and_cr_lf_into_line_feeds() {
+        assert_eq!(translated(Newline::Lf, b"a\nb\r\nc\rd"), b"a\nb\nc\nd");
+    }
+
+    #[test]
+    fn line_endings_are_left_alone_by_default() {
+        assert_eq!(translated(Newline::AsIs, b"a\nb\r\nc\r"), b"a\nb\r\nc\r");
+        assert_eq!(Newline::parse("none"), Some(Newline::AsIs));
+        assert_eq!(Newline::parse("CRLF"), None);
+    }
+
+    #[test]
+    fn unknown_trap_vectors_follow_on_unknown_trap() {
+        // TRAP x30; ADD R0, R0, #1; HALT
+        let run = |on_unknown_trap| {
+            let mut vm = Vm::new();
+            vm.set_input(Vec::new());
+            vm.on_unknown_trap = on_unknown_trap;
+            vm.memory[0x3000..0x3003].copy_from_slice(&[0xF030, 0x1021, 0xF025]);
+            let result = vm.run();
+            (vm, result)
+        };
+
+        let (mut vm, result) = run(UnknownTrap::Error);
+        assert_eq!(
+            result,
+            Err(VmError::UnknownTrap {
+                pc: 0x3000,
+                vector: 0x30
+            })
+        );
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "unknown trap vector x30 at x3000"
+        );
+        assert!(vm.take_output().is_empty());
+
+        let (mut vm, result) = run(UnknownTrap::Ignore);
+        assert_eq!(result, Ok(StepResult::Halted));
+        assert_eq!(vm.reg[0], 1);
+        assert_eq!(vm.take_output(), b"HALT\n");
+
+        let (mut vm, _) = run(UnknownTrap::Halt);
+        assert_eq!(vm.reg[0], 0);
+        assert_eq!(vm.take_output(), b"Unknown trap code\n");
+    }
+
+    #[test]
+    fn hot_addresses_put_the_loop_body_first() {
+        let mut vm = Vm::new();
+        vm.enable_coverage();
+        vm.reg[1] = 7;
+        // loop: ADD R0, R0, #1; ADD R1, R1, #-1; BRp loop; HALT
+        vm.memory[0x3000..0x3004].copy_from_slice(&[0x1021, 0x127F, 0x03FD, 0xF025]);
+        vm.set_input(Vec::new());
+        assert_eq!(vm.run(), Ok(StepResult::Halted));
+        assert_eq!(vm.reg[0], 7);
+        assert_eq!(
+            vm.hot_addresses(4),
+            vec![(0x3000, 7), (0x3001, 7), (0x3002, 7), (0x3003, 1)]
+        );
+        assert_eq!(vm.hot_addresses(1), vec![(0x3000, 7)]);
+        assert_eq!(vm.executed_ranges(), vec![(0x3000, 0x3003)]);
+    }
+
+    #[test]
+    fn overflow_flag_is_set_by_signed_overflow_when_enabled() {
+        // ADD R0, R1, R2; BRv #1; ADD R3, R3, #1; HALT
+        let program = [0x1042, 0x0001, 0x16E1, 0xF025];
+        let run = |enabled: bool| {
+            let mut vm = Vm::new();
+            vm.overflow_flag = enabled;
+            vm.reg[1] = 0x7FFF;
+            vm.reg[2] = 0x0001;
+            vm.memory[0x3000..0x3004].copy_from_slice(&program);
+            vm.step().unwrap();
+            let psr = vm.psr();
+            vm.step().unwrap();
+            (psr, vm.reg[Register::RPC as usize])
+        };
+        let (psr, pc) = run(true);
+        assert_eq!(psr & PSR_OVERFLOW, PSR_OVERFLOW);
+        assert_eq!(psr & PSR_COND, ConditionFlag::NEG as u16);
+        assert_eq!(pc, 0x3003);
+        // off by default: no flag and BR with no n/z/p bits never branches
+        let (psr, pc) = run(false);
+        assert_eq!(psr & PSR_OVERFLOW, 0);
+        assert_eq!(pc, 0x3002);
+    }
+
+    #[test]
+    fn overflow_flag_clears_on_an_add_that_fits() {
+        let mut vm = Vm::new();
+        vm.overflow_flag = true;
+        vm.reg[1] = 0x8000;
+        // ADD R0, R1, #-1 overflows; ADD R0, R0, #-1 does not
+        vm.memory[0x3000..0x3002].copy_from_slice(&[0x107F, 0x103F]);
+        vm.step().unwrap();
+        assert_eq!(vm.psr() & PSR_OVERFLOW, PSR_OVERFLOW);
+        assert!(vm.dump_registers().contains("CC p v"));
+        vm.step().unwrap();
+        assert_eq!(vm.psr() & PSR_OVERFLOW, 0);
+    }
+
+    #[test]
+    fn twelve_bit_memory_wraps_at_4k() {
+        let mut vm = Vm::with_memory_bits(12);
+        assert_eq!(vm.memory_size(), 0x1000);
+        vm.mem_write(0x1001, 0x0042);
+        assert_eq!(vm.mem_read(0x0001), 0x0042);
+        assert_eq!(vm.mem_read(0x3001), 0x0042);
+        // the device registers stay where they are
+        assert_eq!(
+            vm.mem_read(MemoryMappedRegister::MCR as u16) & 0x8000,
+            0x8000
+        );
+        assert!(matches!(
+            vm.load_words(0x0FFF, &[1, 2]),
+            Err(LoadError::OriginOutOfRange)
+        ));
+        assert_eq!(Vm::new().memory_size(), MEMORY_SIZE);
+    }
+
+    #[test]
+    fn abi_names_label_the_stack_pointer_and_link_register() {
+        let mut vm = Vm::new();
+        vm.abi_names = true;
+        vm.reg[6] = 0xFE00;
+        let dump = vm.dump_registers();
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines[0], "R0    x0000  0");
+        assert_eq!(lines[6], "R6/SP xFE00  -512");
+        assert_eq!(lines[7], "R7/LR x0000  0");
+        assert_eq!(lines[8], "PC    x3000  12288");
+        assert_eq!(lines[9], "CC    z");
+        vm.abi_names = false;
+        assert!(vm.dump_registers().contains("\nR6 xFE00  -512\n"));
+    }
+}
@@ -0,0 +1,91 @@
+// JS-callable wrapper around `Vm` for browser builds. The VM always runs with
+// buffered I/O here: input is supplied with `set_input` and console output is
+// collected until `take_output` drains it.
+
+use std::io::Cursor;
+
+use wasm_bindgen::prelude::*;
+
+use crate::isa::Register;
+use crate::loader::LoadOptions;
+use crate::vm::Vm;
+
+#[wasm_bindgen]
+pub struct WasmVm {
+    vm: Box<Vm>,
+}
+
+impl Default for WasmVm {
+    fn default() -> WasmVm {
+        WasmVm::new()
+    }
+}
+
+#[wasm_bindgen]
+impl WasmVm {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> WasmVm {
+        let mut vm = Box::new(Vm::new());
+        vm.set_input(Vec::new());
+        WasmVm { vm }
+    }
+
+    // load an .obj image and start at its origin
+    pub fn load_bytes(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let range = self
+            .vm
+            .read_image_file(&mut Cursor::new(bytes), LoadOptions::default())
+            .map_err(|e| e.to_string())?;
+        self.vm.reg[Register::RPC as usize] = range.start as u16;
+        Ok(())
+    }
+
+    // execute one instruction; false once the program has halted
+    pub fn step(&mut self) -> Result<bool, String> {
+        self.vm.step().map_err(|e| e.to_string())?;
+        Ok(self.vm.is_running())
+    }
+
+    // R0-R7, PC and PSR
+    pub fn registers(&self) -> Vec<u16> {
+        let mut registers = self.vm.reg.to_vec();
+        registers.push(self.vm.psr());
+        registers
+    }
+
+    pub fn read_mem(&self, addr: u16) -> u16 {
+        self.vm.memory[addr as usize]
+    }
+
+    // replace the pending keyboard input
+    pub fn set_input(&mut self, bytes: &[u8]) {
+        self.vm.set_input(bytes.to_vec());
+    }
+
+    // console output written since the last call
+    pub fn take_output(&mut self) -> Vec<u8> {
+        self.vm.take_output()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_an_image_with_buffered_io() {
+        let mut vm = WasmVm::new();
+        vm.load_bytes(&[
+            0x30, 0x00, 0xE0, 0x02, 0xF0, 0x22, 0xF0, 0x25, 0x00, 0x48, 0x00, 0x00,
+        ])
+        .unwrap();
+        assert_eq!(vm.read_mem(0x3003), 0x48);
+        assert_eq!(vm.step(), Ok(true));
+        assert_eq!(vm.registers()[0], 0x3003);
+        assert_eq!(vm.step(), Ok(true));
+        assert_eq!(vm.take_output(), b"H");
+        assert_eq!(vm.step(), Ok(false));
+        assert_eq!(vm.take_output(), b"HALT\n");
+        assert!(vm.load_bytes(&[0x30]).is_err());
+    }
+}
@@ -0,0 +1,399 @@
+// Runs the binary the way users do: image paths and options on the command
+// line, program output on stdout.
+
+use std::process::{Command, Output, Stdio};
+
+fn fixture(name: &str) -> String {
+    format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rust_vm"))
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn runs_an_image_given_on_the_command_line() {
+    let output = run(&[&fixture("hello.obj")]);
+    assert!(output.status.success());
+    assert!(stdout(&output).starts_with("Hi"), "{:?}", stdout(&output));
+}
+
+#[test]
+fn later_images_overwrite_earlier_ones() {
+    // yo.obj only replaces hello's string, so keep starting at hello's code
+    let output = run(&["--pc", "x3000", &fixture("hello.obj"), &fixture("yo.obj")]);
+    assert!(output.status.success());
+    assert!(stdout(&output).starts_with("Yo"), "{:?}", stdout(&output));
+}
+
+#[test]
+fn missing_image_argument_prints_usage() {
+    let output = run(&[]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("usage:"));
+}
+
+#[test]
+fn unloadable_image_fails_with_a_message() {
+    let output = run(&["/nonexistent/program.obj"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("failed to load image"));
+}
+
+#[test]
+fn disasm_matches_the_golden_listing() {
+    let output = run(&["--disasm", "--data-as-hex", &fixture("hello.obj")]);
+    assert!(output.status.success());
+    let expected = std::fs::read_to_string(fixture("hello.disasm")).unwrap();
+    assert_eq!(stdout(&output), expected);
+}
+
+#[test]
+fn trace_prints_each_instruction_in_order() {
+    let output = run(&["--trace", "--endian", "big", &fixture("hello.obj")]);
+    assert!(output.status.success());
+    let trace = String::from_utf8_lossy(&output.stderr).into_owned();
+    let lines: Vec<&str> = trace.lines().collect();
+    assert_eq!(lines[0], "x3000  xE002  LEA R0, #2 ; -> x3003");
+    assert_eq!(lines[1], "    R0: x0000 -> x3003");
+    assert!(lines[2].starts_with("x3001  xF022  PUTS"), "{:?}", lines[2]);
+    assert!(lines[2].ends_with("R0=x3003"), "{:?}", lines[2]);
+}
+
+#[test]
+fn disasm_shows_labels_from_a_symbol_table() {
+    let output = run(&[
+        "--disasm",
+        "--sym",
+        &fixture("hello.sym"),
+        &fixture("hello.obj"),
+    ]);
+    assert!(output.status.success());
+    let listing = stdout(&output);
+    assert!(
+        listing.starts_with("x3000  xE002  MAIN     LEA R0, MSG\n"),
+        "{}",
+        listing
+    );
+}
+
+#[test]
+fn putsp_stops_at_a_zero_high_byte_and_keeps_r0() {
+    let output = run(&["--dump-on-halt", &fixture("putsp.obj")]);
+    assert!(output.status.success());
+    let out = stdout(&output);
+    assert!(out.starts_with("abcHALT\n"), "{:?}", out);
+    assert!(out.contains("R0 x3003"), "{:?}", out);
+}
+
+#[test]
+fn max_instructions_stops_an_infinite_loop() {
+    let output = run(&["--max-instructions", "1000", &fixture("spin.obj")]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("instruction limit of 1000 reached"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn illegal_opcode_reports_the_pc_and_word() {
+    let output = run(&[&fixture("reserved.obj")]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("error: illegal opcode xD000 at x3001"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("  x3001  xD000"), "{}", stderr);
+}
+
+#[test]
+fn load_at_relocates_the_image_and_starts_there() {
+    // hello.obj's LEA is PC-relative, so it still finds its string at x4003
+    let output = run(&[
+        "--load-at",
+        "x4000",
+        "--dump-on-halt",
+        &fixture("hello.obj"),
+    ]);
+    assert!(output.status.success());
+    let out = stdout(&output);
+    assert!(out.starts_with("Hi"), "{:?}", out);
+    assert!(out.contains("PC x4003"), "{:?}", out);
+}
+
+#[test]
+fn asm_assembles_and_runs_the_source() {
+    let output = run(&["--asm", &fixture("hello.asm")]);
+    assert!(output.status.success());
+    assert!(stdout(&output).starts_with("Hi"), "{:?}", stdout(&output));
+}
+
+#[test]
+fn coverage_lists_the_executed_ranges() {
+    let output = run(&["--coverage", &fixture("hello.obj")]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    assert!(
+        stderr.ends_with("executed:\n  x3000-x3002\n"),
+        "{:?}",
+        stderr
+    );
+}
+
+#[test]
+fn a_guessed_byte_order_is_always_reported() {
+    let output = run(&[&fixture("add.obj")]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("warning: image looks big-endian"),
+        "{}",
+        stderr
+    );
+
+    let output = run(&["--endian", "big", &fixture("add.obj")]);
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("warning"));
+}
+
+#[test]
+fn trace_shows_only_the_changed_registers() {
+    // ADD R3, R3, #2; ADD R3, R3, #5; HALT
+    let output = run(&["--trace", "--endian", "big", &fixture("add.obj")]);
+    assert!(output.status.success());
+    let trace = String::from_utf8_lossy(&output.stderr).into_owned();
+    let lines: Vec<&str> = trace.lines().collect();
+    assert!(
+        lines[2].starts_with("x3001  x16E5  ADD R3, R3, #5"),
+        "{:?}",
+        lines
+    );
+    assert_eq!(lines[3], "    R3: x0002 -> x0007");
+    assert!(lines[4].starts_with("x3002  xF025  HALT"), "{:?}", lines);
+    assert_eq!(lines.len(), 5);
+}
+
+#[test]
+fn getc_at_the_end_of_stdin_halts_cleanly() {
+    let output = run(&[&fixture("getc.obj")]);
+    assert!(output.status.success(), "{:?}", output);
+    let output = run(&["--on-eof", "error", &fixture("getc.obj")]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("input exhausted at x3000"));
+}
+
+#[test]
+fn exit_from_r0_uses_r0_as_the_exit_status() {
+    let output = run(&["--exit-from-r0", &fixture("exit7.obj")]);
+    assert_eq!(output.status.code(), Some(7));
+    let output = run(&[&fixture("exit7.obj")]);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn a_prompt_is_flushed_before_getc_waits() {
+    use std::io::Read;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    // PUTS ">" then GETC, with stdin left open so GETC blocks
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rust_vm"))
+        .args(["--no-flush", &fixture("prompt.obj")])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdout = child.stdout.take().unwrap();
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut byte = [0u8];
+        sender
+            .send(stdout.read_exact(&mut byte).map(|_| byte[0]).ok())
+            .unwrap();
+    });
+    let prompt = receiver.recv_timeout(Duration::from_secs(10));
+    // closing stdin lets GETC finish and the program halt
+    drop(child.stdin.take());
+    child.wait().unwrap();
+    assert_eq!(prompt, Ok(Some(b'>')));
+}
+
+#[test]
+fn no_color_keeps_escape_codes_out_of_the_dump() {
+    let output = run(&["--no-color", "--dump-on-halt", &fixture("hello.obj")]);
+    assert!(
+        stdout(&output).contains("R0 x3003"),
+        "{:?}",
+        stdout(&output)
+    );
+    assert!(!stdout(&output).contains('\x1b'));
+}
+
+#[test]
+fn asm_links_several_files() {
+    let dir = std::env::temp_dir().join(format!("rust_vm_link_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let main = dir.join("main.asm");
+    let lib = dir.join("lib.asm");
+    std::fs::write(
+        &main,
+        ".ORIG x3000\n.EXTERNAL PRINT\nJSR PRINT\nHALT\n.END\n",
+    )
+    .unwrap();
+    std::fs::write(
+        &lib,
+        ".ORIG x3100\n.GLOBAL PRINT\nPRINT ST R7, SAVE\nLEA R0, MSG\nPUTS\nLD R7, SAVE\nRET\n\
+         SAVE .BLKW #1\nMSG .STRINGZ \"linked\"\n.END\n",
+    )
+    .unwrap();
+    let output = run(&[
+        "--asm",
+        main.to_str().unwrap(),
+        "--asm",
+        lib.to_str().unwrap(),
+    ]);
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert!(
+        stdout(&output).starts_with("linked"),
+        "{:?}",
+        stdout(&output)
+    );
+}
+
+#[test]
+fn diff_prints_the_words_that_differ() {
+    let dir = std::env::temp_dir().join(format!("rust_vm_diff_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (a, b) = (dir.join("a.snapshot"), dir.join("b.snapshot"));
+    let mut vm = rust_vm::Vm::new();
+    std::fs::write(&a, vm.save_snapshot()).unwrap();
+    vm.memory[0x4000] = 0x002A;
+    std::fs::write(&b, vm.save_snapshot()).unwrap();
+
+    let (a, b) = (a.to_str().unwrap(), b.to_str().unwrap());
+    let different = run(&["--diff", a, b]);
+    let same = run(&["--diff", a, a]);
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(different.status.code(), Some(1));
+    assert_eq!(stdout(&different), "x4000: x0000 x002A\n");
+    assert_eq!(same.status.code(), Some(0));
+    assert_eq!(stdout(&same), "");
+}
+
+#[cfg(feature = "elf")]
+#[test]
+fn elf_images_run_from_their_entry_point() {
+    let output = run(&["--elf", &fixture("hello.elf")]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(stdout(&output).starts_with("Hi"), "{:?}", stdout(&output));
+}
+
+#[test]
+fn quiet_leaves_only_the_program_output_on_stdout() {
+    let output = run(&["--quiet", &fixture("hello.obj")]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "Hi");
+}
+
+#[test]
+fn fill_mem_preloads_a_data_table() {
+    // sum.obj adds the two words at x4000 into R0
+    let args = [
+        "--exit-from-r0",
+        "--fill-mem",
+        &fixture("table.hex"),
+        &fixture("sum.obj"),
+    ];
+    assert_eq!(run(&args).status.code(), Some(7));
+    let output = run(&["--fill-mem", "/nonexistent/table.hex", &fixture("sum.obj")]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn asm_dash_assembles_source_piped_on_stdin() {
+    use std::io::Write;
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rust_vm"))
+        .args(["--quiet", "--asm", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b".ORIG x3000\nLEA R0, MSG\nPUTS\nHALT\nMSG .STRINGZ \"piped\"\n.END\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "piped");
+}
+
+#[test]
+fn log_json_writes_one_line_per_instruction() {
+    let path = std::env::temp_dir().join(format!("rust_vm_log_{}.jsonl", std::process::id()));
+    // ADD R3, R3, #2; ADD R3, R3, #5; HALT
+    let output = run(&[
+        "--quiet",
+        "--log-json",
+        path.to_str().unwrap(),
+        &fixture("add.obj"),
+    ]);
+    let log = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let lines: Vec<serde_json::Value> = log
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 3, "{}", log);
+    assert_eq!(lines[0]["pc"], 0x3000);
+    assert_eq!(lines[0]["disasm"], "ADD R3, R3, #2");
+    assert_eq!(lines[1]["regs"], serde_json::json!([[3, 7]]));
+    assert_eq!(lines[2]["op"], "TRAP");
+}
+
+#[test]
+fn dump_obj_saves_memory_changed_in_the_debugger() {
+    use std::io::Write;
+
+    let path = std::env::temp_dir().join(format!("rust_vm_dump_{}.obj", std::process::id()));
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rust_vm"))
+        .args([
+            "--debug",
+            "--dump-obj",
+            path.to_str().unwrap(),
+            &fixture("hello.obj"),
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    // the string starts at x3003; turn "Hi" into "Yi"
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"set x3003 x0059\nquit\n")
+        .unwrap();
+    assert!(child.wait().unwrap().success());
+
+    let output = run(&["--quiet", path.to_str().unwrap()]);
+    std::fs::remove_file(&path).unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert!(stdout(&output).starts_with("Yi"), "{:?}", stdout(&output));
+}
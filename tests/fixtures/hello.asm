@@ -0,0 +1,7 @@
+; prints a greeting and halts
+        .ORIG x3000
+MAIN    LEA R0, MSG
+        PUTS
+        HALT
+MSG     .STRINGZ "Hi"
+        .END
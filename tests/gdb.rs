@@ -0,0 +1,118 @@
+// Drives the GDB stub over a real socket with a minimal remote-protocol
+// client.
+
+use std::io::{BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+use rust_vm::gdbstub;
+use rust_vm::Vm;
+
+struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Client {
+    fn byte(&mut self) -> u8 {
+        let mut byte = [0u8];
+        self.reader.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+
+    // send one packet and return the stub's reply
+    fn request(&mut self, data: &str) -> String {
+        let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        write!(self.writer, "${}#{:02x}", data, checksum).unwrap();
+        assert_eq!(self.byte(), b'+');
+        assert_eq!(self.byte(), b'$');
+        let mut reply = Vec::new();
+        loop {
+            match self.byte() {
+                b'#' => break,
+                b => reply.push(b),
+            }
+        }
+        let mut checksum = [0u8; 2];
+        self.reader.read_exact(&mut checksum).unwrap();
+        let expected = reply.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        assert_eq!(
+            u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16),
+            Ok(expected)
+        );
+        self.writer.write_all(b"+").unwrap();
+        String::from_utf8(reply).unwrap()
+    }
+}
+
+// serve `vm` on a local port while `session` talks to it from another
+// thread
+fn with_stub(vm: &mut Vm, session: impl FnOnce(&mut Client) + Send) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::scope(|scope| {
+        let client = scope.spawn(move || {
+            let stream = TcpStream::connect(addr).unwrap();
+            let mut client = Client {
+                reader: BufReader::new(stream.try_clone().unwrap()),
+                writer: stream,
+            };
+            session(&mut client);
+        });
+        let (stream, _) = listener.accept().unwrap();
+        gdbstub::serve_connection(vm, stream).unwrap();
+        client.join().unwrap();
+    });
+}
+
+fn program() -> Vm {
+    let mut vm = Vm::new();
+    // AND R0, R0, #0; ADD R0, R0, #7; ADD R0, R0, R0; HALT
+    vm.memory[0x3000..0x3004].copy_from_slice(&[0x5020, 0x1027, 0x1000, 0xF025]);
+    vm
+}
+
+#[test]
+fn reads_registers_sets_a_breakpoint_and_continues() {
+    let mut vm = program();
+    with_stub(&mut vm, |client| {
+        assert_eq!(client.request("?"), "S05");
+        let regs = client.request("g");
+        assert_eq!(regs.len(), 40);
+        assert_eq!(&regs[..4], "0000");
+        assert_eq!(&regs[32..36], "3000");
+
+        assert_eq!(client.request("Z0,3002,1"), "OK");
+        assert_eq!(client.request("c"), "S05");
+        let regs = client.request("g");
+        assert_eq!(&regs[..4], "0007");
+        assert_eq!(&regs[32..36], "3002");
+
+        assert_eq!(client.request("z0,3002,1"), "OK");
+        assert_eq!(client.request("c"), "W00");
+        assert_eq!(&client.request("g")[..4], "000e");
+    });
+    assert!(!vm.is_running());
+}
+
+#[test]
+fn reads_and_writes_memory_and_registers() {
+    let mut vm = program();
+    with_stub(&mut vm, |client| {
+        assert_eq!(client.request("m3000,2"), "50201027");
+        assert_eq!(client.request("M4000,2:beefcafe"), "OK");
+        assert_eq!(client.request("m4000,2"), "beefcafe");
+        assert_eq!(client.request("M4000,3:beef"), "E01");
+
+        let mut regs = client.request("g");
+        regs.replace_range(4..8, "1234");
+        assert_eq!(client.request(&format!("G{}", regs)), "OK");
+        assert_eq!(client.request("s"), "S05");
+        assert_eq!(&client.request("g")[4..8], "1234");
+        assert_eq!(client.request("G0000"), "E01");
+        assert_eq!(client.request("vMustReplyEmpty"), "");
+        assert_eq!(client.request("D"), "OK");
+    });
+    assert_eq!(vm.memory[0x4000..0x4002], [0xBEEF, 0xCAFE]);
+    assert_eq!(vm.reg[1], 0x1234);
+}
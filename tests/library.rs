@@ -0,0 +1,24 @@
+// Embeds the VM through the public library API, the way other crates do.
+
+use rust_vm::{LoadOptions, Register, StepResult, Vm};
+
+#[test]
+fn runs_a_program_and_reads_back_a_register() {
+    let mut vm = Vm::new();
+    // AND R0, R0, #0; ADD R0, R0, #7; ADD R0, R0, R0; HALT
+    vm.memory[0x3000..0x3004].copy_from_slice(&[0x5020, 0x1027, 0x1000, 0xF025]);
+    assert_eq!(vm.run(), Ok(StepResult::Halted));
+    assert_eq!(vm.reg[Register::RR0 as usize], 14);
+}
+
+#[test]
+fn loads_an_image_through_the_library() {
+    let path = format!("{}/tests/fixtures/hello.obj", env!("CARGO_MANIFEST_DIR"));
+    let mut vm = Vm::new();
+    let region = vm.read_image(&path, LoadOptions::default()).unwrap();
+    assert_eq!(region, 0x3000..0x3006);
+    assert_eq!(
+        rust_vm::disassemble(vm.memory[0x3000], 0x3000),
+        "LEA R0, #2 ; -> x3003"
+    );
+}